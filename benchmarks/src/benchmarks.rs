@@ -1,11 +1,15 @@
+use std::hint::black_box;
 use std::str::FromStr;
 
-#[cfg(not(feature = "ouroboros_compare"))]
-pub use crate::self_cell_cells::{Ast, I32Cell, StringCell};
+#[cfg(not(any(feature = "ouroboros_compare", feature = "hand_rolled_compare")))]
+pub use crate::self_cell_cells::{Ast, I32Cell, LargeCell, LargeOwner, StringCell};
 
 #[cfg(feature = "ouroboros_compare")]
 pub use crate::ouroboros_cells::{Ast, I32Cell, StringCell};
 
+#[cfg(feature = "hand_rolled_compare")]
+pub use crate::hand_rolled_cells::{Ast, I32Cell, StringCell};
+
 pub fn i32_cell_new(x: i32) -> I32Cell {
     I32Cell::new(x, |o| o)
 }
@@ -17,10 +21,7 @@ pub fn i32_cell_try_new_ok(x: i32) -> Result<I32Cell, Box<u32>> {
 pub fn i32_list(n: i32) -> i32 {
     let mut side_effect = 0;
 
-    let cells = (0..n)
-        .into_iter()
-        .map(|x| I32Cell::new(x, |x| x))
-        .collect::<Vec<_>>();
+    let cells = (0..n).map(|x| I32Cell::new(x, |x| x)).collect::<Vec<_>>();
 
     for cell in cells {
         side_effect += if **cell.borrow_dependent() % 66 == 0 {
@@ -33,15 +34,26 @@ pub fn i32_list(n: i32) -> i32 {
     side_effect
 }
 
+// Only borrows, the cell is created once. black_box keeps the compiler from
+// hoisting the borrow out of the loop, so this measures the cost of a single
+// borrow_dependent.
+pub fn i32_borrow(n: i32) -> i32 {
+    let cell = I32Cell::new(n, |x| x);
+    let mut side_effect = 0;
+
+    for _ in 0..n {
+        side_effect += **black_box(&cell).borrow_dependent() % 7;
+    }
+
+    side_effect
+}
+
 // The list functions mostly test L1 access
 // Let's also test pseudo random access.
 pub fn i32_random(n: i32) -> i32 {
     let mut side_effect = 0;
 
-    let cells = (0..n)
-        .into_iter()
-        .map(|x| I32Cell::new(x, |x| x))
-        .collect::<Vec<_>>();
+    let cells = (0..n).map(|x| I32Cell::new(x, |x| x)).collect::<Vec<_>>();
 
     while side_effect < n * 2 {
         side_effect += **cells[(side_effect as usize) % (cells.len() - 66)].borrow_dependent() + 1;
@@ -56,7 +68,6 @@ pub fn i32_sparse(n: i32) -> i32 {
     let mut side_effect = 0;
 
     let cells = (0..n)
-        .into_iter()
         .map(|x| {
             if x % 8 == 0 {
                 Some(I32Cell::new(x, |x| x))
@@ -77,7 +88,7 @@ pub fn i32_sparse(n: i32) -> i32 {
     side_effect
 }
 
-pub fn ast_from_string(body: &str) -> Ast {
+pub fn ast_from_string(body: &str) -> Ast<'_> {
     body.split("+").filter(|x| x.len() > 1).collect()
 }
 
@@ -93,7 +104,6 @@ pub fn string_list(n: i32) -> i32 {
     let mut side_effect = 0;
 
     let cells = (0..n)
-        .into_iter()
         .map(|x| StringCell::new(x.to_string(), |o| ast_from_string(o)))
         .collect::<Vec<_>>();
 
@@ -114,13 +124,24 @@ pub fn string_list(n: i32) -> i32 {
     side_effect
 }
 
+pub fn string_borrow(n: i32) -> i32 {
+    let cell = StringCell::new("1+22+333".into(), |o| ast_from_string(o));
+    let mut side_effect = 0;
+
+    for _ in 0..n {
+        let cell = black_box(&cell);
+        side_effect += cell.borrow_dependent().len() as i32 + cell.borrow_owner().len() as i32;
+    }
+
+    side_effect
+}
+
 // string 1m is too long
 
 pub fn string_random(n: i32) -> i32 {
     let mut side_effect = 0;
 
     let cells = (0..n)
-        .into_iter()
         .map(|x| StringCell::new(x.to_string(), |o| ast_from_string(o)))
         .collect::<Vec<_>>();
 
@@ -140,7 +161,6 @@ pub fn string_sparse(n: i32) -> i32 {
     let mut side_effect = 0;
 
     let cells = (0..n)
-        .into_iter()
         .map(|x| {
             if x % 8 == 0 {
                 Some(StringCell::new(x.to_string(), |o| ast_from_string(o)))
@@ -160,3 +180,21 @@ pub fn string_sparse(n: i32) -> i32 {
 
     side_effect
 }
+
+#[cfg(not(any(feature = "ouroboros_compare", feature = "hand_rolled_compare")))]
+pub fn large_owner_boxed() -> Box<LargeOwner> {
+    use std::convert::TryInto;
+
+    vec![1; 64 * 1024].into_boxed_slice().try_into().unwrap()
+}
+
+// Moves the boxed owner out of its allocation and into the one of the cell.
+#[cfg(not(any(feature = "ouroboros_compare", feature = "hand_rolled_compare")))]
+pub fn large_cell_new(owner: Box<LargeOwner>) -> LargeCell {
+    LargeCell::new(*owner, |o| &o[1..])
+}
+
+#[cfg(not(any(feature = "ouroboros_compare", feature = "hand_rolled_compare")))]
+pub fn large_cell_new_from_box(owner: Box<LargeOwner>) -> LargeCell {
+    LargeCell::new_from_box(owner, |o| &o[1..])
+}
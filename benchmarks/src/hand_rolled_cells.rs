@@ -0,0 +1,110 @@
+// What self_cell would be replacing, an unsafe self-referential struct written
+// by hand. Owner is boxed and only accessed through a raw pointer, so moving
+// the struct doesn't invalidate the references held by dependent.
+
+use std::ptr::NonNull;
+
+pub type I32Ref<'a> = &'a i32;
+
+pub struct I32Cell {
+    // Dropped before owner, in Drop.
+    dependent: I32Ref<'static>,
+    owner: NonNull<i32>,
+}
+
+impl I32Cell {
+    pub fn new(owner: i32, dependent_builder: impl for<'a> FnOnce(&'a i32) -> I32Ref<'a>) -> Self {
+        let owner = NonNull::from(Box::leak(Box::new(owner)));
+
+        Self {
+            dependent: dependent_builder(unsafe { &*owner.as_ptr() }),
+            owner,
+        }
+    }
+
+    pub fn try_new<E>(
+        owner: i32,
+        dependent_builder: impl for<'a> FnOnce(&'a i32) -> Result<I32Ref<'a>, E>,
+    ) -> Result<Self, E> {
+        let owner = NonNull::from(Box::leak(Box::new(owner)));
+
+        match dependent_builder(unsafe { &*owner.as_ptr() }) {
+            Ok(dependent) => Ok(Self { dependent, owner }),
+            Err(err) => {
+                drop(unsafe { Box::from_raw(owner.as_ptr()) });
+                Err(err)
+            }
+        }
+    }
+
+    pub fn borrow_owner(&self) -> &i32 {
+        unsafe { &*self.owner.as_ptr() }
+    }
+
+    pub fn borrow_dependent<'a>(&'a self) -> &'a I32Ref<'a> {
+        &self.dependent
+    }
+}
+
+impl Drop for I32Cell {
+    fn drop(&mut self) {
+        drop(unsafe { Box::from_raw(self.owner.as_ptr()) });
+    }
+}
+
+pub type Ast<'a> = Vec<&'a str>;
+
+pub struct StringCell {
+    // ManuallyDrop, so it can be dropped before owner.
+    dependent: std::mem::ManuallyDrop<Ast<'static>>,
+    owner: NonNull<String>,
+}
+
+impl StringCell {
+    pub fn new(
+        owner: String,
+        dependent_builder: impl for<'a> FnOnce(&'a String) -> Ast<'a>,
+    ) -> Self {
+        let owner = NonNull::from(Box::leak(Box::new(owner)));
+
+        Self {
+            dependent: std::mem::ManuallyDrop::new(dependent_builder(unsafe { &*owner.as_ptr() })),
+            owner,
+        }
+    }
+
+    pub fn try_new<E>(
+        owner: String,
+        dependent_builder: impl for<'a> FnOnce(&'a String) -> Result<Ast<'a>, E>,
+    ) -> Result<Self, E> {
+        let owner = NonNull::from(Box::leak(Box::new(owner)));
+
+        match dependent_builder(unsafe { &*owner.as_ptr() }) {
+            Ok(dependent) => Ok(Self {
+                dependent: std::mem::ManuallyDrop::new(dependent),
+                owner,
+            }),
+            Err(err) => {
+                drop(unsafe { Box::from_raw(owner.as_ptr()) });
+                Err(err)
+            }
+        }
+    }
+
+    pub fn borrow_owner(&self) -> &String {
+        unsafe { &*self.owner.as_ptr() }
+    }
+
+    pub fn borrow_dependent<'a>(&'a self) -> &'a Ast<'a> {
+        &self.dependent
+    }
+}
+
+impl Drop for StringCell {
+    fn drop(&mut self) {
+        unsafe {
+            std::mem::ManuallyDrop::drop(&mut self.dependent);
+            drop(Box::from_raw(self.owner.as_ptr()));
+        }
+    }
+}
@@ -10,6 +10,10 @@ fn i32_cell_try_new_ok() -> Result<I32Cell, Box<u32>> {
     benchmarks::benchmarks::i32_cell_try_new_ok(black_box(66))
 }
 
+fn i32_borrow_1k() -> i32 {
+    i32_borrow(black_box(1_000))
+}
+
 fn i32_list_1k() -> i32 {
     i32_list(black_box(1_000))
 }
@@ -54,6 +58,10 @@ fn string_cell_try_new_ok() -> Result<StringCell, Box<u32>> {
     benchmarks::benchmarks::string_cell_try_new_ok(black_box("short".into()))
 }
 
+fn string_borrow_1k() -> i32 {
+    string_borrow(black_box(1_000))
+}
+
 fn string_list_1k() -> i32 {
     string_list(black_box(1_000))
 }
@@ -81,6 +89,7 @@ fn string_sparse_100k() -> i32 {
 iai::main!(
     i32_cell_new,
     i32_cell_try_new_ok,
+    i32_borrow_1k,
     i32_list_1k,
     i32_list_100k,
     i32_list_1m,
@@ -92,6 +101,7 @@ iai::main!(
     i32_sparse_1m,
     string_cell_new,
     string_cell_try_new_ok,
+    string_borrow_1k,
     string_list_1k,
     string_list_100k,
     string_random_1k,
@@ -1,7 +1,17 @@
+// The ouroboros builders already get the owner as &str, the closures deref
+// &String for the other implementations.
+#![cfg_attr(feature = "ouroboros_compare", allow(clippy::redundant_closure))]
+
 pub mod benchmarks;
 
-#[cfg(not(feature = "ouroboros_compare"))]
+#[cfg(all(feature = "ouroboros_compare", feature = "hand_rolled_compare"))]
+compile_error!("Enable only one of ouroboros_compare and hand_rolled_compare.");
+
+#[cfg(not(any(feature = "ouroboros_compare", feature = "hand_rolled_compare")))]
 pub mod self_cell_cells;
 
 #[cfg(feature = "ouroboros_compare")]
 pub mod ouroboros_cells;
+
+#[cfg(feature = "hand_rolled_compare")]
+pub mod hand_rolled_cells;
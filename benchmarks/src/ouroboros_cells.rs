@@ -41,7 +41,7 @@ impl I32Cell {
         self.0.borrow_owner()
     }
 
-    pub fn borrow_dependent(&self) -> &I32Ref {
+    pub fn borrow_dependent(&self) -> &I32Ref<'_> {
         self.0.borrow_dependent()
     }
 }
@@ -87,7 +87,7 @@ impl StringCell {
         self.0.borrow_owner()
     }
 
-    pub fn borrow_dependent(&self) -> &Ast {
+    pub fn borrow_dependent(&self) -> &Ast<'_> {
         self.0.borrow_dependent()
     }
 }
@@ -21,3 +21,17 @@ self_cell!(
         dependent: Ast,
     }
 );
+
+// Large enough that moving it dominates creating the cell.
+pub type LargeOwner = [u64; 64 * 1024];
+
+pub type LargeRef<'a> = &'a [u64];
+
+self_cell!(
+    pub struct LargeCell {
+        owner: LargeOwner,
+
+        #[covariant]
+        dependent: LargeRef,
+    }
+);
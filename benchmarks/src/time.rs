@@ -7,6 +7,7 @@ pub fn i32_benchmarks(c: &mut Criterion) {
     c.bench_function("i32_cell_try_new_ok", |b| {
         b.iter(|| i32_cell_try_new_ok(black_box(66)))
     });
+    c.bench_function("i32_borrow_1k", |b| b.iter(|| i32_borrow(black_box(1_000))));
     c.bench_function("i32_list_1k", |b| b.iter(|| i32_list(black_box(1_000))));
     c.bench_function("i32_list_100k", |b| b.iter(|| i32_list(black_box(100_000))));
     c.bench_function("i32_list_1m", |b| b.iter(|| i32_list(black_box(1_000_000))));
@@ -35,6 +36,9 @@ pub fn string_benchmarks(c: &mut Criterion) {
     c.bench_function("string_cell_try_new_ok", |b| {
         b.iter(|| string_cell_try_new_ok(black_box("short".into())))
     });
+    c.bench_function("string_borrow_1k", |b| {
+        b.iter(|| string_borrow(black_box(1_000)))
+    });
     c.bench_function("string_list_1k", |b| {
         b.iter(|| string_list(black_box(1_000)))
     });
@@ -57,4 +61,28 @@ pub fn string_benchmarks(c: &mut Criterion) {
 
 criterion_group!(string_benches, string_benchmarks);
 
+// Boxed owners are a self_cell only API.
+#[cfg(not(any(feature = "ouroboros_compare", feature = "hand_rolled_compare")))]
+pub fn large_owner_benchmarks(c: &mut Criterion) {
+    use criterion::BatchSize;
+
+    c.bench_function("large_cell_new", |b| {
+        b.iter_batched(large_owner_boxed, large_cell_new, BatchSize::LargeInput)
+    });
+    c.bench_function("large_cell_new_from_box", |b| {
+        b.iter_batched(
+            large_owner_boxed,
+            large_cell_new_from_box,
+            BatchSize::LargeInput,
+        )
+    });
+}
+
+#[cfg(not(any(feature = "ouroboros_compare", feature = "hand_rolled_compare")))]
+criterion_group!(large_owner_benches, large_owner_benchmarks);
+
+#[cfg(not(any(feature = "ouroboros_compare", feature = "hand_rolled_compare")))]
+criterion_main!(i32_benches, string_benches, large_owner_benches);
+
+#[cfg(any(feature = "ouroboros_compare", feature = "hand_rolled_compare"))]
 criterion_main!(i32_benches, string_benches);
@@ -0,0 +1,103 @@
+// Counts the heap allocations of constructing and borrowing the benchmark
+// cells. Run it with the same features as the benchmarks, to compare self_cell
+// against the other implementations:
+//
+// cargo test --test allocations
+// cargo test --test allocations --features ouroboros_compare
+// cargo test --test allocations --features hand_rolled_compare
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+use benchmarks::benchmarks::*;
+
+struct CountingAllocator;
+
+thread_local! {
+    // Per thread, so that tests running in parallel don't see each others
+    // allocations.
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|allocations| allocations.set(allocations.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+fn count_allocations<T>(func: impl FnOnce() -> T) -> (T, usize) {
+    let before = ALLOCATIONS.with(Cell::get);
+    let ret = func();
+    (ret, ALLOCATIONS.with(Cell::get) - before)
+}
+
+// The owner is created before counting, only the cell itself and its
+// dependent are counted. self_cell and the hand rolled cells need one
+// allocation for the owner, so it doesn't move while the dependent borrows it.
+// The Vec of the Ast is the second allocation of the StringCell.
+const I32_CELL_NEW_ALLOCATIONS: usize = 1;
+
+#[cfg(not(feature = "ouroboros_compare"))]
+const STRING_CELL_NEW_ALLOCATIONS: usize = 2;
+
+// ouroboros keeps the String owner inline, the dependent borrows its heap
+// buffer, which doesn't move with the struct. Only the Vec is allocated.
+#[cfg(feature = "ouroboros_compare")]
+const STRING_CELL_NEW_ALLOCATIONS: usize = 1;
+
+#[test]
+fn i32_cell_new_allocations() {
+    let (cell, allocations) = count_allocations(|| i32_cell_new(66));
+    assert_eq!(allocations, I32_CELL_NEW_ALLOCATIONS);
+
+    let (_, allocations) = count_allocations(|| **cell.borrow_dependent() + *cell.borrow_owner());
+    assert_eq!(allocations, 0);
+}
+
+#[test]
+fn i32_cell_try_new_ok_allocations() {
+    let (cell, allocations) = count_allocations(|| i32_cell_try_new_ok(66));
+    assert_eq!(allocations, I32_CELL_NEW_ALLOCATIONS);
+    assert_eq!(**cell.unwrap().borrow_dependent(), 66);
+}
+
+#[test]
+fn string_cell_new_allocations() {
+    let owner = String::from("1+22+333");
+
+    let (cell, allocations) = count_allocations(|| string_cell_new(owner));
+    assert_eq!(allocations, STRING_CELL_NEW_ALLOCATIONS);
+
+    let (_, allocations) =
+        count_allocations(|| cell.borrow_dependent().len() + cell.borrow_owner().len());
+    assert_eq!(allocations, 0);
+}
+
+#[test]
+fn string_cell_try_new_ok_allocations() {
+    let owner = String::from("1+22+333");
+
+    let (cell, allocations) = count_allocations(|| string_cell_try_new_ok(owner));
+    assert_eq!(allocations, STRING_CELL_NEW_ALLOCATIONS);
+    assert_eq!(cell.unwrap().borrow_dependent().len(), 2);
+}
+
+#[test]
+fn borrow_allocations() {
+    // The cell is created once inside, the borrows in the loop must not
+    // allocate.
+    let (_, allocations) = count_allocations(|| i32_borrow(1_000));
+    assert_eq!(allocations, I32_CELL_NEW_ALLOCATIONS);
+
+    // Plus the one for the String owner.
+    let (_, allocations) = count_allocations(|| string_borrow(1_000));
+    assert_eq!(allocations, STRING_CELL_NEW_ALLOCATIONS + 1);
+}
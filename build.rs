@@ -0,0 +1,7 @@
+// Declares the cfgs only set through RUSTFLAGS, so rustc doesn't warn about
+// them being unknown. The single colon form is ignored by Cargo versions that
+// don't know rustc-check-cfg yet.
+fn main() {
+    println!("cargo:rustc-check-cfg=cfg(loom)");
+    println!("cargo:rustc-check-cfg=cfg(self_cell_strict_provenance_lints)");
+}
@@ -0,0 +1,80 @@
+use std::collections::HashMap;
+
+use self_cell::self_cell;
+
+// Stands in for a memory mapped file, the outermost owner.
+#[derive(Debug)]
+struct Mapping(Vec<u8>);
+
+#[derive(Debug)]
+struct FileView<'a> {
+    lines: Vec<&'a str>,
+}
+
+self_cell!(
+    struct FileCell {
+        owner: Mapping,
+
+        #[covariant]
+        dependent: FileView,
+    }
+
+    impl {Debug}
+);
+
+type Index<'a> = HashMap<&'a str, usize>;
+
+// The owner is the first cell. Its JoinedCell lives on the heap and is never
+// moved or mutated while IndexCell exists, so the references the index takes
+// out of the file view stay valid, the same as with any other owner.
+self_cell!(
+    struct IndexCell {
+        owner: FileCell,
+
+        #[covariant]
+        dependent: Index,
+    }
+);
+
+fn open(bytes: Vec<u8>) -> FileCell {
+    FileCell::new(Mapping(bytes), |mapping| {
+        let text = std::str::from_utf8(&mapping.0).unwrap_or("");
+        FileView {
+            lines: text.lines().collect(),
+        }
+    })
+}
+
+fn index(file: FileCell) -> IndexCell {
+    IndexCell::new(file, |file| {
+        file.borrow_dependent()
+            .lines
+            .iter()
+            .enumerate()
+            .map(|(line_nr, line)| (*line, line_nr))
+            .collect()
+    })
+}
+
+fn assert_send_sync<T: Send + Sync>(_: &T) {}
+
+fn main() {
+    let index_cell = index(open(b"fox\ncat\ndog".to_vec()));
+
+    // Both cells only hold Send and Sync types, so the nested cell is too.
+    assert_send_sync(&index_cell);
+
+    println!(
+        "index_cell.borrow_dependent()[\"cat\"] -> {}",
+        index_cell.borrow_dependent()["cat"]
+    );
+    println!(
+        "index_cell.borrow_owner().borrow_dependent() -> {:?}",
+        index_cell.borrow_owner().borrow_dependent()
+    );
+
+    // Every layer gives back its owner, the index is dropped first.
+    let file_cell = index_cell.into_owner();
+    let mapping = file_cell.into_owner();
+    println!("mapping -> {:?}", mapping);
+}
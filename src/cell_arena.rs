@@ -0,0 +1,256 @@
+use core::alloc::Layout;
+use core::mem::ManuallyDrop;
+use core::ops::Deref;
+use core::ptr::{addr_of, NonNull};
+
+use alloc::alloc::{alloc, dealloc, handle_alloc_error};
+use alloc::vec::Vec;
+
+use crate::unsafe_self_cell::{JoinedCell, MapTarget, OwnerAndCellDropGuard};
+use crate::DependentOf;
+
+type Joined<Cell> =
+    JoinedCell<<Cell as MapTarget>::Owner, <Cell as DependentOf<'static>>::Dependent>;
+
+/// Many cells sharing a single allocation.
+///
+/// Returned by `new_arena` of [`self_cell`](crate::self_cell), which builds
+/// all cells with two allocations, one block for all owners and dependents and
+/// one for the cells pointing into it, instead of one allocation per cell. The
+/// cells are borrowed through [`Deref`] to `[Cell]`.
+///
+/// A cell normally frees its own memory when dropped, which isn't possible for
+/// a part of a shared block. So the cells can only be borrowed, not moved out,
+/// and are all dropped together with the arena. A single cell can't outlive
+/// the arena, cells that should be moved out or dropped on their own need to
+/// be built with `new`, one allocation each.
+///
+/// Only available for heap allocated cells without dependent mode.
+///
+/// ```
+/// use self_cell::self_cell;
+///
+/// type Words<'a> = Vec<&'a str>;
+///
+/// self_cell!(
+///     struct WordsCell {
+///         owner: String,
+///
+///         #[covariant]
+///         dependent: Words,
+///     }
+/// );
+///
+/// let lines = vec!["fox cat".to_string(), "dog".to_string()];
+/// let cells = WordsCell::new_arena(lines, |line| line.split(' ').collect());
+///
+/// assert_eq!(cells.len(), 2);
+/// assert_eq!(cells[0].borrow_dependent(), &["fox", "cat"]);
+/// assert_eq!(cells[1].borrow_owner(), "dog");
+/// ```
+pub struct CellArena<Cell>
+where
+    Cell: for<'a> DependentOf<'a> + MapTarget,
+{
+    // Point into block and are never dropped themselves, Drop drops their
+    // owners and dependents in place.
+    cells: Vec<ManuallyDrop<Cell>>,
+
+    block: NonNull<u8>,
+    block_layout: Layout,
+}
+
+impl<Cell> CellArena<Cell>
+where
+    Cell: for<'a> DependentOf<'a> + MapTarget,
+{
+    /// Moves all owners into one allocation and builds a dependent from each,
+    /// the same as `new_arena` of the cell.
+    pub fn new(
+        owners: Vec<<Cell as MapTarget>::Owner>,
+        mut dependent_builder: impl for<'a> FnMut(
+            &'a <Cell as MapTarget>::Owner,
+        ) -> <Cell as DependentOf<'a>>::Dependent,
+    ) -> Self {
+        let block_layout = match Layout::array::<Joined<Cell>>(owners.len()) {
+            Ok(layout) => layout,
+            Err(_) => panic!("capacity overflow"),
+        };
+
+        let block = if block_layout.size() == 0 {
+            NonNull::<Joined<Cell>>::dangling().cast()
+        } else {
+            match NonNull::new(unsafe { alloc(block_layout) }) {
+                Some(block) => block,
+                None => handle_alloc_error(block_layout),
+            }
+        };
+
+        // Should a builder panic, Drop cleans up the cells built so far and
+        // frees the block.
+        let mut arena = Self {
+            cells: Vec::with_capacity(owners.len()),
+            block,
+            block_layout,
+        };
+
+        for (index, owner) in owners.into_iter().enumerate() {
+            unsafe {
+                // See the fn new generated by self_cell for why the builder
+                // can't smuggle references in or out. FnMut is fine as well,
+                // its state can't name 'a and so keep nothing borrowed from
+                // one owner for the next.
+                let joined_ptr = block.cast::<Joined<Cell>>().as_ptr().add(index);
+                let drop_guard =
+                    OwnerAndCellDropGuard::<_, <Cell as DependentOf<'_>>::Dependent>::in_storage(
+                        NonNull::new_unchecked(joined_ptr).cast(),
+                        owner,
+                    );
+
+                let dependent = dependent_builder(&*drop_guard.owner_ptr());
+
+                arena
+                    .cells
+                    .push(ManuallyDrop::new(Cell::from_joined_void_ptr(
+                        drop_guard.init_dependent(dependent),
+                    )));
+            }
+        }
+
+        arena
+    }
+
+    pub fn as_slice(&self) -> &[Cell] {
+        // ManuallyDrop is repr(transparent).
+        unsafe { &*(self.cells.as_slice() as *const [ManuallyDrop<Cell>] as *const [Cell]) }
+    }
+
+    /// Calls func with owner and dependent of every cell, in order.
+    ///
+    /// Like `with_dependent` of the cells, so it works for `not_covariant`
+    /// dependents too.
+    pub fn for_each_dependent(
+        &self,
+        mut func: impl for<'a> FnMut(
+            &'a <Cell as MapTarget>::Owner,
+            &'a <Cell as DependentOf<'a>>::Dependent,
+        ),
+    ) {
+        for index in 0..self.cells.len() {
+            self.with_joined(index, &mut func);
+        }
+    }
+
+    /// Like [`CellArena::for_each_dependent`], but stops at and returns the
+    /// first error of func.
+    pub fn try_for_each_dependent<Err>(
+        &self,
+        mut func: impl for<'a> FnMut(
+            &'a <Cell as MapTarget>::Owner,
+            &'a <Cell as DependentOf<'a>>::Dependent,
+        ) -> Result<(), Err>,
+    ) -> Result<(), Err> {
+        for index in 0..self.cells.len() {
+            self.with_joined(index, &mut func)?;
+        }
+
+        Ok(())
+    }
+
+    /// Maps owner and dependent of every cell with func in parallel, with the
+    /// `rayon` feature.
+    ///
+    /// The results can't borrow from the cells, so func works for
+    /// `not_covariant` dependents too.
+    #[cfg(feature = "rayon")]
+    pub fn par_iter_dependents<'c, Ret: Send>(
+        &'c self,
+        func: impl for<'a> Fn(
+                &'a <Cell as MapTarget>::Owner,
+                &'a <Cell as DependentOf<'a>>::Dependent,
+            ) -> Ret
+            + Send
+            + Sync
+            + 'c,
+    ) -> impl rayon::iter::IndexedParallelIterator<Item = Ret> + 'c
+    where
+        Cell: Sync,
+    {
+        use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+        (0..self.cells.len())
+            .into_par_iter()
+            .map(move |index| self.with_joined(index, &func))
+    }
+
+    fn with_joined<Ret>(
+        &self,
+        index: usize,
+        func: impl for<'a> FnOnce(
+            &'a <Cell as MapTarget>::Owner,
+            &'a <Cell as DependentOf<'a>>::Dependent,
+        ) -> Ret,
+    ) -> Ret {
+        assert!(index < self.cells.len());
+
+        unsafe {
+            // The cells only hand out shared references to their owners and
+            // dependents, the same as this. The dependent has the same
+            // layout for every lifetime.
+            let joined_ptr = self.block.cast::<Joined<Cell>>().as_ptr().add(index);
+            let owner = &*addr_of!((*joined_ptr).owner);
+            let dependent =
+                &*addr_of!((*joined_ptr).dependent).cast::<<Cell as DependentOf<'_>>::Dependent>();
+
+            func(owner, dependent)
+        }
+    }
+}
+
+impl<Cell> Deref for CellArena<Cell>
+where
+    Cell: for<'a> DependentOf<'a> + MapTarget,
+{
+    type Target = [Cell];
+
+    fn deref(&self) -> &[Cell] {
+        self.as_slice()
+    }
+}
+
+impl<'c, Cell> IntoIterator for &'c CellArena<Cell>
+where
+    Cell: for<'a> DependentOf<'a> + MapTarget,
+{
+    type Item = &'c Cell;
+    type IntoIter = core::slice::Iter<'c, Cell>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.as_slice().iter()
+    }
+}
+
+impl<Cell> Drop for CellArena<Cell>
+where
+    Cell: for<'a> DependentOf<'a> + MapTarget,
+{
+    fn drop(&mut self) {
+        unsafe {
+            // Should dropping a dependent panic, the remaining cells and the
+            // block are leaked.
+            for cell in &mut self.cells {
+                cell.drop_joined_in_place();
+            }
+
+            if self.block_layout.size() != 0 {
+                dealloc(self.block.as_ptr(), self.block_layout);
+            }
+        }
+    }
+}
+
+// The arena owns the block like a Box, the cells in it are only ever borrowed
+// through the arena.
+unsafe impl<Cell> Send for CellArena<Cell> where Cell: for<'a> DependentOf<'a> + MapTarget + Send {}
+
+unsafe impl<Cell> Sync for CellArena<Cell> where Cell: for<'a> DependentOf<'a> + MapTarget + Sync {}
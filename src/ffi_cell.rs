@@ -0,0 +1,214 @@
+use core::ffi::c_void;
+use core::marker::PhantomData;
+use core::ptr::{addr_of, NonNull};
+
+use crate::unsafe_self_cell::{JoinedCell, MapTarget};
+use crate::DependentOf;
+
+type Joined<Cell> =
+    JoinedCell<<Cell as MapTarget>::Owner, <Cell as DependentOf<'static>>::Dependent>;
+
+/// Handle to a cell with a stable layout, for passing cells between libraries
+/// built separately, possibly with different Rust versions.
+///
+/// The layout of a cell declared with [`self_cell`](crate::self_cell) is
+/// unspecified, as is the way it frees its memory and drops owner and
+/// dependent. `FfiCell` is a `#[repr(C)]` pair of the pointer to the owner and
+/// dependent on the heap and a pointer to a `#[repr(C)]` table of
+/// `extern "C"` functions, [`FfiCellVTable`]. The functions are compiled
+/// together with the library that created the cell, so the other side never
+/// relies on the layout the compiler picked there. Dropping the handle drops
+/// the cell with the code and allocator of the library that created it.
+///
+/// Finding owner and dependent only goes through the table, but reading them
+/// still needs both sides to agree on the layout of `Owner` and the
+/// dependent, eg. because they are `#[repr(C)]` made of FFI-safe fields.
+/// That is why [`FfiCell::borrow_owner`] and [`FfiCell::with_dependent`] are
+/// unsafe. Should the drop of owner, dependent or a finalizer panic, the
+/// process aborts, unwinding can't cross the `extern "C"` functions.
+///
+/// Only available with the `ffi` feature, for heap allocated cells without
+/// dependent mode.
+///
+/// ```
+/// use self_cell::{self_cell, FfiCell};
+///
+/// #[repr(C)]
+/// pub struct Config {
+///     pub retries: u32,
+///     pub timeout_ms: u32,
+/// }
+///
+/// #[repr(C)]
+/// pub struct Limits<'a> {
+///     pub retries: &'a u32,
+/// }
+///
+/// self_cell!(
+///     pub struct ConfigCell {
+///         owner: Config,
+///
+///         #[covariant]
+///         dependent: Limits,
+///     }
+/// );
+///
+/// // Exported by the plugin.
+/// pub extern "C" fn load_config() -> FfiCell<ConfigCell> {
+///     let config = Config { retries: 3, timeout_ms: 500 };
+///     FfiCell::new(ConfigCell::new(config, |config| Limits { retries: &config.retries }))
+/// }
+///
+/// // Called by the host.
+/// let cell = load_config();
+/// let retries = unsafe { cell.with_dependent(|_, limits| *limits.retries) };
+/// assert_eq!(retries, 3);
+/// assert_eq!(unsafe { cell.borrow_owner().timeout_ms }, 500);
+/// ```
+#[repr(C)]
+pub struct FfiCell<Cell>
+where
+    Cell: for<'a> DependentOf<'a> + MapTarget,
+{
+    joined_void_ptr: NonNull<c_void>,
+    vtable: &'static FfiCellVTable,
+
+    cell_marker: PhantomData<Cell>,
+}
+
+/// The `extern "C"` functions of an [`FfiCell`], from the library that
+/// created the cell.
+#[repr(C)]
+pub struct FfiCellVTable {
+    drop: unsafe extern "C" fn(NonNull<c_void>),
+    owner_ptr: unsafe extern "C" fn(NonNull<c_void>) -> *const c_void,
+    dependent_ptr: unsafe extern "C" fn(NonNull<c_void>) -> *const c_void,
+}
+
+struct VTableOf<Cell>(PhantomData<Cell>);
+
+impl<Cell> VTableOf<Cell>
+where
+    Cell: for<'a> DependentOf<'a> + MapTarget,
+{
+    const VTABLE: FfiCellVTable = FfiCellVTable {
+        drop: drop_shim::<Cell>,
+        owner_ptr: owner_ptr_shim::<Cell>,
+        dependent_ptr: dependent_ptr_shim::<Cell>,
+    };
+}
+
+// Aborts instead of unwinding should dropping panic.
+unsafe extern "C" fn drop_shim<Cell>(joined_void_ptr: NonNull<c_void>)
+where
+    Cell: for<'a> DependentOf<'a> + MapTarget,
+{
+    drop(Cell::from_joined_void_ptr(joined_void_ptr.cast()));
+}
+
+unsafe extern "C" fn owner_ptr_shim<Cell>(joined_void_ptr: NonNull<c_void>) -> *const c_void
+where
+    Cell: for<'a> DependentOf<'a> + MapTarget,
+{
+    let joined_ptr = joined_void_ptr.cast::<Joined<Cell>>().as_ptr();
+    addr_of!((*joined_ptr).owner).cast()
+}
+
+unsafe extern "C" fn dependent_ptr_shim<Cell>(joined_void_ptr: NonNull<c_void>) -> *const c_void
+where
+    Cell: for<'a> DependentOf<'a> + MapTarget,
+{
+    let joined_ptr = joined_void_ptr.cast::<Joined<Cell>>().as_ptr();
+    addr_of!((*joined_ptr).dependent).cast()
+}
+
+impl<Cell> FfiCell<Cell>
+where
+    Cell: for<'a> DependentOf<'a> + MapTarget,
+{
+    /// Turns the cell into a handle, without moving owner or dependent.
+    pub fn new(cell: Cell) -> Self {
+        Self {
+            joined_void_ptr: cell.into_joined_void_ptr().cast(),
+            vtable: &VTableOf::<Cell>::VTABLE,
+            cell_marker: PhantomData,
+        }
+    }
+
+    /// Points to owner, found with the code of the library that created the
+    /// cell.
+    pub fn owner_ptr(&self) -> *const c_void {
+        unsafe { (self.vtable.owner_ptr)(self.joined_void_ptr) }
+    }
+
+    /// Points to the dependent, found with the code of the library that
+    /// created the cell.
+    pub fn dependent_ptr(&self) -> *const c_void {
+        unsafe { (self.vtable.dependent_ptr)(self.joined_void_ptr) }
+    }
+
+    /// # Safety
+    ///
+    /// Owner has the same layout in this library and the one that created the
+    /// cell.
+    pub unsafe fn borrow_owner(&self) -> &<Cell as MapTarget>::Owner {
+        &*self.owner_ptr().cast()
+    }
+
+    /// Calls func with owner and dependent, the same as `with_dependent` of
+    /// the cell.
+    ///
+    /// # Safety
+    ///
+    /// Owner and dependent have the same layout in this library and the one
+    /// that created the cell.
+    pub unsafe fn with_dependent<'s, Ret>(
+        &'s self,
+        func: impl for<'a> FnOnce(
+            &'a <Cell as MapTarget>::Owner,
+            &'a <Cell as DependentOf<'a>>::Dependent,
+        ) -> Ret,
+    ) -> Ret {
+        let owner = &*self.owner_ptr().cast::<<Cell as MapTarget>::Owner>();
+        let dependent = &*self
+            .dependent_ptr()
+            .cast::<<Cell as DependentOf<'s>>::Dependent>();
+
+        func(owner, dependent)
+    }
+
+    /// Turns the handle back into the cell.
+    ///
+    /// # Safety
+    ///
+    /// The cell was created by this library, or one built from the same
+    /// source with the same compiler and allocator.
+    pub unsafe fn into_cell(self) -> Cell {
+        // Drop would otherwise drop the cell.
+        let ffi_cell = core::mem::ManuallyDrop::new(self);
+        Cell::from_joined_void_ptr(ffi_cell.joined_void_ptr.cast())
+    }
+}
+
+impl<Cell> From<Cell> for FfiCell<Cell>
+where
+    Cell: for<'a> DependentOf<'a> + MapTarget,
+{
+    fn from(cell: Cell) -> Self {
+        Self::new(cell)
+    }
+}
+
+impl<Cell> Drop for FfiCell<Cell>
+where
+    Cell: for<'a> DependentOf<'a> + MapTarget,
+{
+    fn drop(&mut self) {
+        unsafe { (self.vtable.drop)(self.joined_void_ptr) }
+    }
+}
+
+// The handle owns the cell, the table only holds functions.
+unsafe impl<Cell> Send for FfiCell<Cell> where Cell: for<'a> DependentOf<'a> + MapTarget + Send {}
+
+unsafe impl<Cell> Sync for FfiCell<Cell> where Cell: for<'a> DependentOf<'a> + MapTarget + Sync {}
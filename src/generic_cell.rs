@@ -0,0 +1,235 @@
+use core::marker::PhantomData;
+
+use crate::unsafe_self_cell::{OwnerAndCellDropGuard, Storage, UnsafeSelfCell};
+use crate::DependentOf;
+
+/// Builds the dependent of a [`SelfCell`] from a reference to owner, for
+/// every lifetime of that reference.
+///
+/// Implement it for a marker type:
+///
+/// ```ignore
+/// struct Words;
+///
+/// impl<'a> DependentBuilder<'a, String> for Words {
+///     type Output = Vec<&'a str>;
+///
+///     fn build(owner: &'a String) -> Self::Output {
+///         owner.split(' ').collect()
+///     }
+/// }
+/// ```
+///
+/// `build` takes no `self`, so there is nothing it could smuggle outside
+/// references in from.
+pub trait DependentBuilder<'a, Owner> {
+    type Output: 'a;
+
+    fn build(owner: &'a Owner) -> Self::Output;
+}
+
+/// Self-referential struct declared with types instead of the
+/// [`self_cell`](crate::self_cell) macro.
+///
+/// `Builder` names the dependent type and how to build it, see
+/// [`DependentBuilder`]. Both front-ends share the same internals.
+///
+/// Because `SelfCell` is generic over the dependent type, it can't know if it
+/// is covariant. All access to the dependent happens inside closures, like the
+/// `not_covariant` cells generated by the macro.
+///
+/// ```
+/// use self_cell::{DependentBuilder, SelfCell};
+///
+/// struct Words;
+///
+/// impl<'a> DependentBuilder<'a, String> for Words {
+///     type Output = Vec<&'a str>;
+///
+///     fn build(owner: &'a String) -> Self::Output {
+///         owner.split(' ').collect()
+///     }
+/// }
+///
+/// let cell = SelfCell::<String, Words>::new("fox cat dog".into());
+///
+/// assert_eq!(cell.with_dependent(|_, words| words.len()), 3);
+/// assert_eq!(cell.borrow_owner(), "fox cat dog");
+/// ```
+pub struct SelfCell<Owner: 'static, Builder>
+where
+    Builder: for<'a> DependentBuilder<'a, Owner> + 'static,
+{
+    heap_cell: HeapCell<Owner, BuilderOutput<Owner, Builder>>,
+}
+
+// Names the dependent built by Builder as DependentOf, for HeapCell.
+pub(crate) struct BuilderOutput<Owner, Builder>(PhantomData<(Owner, Builder)>);
+
+impl<'a, Owner, Builder> DependentOf<'a> for BuilderOutput<Owner, Builder>
+where
+    Builder: DependentBuilder<'a, Owner>,
+{
+    type Dependent = <Builder as DependentBuilder<'a, Owner>>::Output;
+}
+
+impl<Owner, Builder> SelfCell<Owner, Builder>
+where
+    Builder: for<'a> DependentBuilder<'a, Owner> + 'static,
+{
+    /// Moves owner into the heap and builds the dependent from it with
+    /// `Builder::build`.
+    pub fn new(owner: Owner) -> Self {
+        Self {
+            heap_cell: HeapCell::new(owner, |owner| Builder::build(owner)),
+        }
+    }
+
+    pub fn borrow_owner(&self) -> &Owner {
+        self.heap_cell.borrow_owner()
+    }
+
+    /// Calls func with owner and the dependent.
+    pub fn with_dependent<Ret>(
+        &self,
+        func: impl for<'a> FnOnce(
+            &'a Owner,
+            &'a <Builder as DependentBuilder<'a, Owner>>::Output,
+        ) -> Ret,
+    ) -> Ret {
+        self.heap_cell.with_dependent(func)
+    }
+
+    /// Calls func with owner and mutable access to the dependent.
+    pub fn with_dependent_mut<Ret>(
+        &mut self,
+        func: impl for<'a> FnOnce(
+            &'a Owner,
+            &'a mut <Builder as DependentBuilder<'a, Owner>>::Output,
+        ) -> Ret,
+    ) -> Ret {
+        self.heap_cell.with_dependent_mut(func)
+    }
+
+    pub fn into_owner(self) -> Owner {
+        self.heap_cell.into_owner()
+    }
+}
+
+// Heap allocated owner and dependent, shared by SelfCell, OwnedIterCell and
+// OwnedCaptures. Dependent names the dependent type for every lifetime, the
+// front-ends either know it's covariant, or only access it inside closures.
+pub(crate) struct HeapCell<Owner: 'static, Dependent>
+where
+    Dependent: for<'a> DependentOf<'a> + 'static,
+{
+    unsafe_self_cell: UnsafeSelfCell<Owner, <Dependent as DependentOf<'static>>::Dependent>,
+
+    dependent_marker: PhantomData<Dependent>,
+}
+
+impl<Owner, Dependent> HeapCell<Owner, Dependent>
+where
+    Dependent: for<'a> DependentOf<'a> + 'static,
+{
+    pub(crate) fn new(
+        owner: Owner,
+        dependent_builder: impl for<'a> FnOnce(&'a Owner) -> <Dependent as DependentOf<'a>>::Dependent,
+    ) -> Self {
+        unsafe {
+            // See the fn new generated by self_cell for more explanation.
+            let drop_guard = OwnerAndCellDropGuard::allocate(owner);
+
+            let dependent = dependent_builder(&*drop_guard.owner_ptr());
+
+            Self {
+                unsafe_self_cell: UnsafeSelfCell::new(drop_guard.init_dependent(dependent)),
+                dependent_marker: PhantomData,
+            }
+        }
+    }
+
+    // On failure owner is dropped and the error returned.
+    pub(crate) fn try_new<Err>(
+        owner: Owner,
+        dependent_builder: impl for<'a> FnOnce(
+            &'a Owner,
+        )
+            -> Result<<Dependent as DependentOf<'a>>::Dependent, Err>,
+    ) -> Result<Self, Err> {
+        unsafe {
+            let drop_guard = OwnerAndCellDropGuard::allocate(owner);
+
+            match dependent_builder(&*drop_guard.owner_ptr()) {
+                Ok(dependent) => Ok(Self {
+                    unsafe_self_cell: UnsafeSelfCell::new(drop_guard.init_dependent(dependent)),
+                    dependent_marker: PhantomData,
+                }),
+                Err(err) => Err(err),
+            }
+        }
+    }
+
+    pub(crate) fn borrow_owner<'a>(&'a self) -> &'a Owner {
+        unsafe {
+            self.unsafe_self_cell
+                .borrow_owner::<<Dependent as DependentOf<'a>>::Dependent>()
+        }
+    }
+
+    // Only sound if the dependent is covariant, otherwise the shorter
+    // lifetime allows storing references that don't live as long as owner.
+    pub(crate) unsafe fn borrow_dependent<'a>(
+        &'a self,
+    ) -> &'a <Dependent as DependentOf<'a>>::Dependent {
+        self.unsafe_self_cell
+            .borrow_dependent::<<Dependent as DependentOf<'a>>::Dependent>()
+    }
+
+    pub(crate) fn with_dependent<Ret>(
+        &self,
+        func: impl for<'a> FnOnce(&'a Owner, &'a <Dependent as DependentOf<'a>>::Dependent) -> Ret,
+    ) -> Ret {
+        unsafe {
+            func(
+                self.unsafe_self_cell
+                    .borrow_owner::<<Dependent as DependentOf<'_>>::Dependent>(),
+                self.unsafe_self_cell
+                    .borrow_dependent::<<Dependent as DependentOf<'_>>::Dependent>(),
+            )
+        }
+    }
+
+    pub(crate) fn with_dependent_mut<Ret>(
+        &mut self,
+        func: impl for<'a> FnOnce(&'a Owner, &'a mut <Dependent as DependentOf<'a>>::Dependent) -> Ret,
+    ) -> Ret {
+        let (owner, dependent) = unsafe {
+            self.unsafe_self_cell
+                .borrow_mut::<<Dependent as DependentOf<'_>>::Dependent>()
+        };
+        func(owner, dependent)
+    }
+
+    pub(crate) fn into_owner(self) -> Owner {
+        // Drop would otherwise run on the moved out cell.
+        let cell = core::mem::ManuallyDrop::new(self);
+        let unsafe_self_cell = unsafe { core::ptr::read(&cell.unsafe_self_cell) };
+
+        unsafe {
+            unsafe_self_cell.into_owner::<<Dependent as DependentOf<'_>>::Dependent>(Storage::Heap)
+        }
+    }
+}
+
+impl<Owner, Dependent> Drop for HeapCell<Owner, Dependent>
+where
+    Dependent: for<'a> DependentOf<'a> + 'static,
+{
+    fn drop(&mut self) {
+        unsafe {
+            self.unsafe_self_cell
+                .drop_joined::<<Dependent as DependentOf<'_>>::Dependent>(Storage::Heap);
+        }
+    }
+}
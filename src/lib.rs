@@ -39,10 +39,10 @@
 //! expensive to compile due to its use of procedural macros.
 //!
 //! This alternative is `no_std`, uses no proc-macros, some self contained
-//! unsafe and works on stable Rust, and is miri tested. With a total of less
-//! than 300 lines of implementation code, which consists mostly of type and
-//! trait implementations, this crate aims to be a good minimal solution to the
-//! problem of self-referential structs.
+//! unsafe and works on stable Rust, and is miri tested. The core, the
+//! `self_cell!` macro and the unsafe layer beneath it, is kept minimal. Most of
+//! the roughly 3000 lines of implementation code are the opt-in dependent
+//! modes, constructors and collection types built on top of it.
 //!
 //! It has undergone [community code
 //! review](https://users.rust-lang.org/t/experimental-safe-to-use-proc-macro-free-self-referential-structs-in-stable-rust/52775)
@@ -51,20 +51,25 @@
 //! ### Fast compile times
 //!
 //! ```ignore
-//! $ rm -rf target && cargo +nightly build -Z timings
+//! $ rm -rf target && cargo build --timings
 //!
-//! Compiling self_cell v0.7.0
-//! Completed self_cell v0.7.0 in 0.2s
+//! Compiling self_cell v0.9.0
+//! Completed self_cell v0.9.0 build-script in 0.1s
+//! Completed self_cell v0.9.0 in 0.2s
 //! ```
 //!
-//! Because it does **not** use proc-macros, and has 0 dependencies
-//! compile-times are fast.
+//! Because it does **not** use proc-macros, and has no required dependencies
+//! compile-times are fast. The optional `parking_lot`, `stable_deref_trait` and
+//! `rayon` dependencies are only pulled in by the features of the same name. loom
+//! and criterion are only used by the tests and benchmarks.
 //!
-//! Measurements done on a slow laptop.
+//! Measured with the default features.
 //!
 //! ### A motivating use case
 //!
-//! ```rust
+// The heap allocated examples are only tested with alloc.
+#![cfg_attr(feature = "alloc", doc = "```rust")]
+#![cfg_attr(not(feature = "alloc"), doc = "```ignore")]
 //! use self_cell::self_cell;
 //!
 //! #[derive(Debug, Eq, PartialEq)]
@@ -131,80 +136,2045 @@
 //!
 //! - [How to build a lazy AST with
 //!   self_cell](https://github.com/Voultapher/self_cell/tree/main/examples/lazy_ast)
+//!
+//! - [How to nest one cell in the owner of
+//!   another](https://github.com/Voultapher/self_cell/tree/main/examples/nested_cells)
+//!
+//! ### Unsized owners
+//!
+//! Text and byte owners are often best held as `Box<str>`, `Box<[u8]>` or
+//! `Arc<str>`, which unlike `String` and `Vec<u8>` carry no spare capacity.
+//! The owner field of the cell is then the box itself, a fat pointer, and the
+//! dependent borrows the boxed payload behind it. The payload is never moved
+//! or reallocated while the cell is alive, so these borrows stay valid no
+//! matter how often the cell is moved, and `into_owner` returns the same box
+//! with the payload in place. `new_from` converts owner with `Into`, which
+//! saves converting at every call site:
+//!
+#![cfg_attr(feature = "alloc", doc = "```rust")]
+#![cfg_attr(not(feature = "alloc"), doc = "```ignore")]
+//! use self_cell::self_cell;
+//!
+//! type Words<'a> = Vec<&'a str>;
+//!
+//! self_cell!(
+//!     struct WordsCell {
+//!         owner: Box<str>,
+//!
+//!         #[covariant]
+//!         dependent: Words,
+//!     }
+//! );
+//!
+//! let cell = WordsCell::new_from("fox cat", |text| text.split(' ').collect());
+//! let cell = Box::new(cell);
+//! assert_eq!(cell.borrow_dependent(), &["fox", "cat"]);
+//! ```
+//!
+//! ### Passing cells between libraries
+//!
+//! The layout of a cell is unspecified like that of any other Rust struct, so
+//! a cell can't cross the boundary to a plugin built separately, possibly with
+//! another Rust version. With the `ffi` feature, `FfiCell` wraps a cell in a
+//! `#[repr(C)]` handle together with `extern "C"` functions to find, borrow and
+//! drop owner and dependent, all compiled with the library that created it.
+//!
+//! ### Nesting cells
+//!
+//! A cell can be the owner of another cell, eg. a file view borrowing from a
+//! memory mapping, and an index borrowing from the file view. The inner cell is
+//! an owner like any other, its `borrow_dependent` ties the references to the
+//! borrow of the outer owner, so no additional safety argument is needed. The
+//! outer cell is `Send` and `Sync` if all owners and dependents are, and
+//! `into_owner` unwraps one layer at a time:
+//!
+//! ```ignore
+//! let index_cell = IndexCell::new(file_cell, |file| build_index(file.borrow_dependent()));
+//! let file_cell = index_cell.into_owner();
+//! let mapping = file_cell.into_owner();
+//! ```
+
+#![no_std]
+// Checked in CI with a nightly compiler, the lints are unstable.
+#![cfg_attr(self_cell_strict_provenance_lints, feature(strict_provenance_lints))]
+#![cfg_attr(
+    self_cell_strict_provenance_lints,
+    deny(fuzzy_provenance_casts, lossy_provenance_casts)
+)]
+
+#[doc(hidden)]
+#[cfg(feature = "alloc")]
+pub extern crate alloc;
+
+#[cfg(feature = "std")]
+extern crate std;
+
+#[doc(hidden)]
+pub mod unsafe_self_cell;
+
+pub use unsafe_self_cell::{
+    AllocError, BorrowedDependent, BuildError, DependentOf, DependentRef, JoinedStorage, OwnerMut,
+    SelfCellExt,
+};
+
+#[cfg(feature = "alloc")]
+mod owned_captures;
+
+#[cfg(feature = "alloc")]
+pub use owned_captures::{CapturesFamily, OwnedCaptures};
+
+#[cfg(feature = "alloc")]
+mod generic_cell;
+
+#[cfg(feature = "alloc")]
+pub use generic_cell::{DependentBuilder, SelfCell};
+
+#[cfg(feature = "alloc")]
+mod owned_iter;
+
+#[cfg(feature = "alloc")]
+pub use owned_iter::{ItemFamily, OwnedIterCell};
+
+#[cfg(feature = "alloc")]
+mod cell_arena;
+
+#[cfg(feature = "alloc")]
+pub use cell_arena::CellArena;
+
+#[cfg(feature = "alloc")]
+mod partial_cell;
+
+#[cfg(feature = "alloc")]
+pub use partial_cell::PartialCell;
+
+#[cfg(feature = "ffi")]
+mod ffi_cell;
+
+#[cfg(feature = "ffi")]
+pub use ffi_cell::{FfiCell, FfiCellVTable};
+
+#[cfg(feature = "stable_deref_trait")]
+pub use stable_deref_trait::StableDeref;
+
+#[doc(hidden)]
+#[cfg(any(feature = "std", feature = "parking_lot"))]
+pub mod sync;
+
+#[cfg(all(feature = "alloc", any(feature = "std", feature = "parking_lot")))]
+mod reloadable;
+
+#[cfg(all(feature = "alloc", any(feature = "std", feature = "parking_lot")))]
+pub use reloadable::ReloadableSelfCell;
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! _covariant_access {
+    ([$(#[$Meta:meta])*], covariant, $Vis:vis, $Dependent:tt) => {
+        /// Borrows the dependent.
+        #[inline]
+        $(#[$Meta])*
+        $Vis fn borrow_dependent<'a>(&'a self) -> &'a $crate::BorrowedDependent<'a, Self> {
+            unsafe { self.unsafe_self_cell.borrow_dependent() }
+        }
+
+        // An associated fn and not one nested in borrow_dependent, which
+        // couldn't name the dependent through Self.
+        fn _assert_covariance<'x: 'y, 'y>(x: $crate::_dependent!($Dependent, 'x)) -> $crate::_dependent!($Dependent, 'y) {
+            //  This function only compiles for covariant types.
+            x // Change the macro invocation to not_covariant.
+        }
+    };
+    ([$(#[$Meta:meta])*], not_covariant, $Vis:vis, $Dependent:tt) => {
+        // For types that are not covariant it's unsafe to allow
+        // returning direct references.
+        // For example a lifetime that is too short could be chosen:
+        // See https://github.com/Voultapher/self_cell/issues/5
+    };
+    ([$(#[$Meta:meta])*], $x:ident, $Vis:vis, $Dependent:tt) => {
+        compile_error!("This macro only accepts `covariant` or `not_covariant`");
+    };
+}
+
+// The dependent is passed around as `{Name}` or `{Name<'x, ...>}`, every `'_`
+// is replaced with the borrow lifetime of owner.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! _dependent {
+    // Only the DependentOf impl names the dependent itself, so that a wrong
+    // dependent is reported once and not for every generated fn.
+    ({Self}, $Lifetime:lifetime) => {
+        <Self as $crate::DependentOf<$Lifetime>>::Dependent
+    };
+    ({$Dependent:ident}, $Lifetime:lifetime) => {
+        $Dependent<$Lifetime>
+    };
+    ({$Dependent:ident <$($Arg:lifetime),+>}, $Lifetime:lifetime) => {
+        $crate::_dependent!(@replace $Dependent, $Lifetime, [], [$($Arg),+])
+    };
+    (@replace $Dependent:ident, $Lifetime:lifetime, [$($Done:lifetime),*], ['_ $(, $Rest:lifetime)*]) => {
+        $crate::_dependent!(@replace $Dependent, $Lifetime, [$($Done,)* $Lifetime], [$($Rest),*])
+    };
+    (@replace $Dependent:ident, $Lifetime:lifetime, [$($Done:lifetime),*], [$Arg:lifetime $(, $Rest:lifetime)*]) => {
+        $crate::_dependent!(@replace $Dependent, $Lifetime, [$($Done,)* $Arg], [$($Rest),*])
+    };
+    (@replace $Dependent:ident, $Lifetime:lifetime, [$($Done:lifetime),*], []) => {
+        $Dependent<$($Done),*>
+    };
+}
+
+// Expands to the cell if a listed dependent lifetime is `'_`, otherwise no
+// lifetime would borrow owner.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! _check_dependent {
+    ([], $Dependent:ident, {$($Cell:tt)*}) => {
+        $($Cell)*
+    };
+    ([$($Lifetime:lifetime),+], $Dependent:ident, {$($Cell:tt)*}) => {
+        $crate::_check_dependent!(@find [$($Lifetime)+], [$($Lifetime),+], $Dependent, {$($Cell)*});
+    };
+    (@find ['_ $($Rest:lifetime)*], [$($Lifetime:lifetime),+], $Dependent:ident, {$($Cell:tt)*}) => {
+        $($Cell)*
+    };
+    (@find [$Other:lifetime $($Rest:lifetime)*], [$($Lifetime:lifetime),+], $Dependent:ident, {$($Cell:tt)*}) => {
+        $crate::_check_dependent!(@find [$($Rest)*], [$($Lifetime),+], $Dependent, {$($Cell)*});
+    };
+    (@find [], [$($Lifetime:lifetime),+], $Dependent:ident, {$($Cell:tt)*}) => {
+        compile_error!(concat!(
+            "The dependent has to be the name of a type with exactly one lifetime parameter, ",
+            "eg. `type Ast<'a> = Vec<&'a str>;` and `dependent: Ast`, or list its lifetimes with `'_` ",
+            "for the ones borrowing owner, eg. `dependent: Parsed<'_, 'static>`, found `",
+            stringify!($Dependent),
+            "<",
+            stringify!($($Lifetime),+),
+            ">`"
+        ));
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! _stored_dependent {
+    ([], $Dependent:ty) => {
+        $Dependent
+    };
+    ([rw_lock], $Dependent:ty) => {
+        $crate::_lock!(RwLock, $Dependent)
+    };
+    ([mutex], $Dependent:ty) => {
+        $crate::_lock!(Mutex, $Dependent)
+    };
+    ([static_storage], $Dependent:ty) => {
+        $Dependent
+    };
+    ([owner_mut], $Dependent:ty) => {
+        $Dependent
+    };
+    ([small_owner], $Dependent:ty) => {
+        $Dependent
+    };
+    ([stable_deref], $Dependent:ty) => {
+        $Dependent
+    };
+    // Unknown modes are reported once by _check_mode and otherwise treated like
+    // no mode, so they don't cause follow up errors.
+    ([$x:ident], $Dependent:ty) => {
+        $Dependent
+    };
+}
+
+// The generated functions introduce these lifetimes next to the owner
+// lifetime, which would shadow them.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! _reserved_lifetime {
+    ($OwnerLifetime:lifetime) => {
+        compile_error!(concat!(
+            "The owner lifetime can't be named ",
+            stringify!($OwnerLifetime),
+            ", the generated functions already use that name. Reserved are 'a, 'b, 'x, 'y and '_brand"
+        ));
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! _check_mode {
+    (rw_lock) => {};
+    (mutex) => {};
+    (static_storage) => {};
+    (owner_mut) => {};
+    (small_owner) => {};
+    (stable_deref) => {};
+    ($x:ident) => {
+        compile_error!(concat!(
+            "Unknown dependent mode: `",
+            stringify!($x),
+            "`, expected `rw_lock`, `mutex`, `static_storage`, `owner_mut`, `small_owner` or `stable_deref`"
+        ));
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! _store_dependent {
+    ([], $dependent:expr) => {
+        $dependent
+    };
+    ([rw_lock], $dependent:expr) => {
+        <$crate::_stored_dependent!([rw_lock], _)>::new($dependent)
+    };
+    ([mutex], $dependent:expr) => {
+        <$crate::_stored_dependent!([mutex], _)>::new($dependent)
+    };
+    ([$Mode:ident], $dependent:expr) => {
+        $dependent
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! _cell_struct {
+    (
+        [small_owner], [$(#[$StructMeta:meta])*], $Vis:vis, $StructName:ident,
+        [$($OwnerLifetime:lifetime)?], $Owner:ty, $DependentStatic:ty
+    ) => {
+        // The cell can't be transmuted into UnsafeSelfCell, owner_copy has to
+        // be skipped explicitly. A Copy bound here would be repeated as error
+        // for every function, the constructors check it instead.
+        $(#[$StructMeta])*
+        $Vis struct $StructName $(<$OwnerLifetime>)? {
+            unsafe_self_cell: $crate::unsafe_self_cell::UnsafeSelfCell<$Owner, $DependentStatic>,
+
+            // Equal to the owner in the JoinedCell, which is never mutated.
+            owner_copy: $Owner,
+        }
+    };
+    (
+        [$($Mode:ident)?], [$(#[$StructMeta:meta])*], $Vis:vis, $StructName:ident,
+        [$($OwnerLifetime:lifetime)?], $Owner:ty, $DependentStatic:ty
+    ) => {
+        #[repr(transparent)]
+        $(#[$StructMeta])*
+        $Vis struct $StructName $(<$OwnerLifetime>)? {
+            unsafe_self_cell: $crate::unsafe_self_cell::UnsafeSelfCell<$Owner, $DependentStatic>
+        }
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! _assert_owner_copy {
+    ($owner:expr) => {{
+        fn small_owner_needs_copy_owner<Owner: Copy>(_owner: &Owner) {}
+        small_owner_needs_copy_owner(&$owner);
+    }};
+}
+
+#[doc(hidden)]
+#[cfg(feature = "stable_deref_trait")]
+#[macro_export]
+macro_rules! _assert_owner_stable_deref {
+    ($owner:expr) => {{
+        fn stable_deref_needs_stable_deref_owner<Owner: $crate::StableDeref>(_owner: &Owner) {}
+        stable_deref_needs_stable_deref_owner(&$owner);
+    }};
+}
+
+#[doc(hidden)]
+#[cfg(not(feature = "stable_deref_trait"))]
+#[macro_export]
+macro_rules! _assert_owner_stable_deref {
+    ($owner:expr) => {
+        compile_error!(
+            "The `stable_deref` dependent mode needs the `stable_deref_trait` feature of self_cell"
+        )
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! _storage {
+    ([static_storage]) => {
+        $crate::unsafe_self_cell::Storage::Static
+    };
+    ([$($Mode:ident)?]) => {
+        $crate::unsafe_self_cell::Storage::Heap
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! _constructors {
+    ([static_storage], $Vis:vis, $Owner:ty, $Dependent:tt) => {
+        /// Moves owner into storage and builds the dependent from it.
+        $Vis fn new_in(
+            storage: &'static mut $crate::JoinedStorage<$Owner, $crate::_dependent!($Dependent, 'static)>,
+            owner: $Owner,
+            dependent_builder: impl for<'a> FnOnce(&'a $Owner) -> $crate::_dependent!($Dependent, 'a)
+        ) -> Self {
+            unsafe {
+                // See fn new for more explanation.
+
+                let drop_guard = $crate::unsafe_self_cell::OwnerAndCellDropGuard::in_storage(
+                    storage.joined_void_ptr(),
+                    owner,
+                );
+
+                let dependent = dependent_builder(&*drop_guard.owner_ptr());
+
+                Self {
+                    unsafe_self_cell: $crate::unsafe_self_cell::UnsafeSelfCell::new(
+                        drop_guard.init_dependent(dependent),
+                    ),
+                }
+            }
+        }
+
+        /// Same as `new_in`, but the dependent builder can fail.
+        $Vis fn try_new_in<Err>(
+            storage: &'static mut $crate::JoinedStorage<$Owner, $crate::_dependent!($Dependent, 'static)>,
+            owner: $Owner,
+            dependent_builder: impl for<'a> FnOnce(&'a $Owner) -> Result<$crate::_dependent!($Dependent, 'a), Err>
+        ) -> Result<Self, Err> {
+            unsafe {
+                // See fn new for more explanation.
+
+                let drop_guard = $crate::unsafe_self_cell::OwnerAndCellDropGuard::in_storage(
+                    storage.joined_void_ptr(),
+                    owner,
+                );
+
+                match dependent_builder(&*drop_guard.owner_ptr()) {
+                    Ok(dependent) => Ok(Self {
+                        unsafe_self_cell: $crate::unsafe_self_cell::UnsafeSelfCell::new(
+                            drop_guard.init_dependent(dependent),
+                        ),
+                    }),
+                    Err(err) => Err(err)
+                }
+            }
+        }
+
+        /// Same as `try_new_in`, but returns owner together with the error.
+        $Vis fn try_new_or_recover_in<Err>(
+            storage: &'static mut $crate::JoinedStorage<$Owner, $crate::_dependent!($Dependent, 'static)>,
+            owner: $Owner,
+            dependent_builder: impl for<'a> FnOnce(&'a $Owner) -> Result<$crate::_dependent!($Dependent, 'a), Err>
+        ) -> Result<Self, ($Owner, Err)> {
+            unsafe {
+                // See fn new for more explanation.
+
+                let drop_guard = $crate::unsafe_self_cell::OwnerAndCellDropGuard::in_storage(
+                    storage.joined_void_ptr(),
+                    owner,
+                );
+
+                match dependent_builder(&*drop_guard.owner_ptr()) {
+                    Ok(dependent) => Ok(Self {
+                        unsafe_self_cell: $crate::unsafe_self_cell::UnsafeSelfCell::new(
+                            drop_guard.init_dependent(dependent),
+                        ),
+                    }),
+                    Err(err) => Err((drop_guard.recover_owner(), err))
+                }
+            }
+        }
+    };
+    ([$($Mode:ident)?], $Vis:vis, $Owner:ty, $Dependent:tt) => {
+        $crate::_heap_constructors!([$($Mode)?], $Vis, $Owner, $Dependent);
+    };
+}
+
+#[doc(hidden)]
+#[cfg(feature = "alloc")]
+#[macro_export]
+macro_rules! _heap_constructors {
+    ([owner_mut], $Vis:vis, $Owner:ty, $Dependent:tt) => {
+        /// Moves owner to the heap and builds the dependent from a unique borrow of it.
+        $Vis fn new(
+            owner: $Owner,
+            dependent_builder: impl for<'a> FnOnce(&'a mut $Owner) -> $crate::_dependent!($Dependent, 'a)
+        ) -> Self {
+            unsafe {
+                // See fn new for more explanation. Owner is exclusively
+                // borrowed by dependent from here on, the cell never hands out
+                // references to it.
+
+                let mut drop_guard = $crate::unsafe_self_cell::OwnerAndCellDropGuard::allocate(owner);
+
+                let dependent = dependent_builder(&mut *drop_guard.owner_mut_ptr());
+
+                Self {
+                    unsafe_self_cell: $crate::unsafe_self_cell::UnsafeSelfCell::new(
+                        drop_guard.init_dependent(dependent),
+                    ),
+                }
+            }
+        }
+
+        /// Same as `new`, but the dependent builder can fail.
+        $Vis fn try_new<Err>(
+            owner: $Owner,
+            dependent_builder: impl for<'a> FnOnce(&'a mut $Owner) -> Result<$crate::_dependent!($Dependent, 'a), Err>
+        ) -> Result<Self, Err> {
+            match Self::try_new_or_recover(owner, dependent_builder) {
+                Ok(cell) => Ok(cell),
+                Err((_, err)) => Err(err),
+            }
+        }
+
+        /// Same as `try_new`, but returns owner together with the error.
+        $Vis fn try_new_or_recover<Err>(
+            owner: $Owner,
+            dependent_builder: impl for<'a> FnOnce(&'a mut $Owner) -> Result<$crate::_dependent!($Dependent, 'a), Err>
+        ) -> Result<Self, ($Owner, Err)> {
+            unsafe {
+                // See fn new for more explanation. Err can't borrow owner, so
+                // the unique borrow has ended if building failed.
+
+                let mut drop_guard = $crate::unsafe_self_cell::OwnerAndCellDropGuard::allocate(owner);
+
+                match dependent_builder(&mut *drop_guard.owner_mut_ptr()) {
+                    Ok(dependent) => Ok(Self {
+                        unsafe_self_cell: $crate::unsafe_self_cell::UnsafeSelfCell::new(
+                            drop_guard.init_dependent(dependent),
+                        ),
+                    }),
+                    Err(err) => Err((drop_guard.recover_owner(), err))
+                }
+            }
+        }
+    };
+    ([stable_deref], $Vis:vis, $Owner:ty, $Dependent:tt) => {
+        /// Moves owner to the heap and builds the dependent from the data owner dereferences to.
+        $Vis fn new(
+            owner: $Owner,
+            dependent_builder: impl for<'a> FnOnce(
+                &'a <$Owner as core::ops::Deref>::Target
+            ) -> $crate::_dependent!($Dependent, 'a)
+        ) -> Self {
+            unsafe {
+                // See fn new for more explanation. The target is borrowed
+                // through the owner in its final place, which never moves.
+                // StableDeref isn't needed for that, it's required to keep the
+                // contract of other owning reference types, derefs always
+                // return the same address.
+
+                $crate::_assert_owner_stable_deref!(owner);
+
+                let drop_guard = $crate::unsafe_self_cell::OwnerAndCellDropGuard::allocate(owner);
+
+                let dependent = dependent_builder(&**drop_guard.owner_ptr());
+
+                Self {
+                    unsafe_self_cell: $crate::unsafe_self_cell::UnsafeSelfCell::new(
+                        drop_guard.init_dependent(dependent),
+                    ),
+                }
+            }
+        }
+
+        /// Same as `new`, but the dependent builder can fail.
+        $Vis fn try_new<Err>(
+            owner: $Owner,
+            dependent_builder: impl for<'a> FnOnce(
+                &'a <$Owner as core::ops::Deref>::Target
+            ) -> Result<$crate::_dependent!($Dependent, 'a), Err>
+        ) -> Result<Self, Err> {
+            match Self::try_new_or_recover(owner, dependent_builder) {
+                Ok(cell) => Ok(cell),
+                Err((_, err)) => Err(err),
+            }
+        }
+
+        /// Same as `try_new`, but returns owner together with the error.
+        $Vis fn try_new_or_recover<Err>(
+            owner: $Owner,
+            dependent_builder: impl for<'a> FnOnce(
+                &'a <$Owner as core::ops::Deref>::Target
+            ) -> Result<$crate::_dependent!($Dependent, 'a), Err>
+        ) -> Result<Self, ($Owner, Err)> {
+            unsafe {
+                // See fn new.
+
+                $crate::_assert_owner_stable_deref!(owner);
+
+                let drop_guard = $crate::unsafe_self_cell::OwnerAndCellDropGuard::allocate(owner);
+
+                match dependent_builder(&**drop_guard.owner_ptr()) {
+                    Ok(dependent) => Ok(Self {
+                        unsafe_self_cell: $crate::unsafe_self_cell::UnsafeSelfCell::new(
+                            drop_guard.init_dependent(dependent),
+                        ),
+                    }),
+                    Err(err) => Err((drop_guard.recover_owner(), err))
+                }
+            }
+        }
+
+        /// Same as `new`, but returns an error instead of calling `handle_alloc_error` if allocating fails.
+        $Vis fn try_new_fallible_alloc(
+            owner: $Owner,
+            dependent_builder: impl for<'a> FnOnce(
+                &'a <$Owner as core::ops::Deref>::Target
+            ) -> $crate::_dependent!($Dependent, 'a)
+        ) -> Result<Self, $crate::AllocError> {
+            unsafe {
+                // See fn new and the try_new_fallible_alloc without mode.
+
+                $crate::_assert_owner_stable_deref!(owner);
+
+                let drop_guard = $crate::unsafe_self_cell::OwnerAndCellDropGuard::try_allocate(owner)?;
+
+                let dependent = dependent_builder(&**drop_guard.owner_ptr());
+
+                Ok(Self {
+                    unsafe_self_cell: $crate::unsafe_self_cell::UnsafeSelfCell::new(
+                        drop_guard.init_dependent(dependent),
+                    ),
+                })
+            }
+        }
+
+        /// Same as `new`, but reuses the allocation of the boxed owner where possible.
+        $Vis fn new_from_box(
+            owner: $crate::alloc::boxed::Box<$Owner>,
+            dependent_builder: impl for<'a> FnOnce(
+                &'a <$Owner as core::ops::Deref>::Target
+            ) -> $crate::_dependent!($Dependent, 'a)
+        ) -> Self {
+            unsafe {
+                // See fn new and the new_from_box without mode.
+
+                $crate::_assert_owner_stable_deref!(*owner);
+
+                let drop_guard = $crate::unsafe_self_cell::OwnerAndCellDropGuard::allocate_from_box(owner);
+
+                let dependent = dependent_builder(&**drop_guard.owner_ptr());
+
+                Self {
+                    unsafe_self_cell: $crate::unsafe_self_cell::UnsafeSelfCell::new(
+                        drop_guard.init_dependent(dependent),
+                    ),
+                }
+            }
+        }
+
+        $crate::_wrapping_constructors!(
+            $Vis, $Owner, $Dependent, <$Owner as core::ops::Deref>::Target
+        );
+    };
+    ([small_owner], $Vis:vis, $Owner:ty, $Dependent:tt) => {
+        /// Moves owner to the heap and builds the dependent from it, keeping a copy of owner in the cell.
+        $Vis fn new(
+            owner: $Owner,
+            dependent_builder: impl for<'a> FnOnce(&'a $Owner) -> $crate::_dependent!($Dependent, 'a)
+        ) -> Self {
+            unsafe {
+                // See fn new for more explanation. Dependent only borrows the
+                // owner on the heap, the copy is for borrow_owner.
+
+                $crate::_assert_owner_copy!(owner);
+
+                let drop_guard = $crate::unsafe_self_cell::OwnerAndCellDropGuard::allocate(owner);
+
+                let dependent = dependent_builder(&*drop_guard.owner_ptr());
+
+                Self {
+                    unsafe_self_cell: $crate::unsafe_self_cell::UnsafeSelfCell::new(
+                        drop_guard.init_dependent(dependent),
+                    ),
+                    owner_copy: owner,
+                }
+            }
+        }
+
+        /// Same as `new`, but the dependent builder can fail.
+        $Vis fn try_new<Err>(
+            owner: $Owner,
+            dependent_builder: impl for<'a> FnOnce(&'a $Owner) -> Result<$crate::_dependent!($Dependent, 'a), Err>
+        ) -> Result<Self, Err> {
+            match Self::try_new_or_recover(owner, dependent_builder) {
+                Ok(cell) => Ok(cell),
+                Err((_, err)) => Err(err),
+            }
+        }
+
+        /// Same as `try_new`, but returns owner together with the error.
+        $Vis fn try_new_or_recover<Err>(
+            owner: $Owner,
+            dependent_builder: impl for<'a> FnOnce(&'a $Owner) -> Result<$crate::_dependent!($Dependent, 'a), Err>
+        ) -> Result<Self, ($Owner, Err)> {
+            unsafe {
+                // See fn new.
+
+                $crate::_assert_owner_copy!(owner);
+
+                let drop_guard = $crate::unsafe_self_cell::OwnerAndCellDropGuard::allocate(owner);
+
+                match dependent_builder(&*drop_guard.owner_ptr()) {
+                    Ok(dependent) => Ok(Self {
+                        unsafe_self_cell: $crate::unsafe_self_cell::UnsafeSelfCell::new(
+                            drop_guard.init_dependent(dependent),
+                        ),
+                        owner_copy: owner,
+                    }),
+                    Err(err) => Err((drop_guard.recover_owner(), err))
+                }
+            }
+        }
+
+        /// Same as `new`, but returns an error instead of calling `handle_alloc_error` if allocating fails.
+        $Vis fn try_new_fallible_alloc(
+            owner: $Owner,
+            dependent_builder: impl for<'a> FnOnce(&'a $Owner) -> $crate::_dependent!($Dependent, 'a)
+        ) -> Result<Self, $crate::AllocError> {
+            unsafe {
+                // See fn new and the try_new_fallible_alloc without mode.
+
+                $crate::_assert_owner_copy!(owner);
+
+                let drop_guard = $crate::unsafe_self_cell::OwnerAndCellDropGuard::try_allocate(owner)?;
+
+                let dependent = dependent_builder(&*drop_guard.owner_ptr());
+
+                Ok(Self {
+                    unsafe_self_cell: $crate::unsafe_self_cell::UnsafeSelfCell::new(
+                        drop_guard.init_dependent(dependent),
+                    ),
+                    owner_copy: owner,
+                })
+            }
+        }
+
+        /// Same as `new`, but reuses the allocation of the boxed owner where possible.
+        $Vis fn new_from_box(
+            owner: $crate::alloc::boxed::Box<$Owner>,
+            dependent_builder: impl for<'a> FnOnce(&'a $Owner) -> $crate::_dependent!($Dependent, 'a)
+        ) -> Self {
+            unsafe {
+                // See fn new and the new_from_box without mode.
+
+                $crate::_assert_owner_copy!(*owner);
+                let owner_copy = *owner;
+
+                let drop_guard = $crate::unsafe_self_cell::OwnerAndCellDropGuard::allocate_from_box(owner);
+
+                let dependent = dependent_builder(&*drop_guard.owner_ptr());
+
+                Self {
+                    unsafe_self_cell: $crate::unsafe_self_cell::UnsafeSelfCell::new(
+                        drop_guard.init_dependent(dependent),
+                    ),
+                    owner_copy,
+                }
+            }
+        }
+
+        $crate::_wrapping_constructors!($Vis, $Owner, $Dependent, $Owner);
+    };
+    ([$($Mode:ident)?], $Vis:vis, $Owner:ty, $Dependent:tt) => {
+        /// Moves owner to the heap and builds the dependent from it.
+        $Vis fn new(
+            owner: $Owner,
+            dependent_builder: impl for<'a> FnOnce(&'a $Owner) -> $crate::_dependent!($Dependent, 'a)
+        ) -> Self {
+            unsafe {
+                // All this has to happen here, because there is not good way
+                // of passing the appropriate logic into UnsafeSelfCell::new
+                // short of assuming Dependent<'static> is the same as
+                // Dependent<'a>, which I'm not confident is safe.
+
+                // For this API to be safe there has to be no safe way to
+                // capture additional references in `dependent_builder` and then
+                // return them as part of Dependent. Eg. it should be impossible
+                // to express: 'a should outlive 'x here `fn
+                // bad<'a>(outside_ref: &'a String) -> impl for<'x> FnOnce(&'x
+                // Owner) -> Dependent<'x>`.
+
+                // Move owner into newly allocated space. The drop guard cleans
+                // up should building the dependent panic.
+                let drop_guard = $crate::unsafe_self_cell::OwnerAndCellDropGuard::allocate(owner);
+
+                // Initialize dependent with owner reference in final place.
+                let dependent = dependent_builder(&*drop_guard.owner_ptr());
+
+                Self {
+                    unsafe_self_cell: $crate::unsafe_self_cell::UnsafeSelfCell::new(
+                        drop_guard.init_dependent($crate::_store_dependent!([$($Mode)?], dependent)),
+                    ),
+                }
+            }
+        }
+
+        /// Same as `new`, but returns an error instead of calling `handle_alloc_error` if allocating fails.
+        $Vis fn try_new_fallible_alloc(
+            owner: $Owner,
+            dependent_builder: impl for<'a> FnOnce(&'a $Owner) -> $crate::_dependent!($Dependent, 'a)
+        ) -> Result<Self, $crate::AllocError> {
+            unsafe {
+                // See fn new for more explanation. Owner is dropped if the
+                // allocation fails, dependent_builder is not called.
+
+                let drop_guard = $crate::unsafe_self_cell::OwnerAndCellDropGuard::try_allocate(owner)?;
+
+                let dependent = dependent_builder(&*drop_guard.owner_ptr());
+
+                Ok(Self {
+                    unsafe_self_cell: $crate::unsafe_self_cell::UnsafeSelfCell::new(
+                        drop_guard.init_dependent($crate::_store_dependent!([$($Mode)?], dependent)),
+                    ),
+                })
+            }
+        }
+
+        /// Same as `new`, but reuses the allocation of the boxed owner where possible.
+        $Vis fn new_from_box(
+            owner: $crate::alloc::boxed::Box<$Owner>,
+            dependent_builder: impl for<'a> FnOnce(&'a $Owner) -> $crate::_dependent!($Dependent, 'a)
+        ) -> Self {
+            unsafe {
+                // See fn new for more explanation. Owner is not moved, unless
+                // its allocation can't hold the JoinedCell.
+
+                let drop_guard = $crate::unsafe_self_cell::OwnerAndCellDropGuard::allocate_from_box(owner);
+
+                let dependent = dependent_builder(&*drop_guard.owner_ptr());
+
+                Self {
+                    unsafe_self_cell: $crate::unsafe_self_cell::UnsafeSelfCell::new(
+                        drop_guard.init_dependent($crate::_store_dependent!([$($Mode)?], dependent)),
+                    ),
+                }
+            }
+        }
+
+        /// Same as `new`, but the builder can mutate owner before freezing it with `OwnerMut::into_ref`.
+        $Vis fn new_mut(
+            owner: $Owner,
+            dependent_builder: impl for<'a, '_brand> FnOnce(
+                $crate::OwnerMut<'a, '_brand, $Owner>
+            ) -> $crate::_dependent!($Dependent, 'a)
+        ) -> Self {
+            unsafe {
+                // See fn new for more explanation. The dependent can only keep
+                // shared references to owner, see OwnerMut.
+
+                let mut drop_guard = $crate::unsafe_self_cell::OwnerAndCellDropGuard::allocate(owner);
+
+                let dependent = dependent_builder($crate::OwnerMut::new(&mut *drop_guard.owner_mut_ptr()));
+
+                Self {
+                    unsafe_self_cell: $crate::unsafe_self_cell::UnsafeSelfCell::new(
+                        drop_guard.init_dependent($crate::_store_dependent!([$($Mode)?], dependent)),
+                    ),
+                }
+            }
+        }
+
+        /// Same as `new`, but the dependent builder can fail.
+        $Vis fn try_new<Err>(
+            owner: $Owner,
+            dependent_builder: impl for<'a> FnOnce(&'a $Owner) -> Result<$crate::_dependent!($Dependent, 'a), Err>
+        ) -> Result<Self, Err> {
+            unsafe {
+                // See fn new for more explanation.
+
+                let drop_guard = $crate::unsafe_self_cell::OwnerAndCellDropGuard::allocate(owner);
+
+                match dependent_builder(&*drop_guard.owner_ptr()) {
+                    Ok(dependent) => Ok(Self {
+                        unsafe_self_cell: $crate::unsafe_self_cell::UnsafeSelfCell::new(
+                            drop_guard.init_dependent($crate::_store_dependent!([$($Mode)?], dependent)),
+                        ),
+                    }),
+                    Err(err) => Err(err)
+                }
+            }
+        }
+
+        /// Same as `try_new`, but returns owner together with the error.
+        $Vis fn try_new_or_recover<Err>(
+            owner: $Owner,
+            dependent_builder: impl for<'a> FnOnce(&'a $Owner) -> Result<$crate::_dependent!($Dependent, 'a), Err>
+        ) -> Result<Self, ($Owner, Err)> {
+            unsafe {
+                // See fn new for more explanation.
+
+                let drop_guard = $crate::unsafe_self_cell::OwnerAndCellDropGuard::allocate(owner);
+
+                match dependent_builder(&*drop_guard.owner_ptr()) {
+                    Ok(dependent) => Ok(Self {
+                        unsafe_self_cell: $crate::unsafe_self_cell::UnsafeSelfCell::new(
+                            drop_guard.init_dependent($crate::_store_dependent!([$($Mode)?], dependent)),
+                        ),
+                    }),
+                    Err(err) => Err((drop_guard.recover_owner(), err))
+                }
+            }
+        }
+
+        /// Same as `try_new_or_recover`, but calls the builder again as long as `retry_policy` returns true.
+        $Vis fn try_new_with_retry<Err>(
+            owner: $Owner,
+            mut dependent_builder: impl for<'a> FnMut(&'a $Owner) -> Result<$crate::_dependent!($Dependent, 'a), Err>,
+            mut retry_policy: impl FnMut(&Err, usize) -> bool
+        ) -> Result<Self, ($Owner, Err)> {
+            unsafe {
+                // See fn new for more explanation. The builder can't keep a
+                // reference to owner between attempts, it only lives for one
+                // call.
+
+                let drop_guard = $crate::unsafe_self_cell::OwnerAndCellDropGuard::allocate(owner);
+
+                let mut failed_attempts = 0;
+                loop {
+                    match dependent_builder(&*drop_guard.owner_ptr()) {
+                        Ok(dependent) => {
+                            return Ok(Self {
+                                unsafe_self_cell: $crate::unsafe_self_cell::UnsafeSelfCell::new(
+                                    drop_guard.init_dependent(
+                                        $crate::_store_dependent!([$($Mode)?], dependent)
+                                    ),
+                                ),
+                            });
+                        }
+                        Err(err) => {
+                            failed_attempts += 1;
+                            if !retry_policy(&err, failed_attempts) {
+                                return Err((drop_guard.recover_owner(), err));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        $crate::_wrapping_constructors!($Vis, $Owner, $Dependent, $Owner);
+
+        $crate::_plain_constructors!([$($Mode)?], $Vis, $Owner, $Dependent);
+
+        $crate::_async_constructors!([$($Mode)?], $Vis, $Owner, $Dependent);
+    };
+}
+
+#[doc(hidden)]
+#[cfg(feature = "alloc")]
+#[macro_export]
+macro_rules! _wrapping_constructors {
+    // Constructors that only call new, try_new or try_new_or_recover. Shared
+    // by the heap allocated modes whose builder borrows a $BuilderOwner.
+    ($Vis:vis, $Owner:ty, $Dependent:tt, $BuilderOwner:ty) => {
+        /// Same as `new`, but converts owner first, eg. a `&str` into a `Box<str>` owner.
+        $Vis fn new_from(
+            owner: impl Into<$Owner>,
+            dependent_builder: impl for<'a> FnOnce(&'a $BuilderOwner) -> $crate::_dependent!($Dependent, 'a)
+        ) -> Self {
+            Self::new(owner.into(), dependent_builder)
+        }
+
+        /// Same as `new`, but passes `ctx` by value to the dependent builder.
+        $Vis fn new_with_ctx<Ctx>(
+            owner: $Owner,
+            ctx: Ctx,
+            dependent_builder: impl for<'a> FnOnce(&'a $BuilderOwner, Ctx) -> $crate::_dependent!($Dependent, 'a)
+        ) -> Self {
+            // Ctx is chosen by the caller before 'a exists, so like captures of
+            // the builder it can only put 'static references into dependent.
+            Self::new(owner, move |owner| dependent_builder(owner, ctx))
+        }
+
+        /// Same as `try_new`, but passes `ctx` by value to the dependent builder.
+        $Vis fn try_new_with_ctx<Ctx, Err>(
+            owner: $Owner,
+            ctx: Ctx,
+            dependent_builder: impl for<'a> FnOnce(&'a $BuilderOwner, Ctx) -> Result<$crate::_dependent!($Dependent, 'a), Err>
+        ) -> Result<Self, Err> {
+            // See fn new_with_ctx.
+            Self::try_new(owner, move |owner| dependent_builder(owner, ctx))
+        }
+
+        /// Same as `try_new_or_recover`, but passes `ctx` by value to the dependent builder.
+        $Vis fn try_new_or_recover_with_ctx<Ctx, Err>(
+            owner: $Owner,
+            ctx: Ctx,
+            dependent_builder: impl for<'a> FnOnce(&'a $BuilderOwner, Ctx) -> Result<$crate::_dependent!($Dependent, 'a), Err>
+        ) -> Result<Self, ($Owner, Err)> {
+            // See fn new_with_ctx.
+            Self::try_new_or_recover(owner, move |owner| dependent_builder(owner, ctx))
+        }
+
+        /// Same as `new`, but first builds scratch data from owner, which is moved into the dependent builder and dropped after it.
+        $Vis fn new_two_phase<Scratch>(
+            owner: $Owner,
+            scratch_builder: impl for<'a> FnOnce(&'a $BuilderOwner) -> Scratch,
+            dependent_builder: impl for<'a> FnOnce(&'a $BuilderOwner, Scratch) -> $crate::_dependent!($Dependent, 'a)
+        ) -> Self {
+            // Scratch is a single type for all 'a, so like Ctx it can't
+            // borrow owner and only put 'static references into dependent.
+            Self::new(owner, move |owner| {
+                let scratch = scratch_builder(owner);
+                dependent_builder(owner, scratch)
+            })
+        }
+    };
+}
+
+#[doc(hidden)]
+#[cfg(feature = "alloc")]
+#[macro_export]
+macro_rules! _plain_constructors {
+    ([], $Vis:vis, $Owner:ty, $Dependent:tt) => {
+        /// Moves owner to the heap without building the dependent, which `PartialCell::build` does later.
+        $Vis fn new_uninit_dependent(owner: $Owner) -> $crate::PartialCell<Self> {
+            $crate::PartialCell::new(owner)
+        }
+
+        /// Moves all owners into one allocation and builds a dependent for each, see `CellArena`.
+        $Vis fn new_arena(
+            owners: $crate::alloc::vec::Vec<$Owner>,
+            dependent_builder: impl for<'a> FnMut(&'a $Owner) -> $crate::_dependent!($Dependent, 'a)
+        ) -> $crate::CellArena<Self> {
+            $crate::CellArena::<Self>::new(owners, dependent_builder)
+        }
+    };
+    // PartialCell and CellArena build the plain dependent, like map_dependent.
+    ([$Mode:ident], $Vis:vis, $Owner:ty, $Dependent:tt) => {};
+}
+
+#[doc(hidden)]
+#[cfg(feature = "async")]
+#[macro_export]
+macro_rules! _async_constructors {
+    ([$($Mode:ident)?], $Vis:vis, $Owner:ty, $Dependent:tt) => {
+        $crate::_async_constructors!(
+            @constructors [$($Mode)?], $Vis, $Owner, $Dependent,
+            [+ Send],
+            [
+                /// Same as `try_new_with_retry`, with a builder returning a future.
+                ///
+                /// The builder future has to be `Send`, so owner has to be `Sync` to be borrowed by it. The returned future is `Send` if owner, dependent, `Err` and the closures are. See `try_new_with_retry_async_local` otherwise.
+            ],
+            try_new_with_retry_async,
+            [
+                /// Same as `try_new_or_recover_with_ctx`, with a builder returning a future.
+                ///
+                /// The builder future has to be `Send`, see `try_new_with_ctx_async_local` otherwise.
+            ],
+            try_new_with_ctx_async
+        );
+
+        $crate::_async_constructors!(
+            @constructors [$($Mode)?], $Vis, $Owner, $Dependent,
+            [],
+            [
+                /// Same as `try_new_with_retry_async`, but the builder future doesn't have to be `Send`, eg. for `Rc` owners and single threaded executors.
+                ///
+                /// The returned future is never `Send`.
+            ],
+            try_new_with_retry_async_local,
+            [
+                /// Same as `try_new_with_ctx_async`, but the builder future doesn't have to be `Send`.
+                ///
+                /// The returned future is never `Send`.
+            ],
+            try_new_with_ctx_async_local
+        );
+    };
+    (
+        @constructors [$($Mode:ident)?], $Vis:vis, $Owner:ty, $Dependent:tt,
+        [$($FutureBounds:tt)*],
+        [$(#[$RetryDoc:meta])*],
+        $RetryName:ident,
+        [$(#[$CtxDoc:meta])*],
+        $CtxName:ident
+    ) => {
+        $(#[$RetryDoc])*
+        $Vis async fn $RetryName<Err>(
+            owner: $Owner,
+            mut dependent_builder: impl for<'a> FnMut(
+                &'a $Owner
+            ) -> core::pin::Pin<$crate::alloc::boxed::Box<
+                dyn core::future::Future<Output = Result<$crate::_dependent!($Dependent, 'a), Err>> $($FutureBounds)* + 'a
+            >>,
+            mut retry_policy: impl FnMut(&Err, usize) -> bool
+        ) -> Result<Self, ($Owner, Err)> {
+            // See fn try_new_with_retry. Should the returned future be dropped
+            // before it completes, the drop guard cleans up owner.
+            let drop_guard = unsafe {
+                $crate::unsafe_self_cell::OwnerAndCellDropGuard::allocate(owner)
+            };
+
+            let mut failed_attempts = 0;
+            loop {
+                let owner = unsafe { &*drop_guard.owner_ptr() };
+                let result = dependent_builder(owner).await;
+
+                match result {
+                    Ok(dependent) => {
+                        return Ok(unsafe {
+                            Self {
+                                unsafe_self_cell: $crate::unsafe_self_cell::UnsafeSelfCell::new(
+                                    drop_guard.init_dependent(
+                                        $crate::_store_dependent!([$($Mode)?], dependent)
+                                    ),
+                                ),
+                            }
+                        });
+                    }
+                    Err(err) => {
+                        failed_attempts += 1;
+                        if !retry_policy(&err, failed_attempts) {
+                            return Err((unsafe { drop_guard.recover_owner() }, err));
+                        }
+                    }
+                }
+            }
+        }
+
+        $(#[$CtxDoc])*
+        $Vis async fn $CtxName<Ctx, Err>(
+            owner: $Owner,
+            ctx: Ctx,
+            dependent_builder: impl for<'a> FnOnce(
+                &'a $Owner,
+                Ctx
+            ) -> core::pin::Pin<$crate::alloc::boxed::Box<
+                dyn core::future::Future<Output = Result<$crate::_dependent!($Dependent, 'a), Err>> $($FutureBounds)* + 'a
+            >>
+        ) -> Result<Self, ($Owner, Err)> {
+            // See fn new_with_ctx. Without retries the builder is called
+            // exactly once, so taking ctx and the builder can't fail.
+            let mut dependent_builder = Some(dependent_builder);
+            let mut ctx = Some(ctx);
+
+            Self::$RetryName(
+                owner,
+                move |owner| (dependent_builder.take().unwrap())(owner, ctx.take().unwrap()),
+                |_, _| false,
+            )
+            .await
+        }
+    };
+}
+
+#[doc(hidden)]
+#[cfg(not(feature = "async"))]
+#[macro_export]
+macro_rules! _async_constructors {
+    ([$($Mode:ident)?], $Vis:vis, $Owner:ty, $Dependent:tt) => {};
+}
+
+#[doc(hidden)]
+#[cfg(not(feature = "alloc"))]
+#[macro_export]
+macro_rules! _heap_constructors {
+    ([$($Mode:ident)?], $Vis:vis, $Owner:ty, $Dependent:tt) => {
+        compile_error!(
+            "Without the `alloc` feature of self_cell only the `static_storage` mode is available"
+        );
+    };
+}
+
+#[doc(hidden)]
+#[cfg(any(feature = "std", feature = "parking_lot"))]
+#[macro_export]
+macro_rules! _lock {
+    ($Lock:ident, $Dependent:ty) => {
+        $crate::sync::$Lock<$Dependent>
+    };
+}
+
+#[doc(hidden)]
+#[cfg(not(any(feature = "std", feature = "parking_lot")))]
+#[macro_export]
+macro_rules! _lock {
+    ($Lock:ident, $Dependent:ty) => {
+        compile_error!(
+            "The `rw_lock` and `mutex` dependent modes need the `std` or `parking_lot` feature of self_cell"
+        )
+    };
+}
+
+// Methods have the visibility of their field if one was given, otherwise the
+// one of the struct. An empty vis fragment can't be told apart from a missing
+// one, so the field visibility is passed as the raw tokens.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! _method_vis {
+    ([], $Vis:vis, $Access:ident, [$($Before:tt)*], [$($After:tt)*]) => {
+        $crate::$Access!($($Before)* $Vis, $($After)*);
+    };
+    ([$($FieldVis:tt)+], $Vis:vis, $Access:ident, [$($Before:tt)*], [$($After:tt)*]) => {
+        $crate::$Access!($($Before)* $($FieldVis)+, $($After)*);
+    };
+}
+
+// Methods borrowing the dependent as trait object, one for every AsDyn in the
+// automatic traits.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! _as_dyn {
+    ([rw_lock], $Vis:vis, $Dependent:tt, [AsDyn $($Rest:tt)*]) => {
+        compile_error!("`AsDyn` is not available for the `rw_lock` and `mutex` dependent modes, the dependent can't be borrowed without a guard");
+    };
+    ([mutex], $Vis:vis, $Dependent:tt, [AsDyn $($Rest:tt)*]) => {
+        $crate::_as_dyn!([rw_lock], $Vis, $Dependent, [AsDyn $($Rest)*]);
+    };
+    ([$($Mode:ident)?], $Vis:vis, $Dependent:tt, [AsDyn($Name:ident, $DynType:ty), $($Rest:tt)*]) => {
+        #[doc = concat!("Borrows the dependent as `", stringify!($DynType), "`.")]
+        $Vis fn $Name<'b>(&'b self) -> &'b $DynType {
+            // 'b is generic, so this only compiles if the dependent coerces
+            // for any lifetime, and the trait object can't expose or change
+            // the lifetime of the dependent. This allows it to be used for
+            // dependents that aren't covariant.
+            let dependent: &'b $crate::_dependent!($Dependent, 'b) = unsafe {
+                self.unsafe_self_cell.borrow_dependent()
+            };
+
+            dependent
+        }
+
+        $crate::_as_dyn!([$($Mode)?], $Vis, $Dependent, [$($Rest)*]);
+    };
+    ([$($Mode:ident)?], $Vis:vis, $Dependent:tt, [AsDyn $($Args:tt)*]) => {
+        compile_error!("Expected the name of the method and the trait object type, eg. `AsDyn(as_display, dyn Display)`");
+    };
+    ([$($Mode:ident)?], $Vis:vis, $Dependent:tt, [$AutomaticDerive:ident $(($($DeriveArgs:tt)*))?, $($Rest:tt)*]) => {
+        $crate::_as_dyn!([$($Mode)?], $Vis, $Dependent, [$($Rest)*]);
+    };
+    ([$($Mode:ident)?], $Vis:vis, $Dependent:tt, []) => {};
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! _map_dependent {
+    ([], $Vis:vis, $Owner:ty, $Dependent:tt) => {
+        /// Turns the cell into another cell type with the same owner and `joined_layout`.
+        $Vis fn map_dependent<Other>(
+            self,
+            func: impl for<'a> FnOnce(
+                &'a $Owner,
+                $crate::_dependent!($Dependent, 'a)
+            ) -> <Other as $crate::DependentOf<'a>>::Dependent
+        ) -> Other
+        where
+            Other: for<'a> $crate::DependentOf<'a>
+                + $crate::unsafe_self_cell::MapTarget<Owner = $Owner>
+        {
+            // This is only safe to do with repr(transparent).
+            let unsafe_self_cell = unsafe { core::mem::transmute::<
+                Self,
+                $crate::unsafe_self_cell::UnsafeSelfCell<$Owner, $crate::_dependent!($Dependent, 'static)>
+            >(self) };
+
+            // The same HRTB reasoning as in fn new applies to func, it can't
+            // smuggle references in or out.
+            unsafe {
+                Other::from_joined_void_ptr(
+                    unsafe_self_cell.map_dependent($crate::unsafe_self_cell::Storage::Heap, func)
+                )
+            }
+        }
+    };
+    // Cells with a dependent mode don't store the plain dependent in a heap
+    // allocated JoinedCell.
+    ([$Mode:ident], $Vis:vis, $Owner:ty, $Dependent:tt) => {};
+}
+
+// A closure only gets a signature generic over the owner lifetime if it is
+// created where such a bound is expected. These pass the closure through
+// unchanged, so it can be stored in a variable and used later.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! _builders {
+    ([stable_deref], $Vis:vis, $Owner:ty, $Dependent:tt) => {
+        /// Returns `func`, typed as dependent builder for `new`.
+        $Vis fn builder<F>(func: F) -> F
+        where
+            F: for<'a> FnOnce(&'a <$Owner as core::ops::Deref>::Target) -> $crate::_dependent!($Dependent, 'a)
+        {
+            func
+        }
+
+        /// Returns `func`, typed as dependent builder for `try_new`.
+        $Vis fn try_builder<F, Err>(func: F) -> F
+        where
+            F: for<'a> FnOnce(
+                &'a <$Owner as core::ops::Deref>::Target
+            ) -> Result<$crate::_dependent!($Dependent, 'a), Err>
+        {
+            func
+        }
+    };
+    ([owner_mut], $Vis:vis, $Owner:ty, $Dependent:tt) => {
+        /// Returns `func`, typed as dependent builder for `new`.
+        $Vis fn builder<F>(func: F) -> F
+        where
+            F: for<'a> FnOnce(&'a mut $Owner) -> $crate::_dependent!($Dependent, 'a)
+        {
+            func
+        }
+
+        /// Returns `func`, typed as dependent builder for `try_new`.
+        $Vis fn try_builder<F, Err>(func: F) -> F
+        where
+            F: for<'a> FnOnce(&'a mut $Owner) -> Result<$crate::_dependent!($Dependent, 'a), Err>
+        {
+            func
+        }
+    };
+    ([$($Mode:ident)?], $Vis:vis, $Owner:ty, $Dependent:tt) => {
+        /// Returns `func`, typed as dependent builder for `new`.
+        $Vis fn builder<F>(func: F) -> F
+        where
+            F: for<'a> FnOnce(&'a $Owner) -> $crate::_dependent!($Dependent, 'a)
+        {
+            func
+        }
+
+        /// Returns `func`, typed as dependent builder for `try_new`.
+        $Vis fn try_builder<F, Err>(func: F) -> F
+        where
+            F: for<'a> FnOnce(&'a $Owner) -> Result<$crate::_dependent!($Dependent, 'a), Err>
+        {
+            func
+        }
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! _swap {
+    ([owner_mut], $Vis:vis, $Owner:ty, $Dependent:tt) => {
+        // Without access to owner after construction the dependents can't be
+        // rebuilt.
+        $crate::_swap!(@swap $Vis);
+    };
+    ([stable_deref], $Vis:vis, $Owner:ty, $Dependent:tt) => {
+        $crate::_swap!(@swap $Vis);
+        $crate::_swap!(
+            @swap_and_rebuild $Vis, $Dependent,
+            <$Owner as core::ops::Deref>::Target, owner => &**owner
+        );
+    };
+    ([$($Mode:ident)?], $Vis:vis, $Owner:ty, $Dependent:tt) => {
+        $crate::_swap!(@swap $Vis);
+        $crate::_swap!(@swap_and_rebuild $Vis, $Dependent, $Owner, owner => owner);
+    };
+    (
+        @swap_and_rebuild $Vis:vis, $Dependent:tt,
+        $BuilderOwner:ty, $owner:ident => $builder_arg:expr
+    ) => {
+        /// Swaps both cells like `swap`, then replaces the dependent of each
+        /// with one built by `dependent_builder` from the owner it now holds.
+        $Vis fn swap_and_rebuild(
+            &mut self,
+            other: &mut Self,
+            mut dependent_builder: impl for<'a> FnMut(
+                &'a $BuilderOwner
+            ) -> $crate::_dependent!($Dependent, 'a)
+        ) {
+            // Each owner moves together with a dependent that already borrows
+            // it, so both cells stay valid should dependent_builder panic.
+            self.swap(other);
+
+            for cell in [&mut *self, &mut *other] {
+                // The old dependent is only moved out once its replacement is
+                // built, and then passed to the finalizer of Drop($finalizer),
+                // if any. The closure returns nothing, the allow is for the
+                // field attributes of dependent, eg. must_use, which
+                // with_dependent_mut carries too.
+                #[allow(unused_must_use)]
+                cell.with_dependent_mut(|$owner, dependent| {
+                    let new_dependent = dependent_builder($builder_arg);
+                    let old_dependent = core::mem::replace(dependent, new_dependent);
+                    Self::_finalize_dependent($owner, old_dependent);
+                });
+            }
+        }
+    };
+    (@swap $Vis:vis) => {
+        /// Swaps owners and dependents of both cells, without moving either of them.
+        $Vis fn swap(&mut self, other: &mut Self) {
+            // Only the pointers to the JoinedCells are exchanged.
+            core::mem::swap(self, other);
+        }
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! _map_target {
+    ([], $StructName:ident, [$($OwnerLifetime:lifetime)?], $Owner:ty, $Dependent:tt) => {
+        unsafe impl<$($OwnerLifetime)?> $crate::unsafe_self_cell::MapTarget
+            for $StructName<$($OwnerLifetime)?>
+        {
+            type Owner = $Owner;
+
+            unsafe fn from_joined_void_ptr(joined_void_ptr: core::ptr::NonNull<u8>) -> Self {
+                Self {
+                    unsafe_self_cell: $crate::unsafe_self_cell::UnsafeSelfCell::new(
+                        joined_void_ptr
+                    ),
+                }
+            }
+
+            unsafe fn drop_joined_in_place(&mut self) {
+                // Runs the finalizer of Drop($finalizer), if any.
+                self._drop_joined($crate::unsafe_self_cell::Storage::Static);
+            }
+
+            fn into_joined_void_ptr(self) -> core::ptr::NonNull<u8> {
+                // Drop would otherwise free the JoinedCell.
+                let cell = core::mem::ManuallyDrop::new(self);
+                cell.unsafe_self_cell.joined_void_ptr()
+            }
+        }
+    };
+    ([$Mode:ident], $StructName:ident, [$($OwnerLifetime:lifetime)?], $Owner:ty, $Dependent:tt) => {};
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! _owner_access {
+    ([$(#[$Meta:meta])*], [owner_mut], $Vis:vis, $Owner:ty, $Dependent:tt) => {
+        // Dependent borrows owner uniquely, owner is only available again once
+        // dependent is dropped.
+        $crate::_owner_access!(@into_owner [$(#[$Meta])*], [owner_mut], $Vis, $Owner, $Dependent);
+    };
+    ([$(#[$Meta:meta])*], [small_owner], $Vis:vis, $Owner:ty, $Dependent:tt) => {
+        /// Borrows the copy of the owner kept in the cell, without accessing the heap.
+        #[inline]
+        $(#[$Meta])*
+        $Vis fn borrow_owner<'a>(&'a self) -> &'a $Owner {
+            &self.owner_copy
+        }
+
+        /// Pointer to the owner on the heap, which stays the same for the lifetime of the cell, even if the cell is moved.
+        #[inline]
+        $(#[$Meta])*
+        $Vis fn owner_ptr(&self) -> *const $Owner {
+            unsafe { self.unsafe_self_cell.owner_ptr::<$crate::_dependent!($Dependent, '_)>() }
+        }
+
+        /// Drops the dependent and returns the owner.
+        $(#[$Meta])*
+        $Vis fn into_owner(self) -> $Owner {
+            // Drop would otherwise run on the moved out cell, owner_copy is
+            // Copy and needs no drop.
+            let cell = core::mem::ManuallyDrop::new(self);
+            let unsafe_self_cell = unsafe { core::ptr::read(&cell.unsafe_self_cell) };
+
+            unsafe {
+                unsafe_self_cell.into_owner::<$crate::_dependent!($Dependent, '_)>(
+                    $crate::unsafe_self_cell::Storage::Heap
+                )
+            }
+        }
+
+        /// Moves the dependent into `func` and returns owner together with the result.
+        $(#[$Meta])*
+        $Vis fn into_owner_and<Ret>(self, func: impl for<'a> FnOnce($crate::_dependent!($Dependent, 'a)) -> Ret) -> ($Owner, Ret) {
+            // See fn into_owner.
+            let cell = core::mem::ManuallyDrop::new(self);
+            let unsafe_self_cell = unsafe { core::ptr::read(&cell.unsafe_self_cell) };
+
+            // Ret is chosen outside of func and can't name 'a, so it can't
+            // borrow owner, which is moved out once func returns.
+            unsafe {
+                unsafe_self_cell.into_owner_and::<$crate::_dependent!($Dependent, '_), Ret>(
+                    $crate::unsafe_self_cell::Storage::Heap,
+                    func
+                )
+            }
+        }
+    };
+    ([$(#[$Meta:meta])*], [$($Mode:ident)?], $Vis:vis, $Owner:ty, $Dependent:tt) => {
+        /// Borrows the owner.
+        #[inline]
+        $(#[$Meta])*
+        $Vis fn borrow_owner<'a>(&'a self) -> &'a $Owner {
+            unsafe {
+                self.unsafe_self_cell
+                    .borrow_owner::<$crate::_stored_dependent!([$($Mode)?], $crate::_dependent!($Dependent, 'a))>()
+            }
+        }
+
+        /// Pointer to the owner, which stays the same for the lifetime of the cell, even if the cell is moved.
+        #[inline]
+        $(#[$Meta])*
+        $Vis fn owner_ptr(&self) -> *const $Owner {
+            unsafe {
+                self.unsafe_self_cell
+                    .owner_ptr::<$crate::_stored_dependent!([$($Mode)?], $crate::_dependent!($Dependent, '_))>()
+            }
+        }
+
+        $crate::_owner_access!(@into_owner [$(#[$Meta])*], [$($Mode)?], $Vis, $Owner, $Dependent);
+    };
+    (@into_owner [$(#[$Meta:meta])*], [$($Mode:ident)?], $Vis:vis, $Owner:ty, $Dependent:tt) => {
+        /// Drops the dependent and returns the owner.
+        $(#[$Meta])*
+        $Vis fn into_owner(self) -> $Owner {
+            // This is only safe to do with repr(transparent).
+            let unsafe_self_cell = unsafe { core::mem::transmute::<
+                Self,
+                $crate::unsafe_self_cell::UnsafeSelfCell<
+                    $Owner,
+                    $crate::_stored_dependent!([$($Mode)?], $crate::_dependent!($Dependent, 'static))
+                >
+            >(self) };
 
-#![no_std]
+            let owner = unsafe {
+                unsafe_self_cell.into_owner::<$crate::_stored_dependent!([$($Mode)?], $crate::_dependent!($Dependent, '_))>(
+                    $crate::_storage!([$($Mode)?])
+                )
+            };
 
-#[doc(hidden)]
-pub extern crate alloc;
+            owner
+        }
+
+        $crate::_into_owner_and!([$(#[$Meta])*], [$($Mode)?], $Vis, $Owner, $Dependent);
+    };
+}
 
 #[doc(hidden)]
-pub mod unsafe_self_cell;
+#[macro_export]
+macro_rules! _into_owner_and {
+    // The dependent is stored inside a lock, which can't be moved out.
+    ([$(#[$Meta:meta])*], [rw_lock], $Vis:vis, $Owner:ty, $Dependent:tt) => {};
+    ([$(#[$Meta:meta])*], [mutex], $Vis:vis, $Owner:ty, $Dependent:tt) => {};
+    ([$(#[$Meta:meta])*], [$($Mode:ident)?], $Vis:vis, $Owner:ty, $Dependent:tt) => {
+        /// Moves the dependent into `func` and returns owner together with the result.
+        $(#[$Meta])*
+        $Vis fn into_owner_and<Ret>(self, func: impl for<'a> FnOnce($crate::_dependent!($Dependent, 'a)) -> Ret) -> ($Owner, Ret) {
+            // This is only safe to do with repr(transparent).
+            let unsafe_self_cell = unsafe { core::mem::transmute::<
+                Self,
+                $crate::unsafe_self_cell::UnsafeSelfCell<$Owner, $crate::_dependent!($Dependent, 'static)>
+            >(self) };
+
+            // Ret is chosen outside of func and can't name 'a, so it can't
+            // borrow owner, which is moved out once func returns.
+            unsafe {
+                unsafe_self_cell.into_owner_and::<$crate::_dependent!($Dependent, '_), Ret>($crate::_storage!([$($Mode)?]), func)
+            }
+        }
+    };
+}
 
 #[doc(hidden)]
 #[macro_export]
-macro_rules! _covariant_access {
-    (covariant, $Vis:vis, $Dependent:ident) => {
-        $Vis fn borrow_dependent<'a>(&'a self) -> &'a $Dependent<'a> {
-            fn _assert_covariance<'x: 'y, 'y>(x: $Dependent<'x>) -> $Dependent<'y> {
-                //  This function only compiles for covariant types.
-                x // Change the macro invocation to not_covariant.
+macro_rules! _dependent_access {
+    ([$(#[$Meta:meta])*], [], $Covariance:ident, $Vis:vis, $Owner:ty, $Dependent:tt) => {
+        /// Calls `func` with references to owner and dependent.
+        #[inline]
+        $(#[$Meta])*
+        $Vis fn with_dependent<Ret>(&self, func: impl for<'a> FnOnce(&'a $Owner, &'a $crate::_dependent!($Dependent, 'a)) -> Ret) -> Ret {
+            unsafe {
+                func(
+                    self.unsafe_self_cell.borrow_owner::<$crate::_dependent!($Dependent, '_)>(),
+                    self.unsafe_self_cell.borrow_dependent()
+                )
             }
+        }
 
-            unsafe { self.unsafe_self_cell.borrow_dependent() }
+        /// Calls `func` with owner and a mutable reference to the dependent.
+        #[inline]
+        $(#[$Meta])*
+        $Vis fn with_dependent_mut<Ret>(&mut self, func: impl for<'a> FnOnce(&'a $Owner, &'a mut $crate::_dependent!($Dependent, 'a)) -> Ret) -> Ret {
+            let (owner, dependent) = unsafe {
+                    self.unsafe_self_cell.borrow_mut()
+            };
+
+            func(owner, dependent)
         }
+
+        /// Calls `func` with a reference to the dependent that also leads back to owner, see `DependentRef`.
+        #[inline]
+        $(#[$Meta])*
+        $Vis fn with_dependent_ref<Ret>(
+            &self,
+            func: impl for<'a> FnOnce($crate::DependentRef<'a, $Owner, $crate::_dependent!($Dependent, 'a)>) -> Ret
+        ) -> Ret {
+            // The pointer keeps the provenance of the whole JoinedCell, unlike
+            // a reference to the dependent.
+            unsafe { func($crate::DependentRef::new(self.unsafe_self_cell.joined_void_ptr())) }
+        }
+
+        /// Pointer to the dependent, which stays the same for the lifetime of the cell, even if the cell is moved.
+        #[inline]
+        $(#[$Meta])*
+        $Vis fn dependent_ptr<'a>(&'a self) -> *const $crate::_dependent!($Dependent, 'a) {
+            unsafe { self.unsafe_self_cell.dependent_ptr() }
+        }
+
+        $crate::_covariant_access!([$(#[$Meta])*], $Covariance, $Vis, $Dependent);
     };
-    (not_covariant, $Vis:vis, $Dependent:ident) => {
-        // For types that are not covariant it's unsafe to allow
-        // returning direct references.
-        // For example a lifetime that is too short could be chosen:
-        // See https://github.com/Voultapher/self_cell/issues/5
+    ([$(#[$Meta:meta])*], [static_storage], $Covariance:ident, $Vis:vis, $Owner:ty, $Dependent:tt) => {
+        $crate::_dependent_access!([$(#[$Meta])*], [], $Covariance, $Vis, $Owner, $Dependent);
+    };
+    // The dependent borrows the owner on the heap, so with_dependent passes
+    // that one and not the copy.
+    ([$(#[$Meta:meta])*], [small_owner], $Covariance:ident, $Vis:vis, $Owner:ty, $Dependent:tt) => {
+        $crate::_dependent_access!([$(#[$Meta])*], [], $Covariance, $Vis, $Owner, $Dependent);
+    };
+    // Owner stays accessible, the dependent only borrows what it dereferences
+    // to.
+    ([$(#[$Meta:meta])*], [stable_deref], $Covariance:ident, $Vis:vis, $Owner:ty, $Dependent:tt) => {
+        $crate::_dependent_access!([$(#[$Meta])*], [], $Covariance, $Vis, $Owner, $Dependent);
+    };
+    ([$(#[$Meta:meta])*], [owner_mut], $Covariance:ident, $Vis:vis, $Owner:ty, $Dependent:tt) => {
+        // Same as without mode, but without owner.
+        /// Calls `func` with a reference to the dependent.
+        #[inline]
+        $(#[$Meta])*
+        $Vis fn with_dependent<Ret>(&self, func: impl for<'a> FnOnce(&'a $crate::_dependent!($Dependent, 'a)) -> Ret) -> Ret {
+            unsafe { func(self.unsafe_self_cell.borrow_dependent()) }
+        }
+
+        /// Calls `func` with a mutable reference to the dependent.
+        #[inline]
+        $(#[$Meta])*
+        $Vis fn with_dependent_mut<Ret>(&mut self, func: impl for<'a> FnOnce(&'a mut $crate::_dependent!($Dependent, 'a)) -> Ret) -> Ret {
+            unsafe { func(self.unsafe_self_cell.borrow_dependent_mut()) }
+        }
+
+        /// Pointer to the dependent, which stays the same for the lifetime of the cell, even if the cell is moved.
+        #[inline]
+        $(#[$Meta])*
+        $Vis fn dependent_ptr<'a>(&'a self) -> *const $crate::_dependent!($Dependent, 'a) {
+            unsafe { self.unsafe_self_cell.dependent_ptr() }
+        }
+
+        $crate::_covariant_access!([$(#[$Meta])*], $Covariance, $Vis, $Dependent);
+    };
+    ([$(#[$Meta:meta])*], [rw_lock], $Covariance:ident, $Vis:vis, $Owner:ty, $Dependent:tt) => {
+        $crate::_dependent_access!(@lock [$(#[$Meta])*], [rw_lock], $Covariance, $Vis, $Owner, $Dependent);
+    };
+    ([$(#[$Meta:meta])*], [mutex], $Covariance:ident, $Vis:vis, $Owner:ty, $Dependent:tt) => {
+        $crate::_dependent_access!(@lock [$(#[$Meta])*], [mutex], $Covariance, $Vis, $Owner, $Dependent);
+    };
+    ([$(#[$Meta:meta])*], [$Mode:ident], $Covariance:ident, $Vis:vis, $Owner:ty, $Dependent:tt) => {
+        // Unknown mode, see _stored_dependent.
+        $crate::_dependent_access!([$(#[$Meta])*], [], $Covariance, $Vis, $Owner, $Dependent);
+    };
+    (@lock [$(#[$Meta:meta])*], [$Mode:ident], covariant, $Vis:vis, $Owner:ty, $Dependent:tt) => {
+        // A reference to a locked dependent can't outlive the lock guard, so
+        // even covariant dependents get no borrow_dependent.
+        $crate::_dependent_access!(@lock [$(#[$Meta])*], [$Mode], not_covariant, $Vis, $Owner, $Dependent);
+    };
+    (@lock [$(#[$Meta:meta])*], [$Mode:ident], not_covariant, $Vis:vis, $Owner:ty, $Dependent:tt) => {
+        // Takes the read lock for the duration of func.
+        /// Calls `func` with owner and the dependent, holding the read lock.
+        $(#[$Meta])*
+        $Vis fn with_dependent<Ret>(&self, func: impl for<'a> FnOnce(&'a $Owner, &'a $crate::_dependent!($Dependent, 'a)) -> Ret) -> Ret {
+            let (owner, lock) = unsafe {
+                (
+                    self.unsafe_self_cell
+                        .borrow_owner::<$crate::_stored_dependent!([$Mode], $crate::_dependent!($Dependent, '_))>(),
+                    self.unsafe_self_cell
+                        .borrow_dependent::<$crate::_stored_dependent!([$Mode], $crate::_dependent!($Dependent, '_))>(),
+                )
+            };
+
+            let dependent = lock.read();
+
+            // The guard type names the dependent lifetime, so for non
+            // covariant dependents it can't be shortened to the guard borrow.
+            // Going through a pointer is fine, the guard outlives func.
+            let dependent_ptr = &*dependent as *const _ as *const $crate::_dependent!($Dependent, '_);
+            func(owner, unsafe { &*dependent_ptr })
+        }
+
+        // Takes the write lock for the duration of func.
+        /// Calls `func` with owner and the dependent, holding the write lock.
+        $(#[$Meta])*
+        $Vis fn write_dependent<Ret>(&self, func: impl for<'a> FnOnce(&'a $Owner, &'a mut $crate::_dependent!($Dependent, 'a)) -> Ret) -> Ret {
+            let (owner, lock) = unsafe {
+                (
+                    self.unsafe_self_cell
+                        .borrow_owner::<$crate::_stored_dependent!([$Mode], $crate::_dependent!($Dependent, '_))>(),
+                    self.unsafe_self_cell
+                        .borrow_dependent::<$crate::_stored_dependent!([$Mode], $crate::_dependent!($Dependent, '_))>(),
+                )
+            };
+
+            let mut dependent = lock.write();
+
+            // See with_dependent.
+            let dependent_ptr = &mut *dependent as *mut _ as *mut $crate::_dependent!($Dependent, '_);
+            func(owner, unsafe { &mut *dependent_ptr })
+        }
+
+        /// Calls `func` with owner and a mutable reference to the dependent, without locking.
+        $(#[$Meta])*
+        $Vis fn with_dependent_mut<Ret>(&mut self, func: impl for<'a> FnOnce(&'a $Owner, &'a mut $crate::_dependent!($Dependent, 'a)) -> Ret) -> Ret {
+            let (owner, dependent) = unsafe {
+                self.unsafe_self_cell
+                    .borrow_mut::<$crate::_stored_dependent!([$Mode], $crate::_dependent!($Dependent, '_))>()
+            };
+
+            // No locking needed, &mut self guarantees exclusive access.
+            func(owner, dependent.get_mut())
+        }
     };
-    ($x:ident, $Vis:vis, $Dependent:ident) => {
+    (@lock [$(#[$Meta:meta])*], [$Mode:ident], $x:ident, $Vis:vis, $Owner:ty, $Dependent:tt) => {
         compile_error!("This macro only accepts `covariant` or `not_covariant`");
     };
 }
 
+// Separate from the self_cell arm, because the owner lifetime can't be used
+// inside the repetition of the derive list.
+// Looks for Drop($finalizer) in the automatic derives, the cell gets exactly
+// one Drop impl either way.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! _drop_impl {
+    ([Drop($finalizer:expr), $($Rest:tt)*], $Cell:tt) => {
+        $crate::_drop_impl!(@finalizer $finalizer, $Cell);
+    };
+    ([$AutomaticDerive:ident $(($($DeriveArgs:tt)*))?, $($Rest:tt)*], $Cell:tt) => {
+        $crate::_drop_impl!([$($Rest)*], $Cell);
+    };
+    ([], [[$($Mode:ident)?], $StructName:ident, [$($OwnerLifetime:lifetime)?], $Owner:ty, $Dependent:tt]) => {
+        impl $(<$OwnerLifetime>)? $StructName $(<$OwnerLifetime>)? {
+            // Also used by CellArena, which drops cells in place.
+            unsafe fn _drop_joined(&mut self, storage: $crate::unsafe_self_cell::Storage) {
+                self.unsafe_self_cell
+                    .drop_joined::<$crate::_stored_dependent!([$($Mode)?], $crate::_dependent!($Dependent, '_))>(
+                        storage
+                    );
+            }
+
+            // Used by swap_and_rebuild for the replaced dependents, without
+            // finalizer it only drops them. Owner is passed by reference like
+            // to a finalizer, even if it's eg. a Cow.
+            #[allow(clippy::ptr_arg)]
+            fn _finalize_dependent<'a>(_owner: &'a $Owner, _dependent: $crate::_dependent!($Dependent, 'a)) {}
+        }
+
+        $crate::_drop_impl!(@drop [$($Mode)?], $StructName, [$($OwnerLifetime)?]);
+    };
+    (@drop [$($Mode:ident)?], $StructName:ident, [$($OwnerLifetime:lifetime)?]) => {
+        impl $(<$OwnerLifetime>)? Drop for $StructName $(<$OwnerLifetime>)? {
+            fn drop(&mut self) {
+                unsafe {
+                    self._drop_joined($crate::_storage!([$($Mode)?]));
+                }
+            }
+        }
+    };
+    (@finalizer $finalizer:expr, [[owner_mut], $($Cell:tt)*]) => {
+        compile_error!("`Drop($finalizer)` is not available for the `owner_mut` dependent mode, the dependent borrows owner uniquely");
+    };
+    (@finalizer $finalizer:expr, [[rw_lock], $($Cell:tt)*]) => {
+        compile_error!("`Drop($finalizer)` is not available for the `rw_lock` and `mutex` dependent modes");
+    };
+    (@finalizer $finalizer:expr, [[mutex], $($Cell:tt)*]) => {
+        $crate::_drop_impl!(@finalizer $finalizer, [[rw_lock], $($Cell)*]);
+    };
+    (
+        @finalizer $finalizer:expr,
+        [[$($Mode:ident)?], $StructName:ident, [$($OwnerLifetime:lifetime)?], $Owner:ty, $Dependent:tt]
+    ) => {
+        impl $(<$OwnerLifetime>)? $StructName $(<$OwnerLifetime>)? {
+            // Makes the finalizer generic over the owner lifetime, so that it
+            // can't keep anything borrowed from owner or dependent.
+            fn _drop_finalizer<F>(func: F) -> F
+            where
+                F: for<'a> FnOnce(&'a $Owner, $crate::_dependent!($Dependent, 'a))
+            {
+                func
+            }
+
+            unsafe fn _drop_joined(&mut self, storage: $crate::unsafe_self_cell::Storage) {
+                let finalizer = Self::_drop_finalizer($finalizer);
+
+                self.unsafe_self_cell
+                    .drop_joined_with::<$crate::_dependent!($Dependent, '_)>(storage, finalizer);
+            }
+
+            // Used by swap_and_rebuild for the replaced dependents.
+            #[allow(clippy::ptr_arg)]
+            fn _finalize_dependent<'a>(owner: &'a $Owner, dependent: $crate::_dependent!($Dependent, 'a)) {
+                let finalizer = Self::_drop_finalizer($finalizer);
+                finalizer(owner, dependent);
+            }
+        }
+
+        $crate::_drop_impl!(@drop [$($Mode)?], $StructName, [$($OwnerLifetime)?]);
+    };
+}
+
+// Maps the visibilities of a sealed cell one by one, appending them to the
+// already mapped ones, and declares the cell once all are done.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! _sealed {
+    (@vis [$($Default:tt)*] [] $Done:tt $($Rest:tt)*) => {
+        $crate::_sealed!(@push $Done [$($Default)*] $($Rest)*);
+    };
+    (@vis $Default:tt [pub(self)] $Done:tt $($Rest:tt)*) => {
+        $crate::_sealed!(@push $Done [pub(crate)] $($Rest)*);
+    };
+    (@vis $Default:tt [pub(super)] $Done:tt $($Rest:tt)*) => {
+        $crate::_sealed!(@push $Done [pub(in super::super)] $($Rest)*);
+    };
+    (@vis $Default:tt [pub $($VisArgs:tt)?] $Done:tt $($Rest:tt)*) => {
+        $crate::_sealed!(@push $Done [pub $($VisArgs)?] $($Rest)*);
+    };
+    (@push [$($Done:tt)*] $Mapped:tt [$Default:tt $Vis:tt] $($Rest:tt)*) => {
+        $crate::_sealed!(@vis $Default $Vis [$($Done)* $Mapped] $($Rest)*);
+    };
+    (
+        @push [[$($Vis:tt)*] [$($OwnerVis:tt)*]] [$($DependentVis:tt)*]
+        @emit {
+            [$($StructMeta:tt)*], $StructName:ident, [$($OwnerLifetime:tt)?],
+            [$($OwnerMeta:tt)*], $Owner:ty,
+            [$($DependentMeta:tt)*], [$($Dependent:tt)*],
+            [$($AutomaticDerives:tt)*]
+        }
+    ) => {
+        $crate::self_cell!(
+            $($StructMeta)*
+            $($Vis)* struct $StructName $(<$OwnerLifetime>)? {
+                $($OwnerMeta)*
+                $($OwnerVis)* owner: $Owner,
+
+                $($DependentMeta)*
+                $($DependentVis)* dependent: $($Dependent)*,
+            }
+
+            $($AutomaticDerives)*
+        );
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! _self_cell_ext {
+    (
+        [SelfCellExt, $($Rest:tt)*],
+        [[$($Mode:ident)?], $StructName:ident, [$($OwnerLifetime:lifetime)?], $Owner:ty, $Dependent:tt]
+    ) => {
+        unsafe impl $(<$OwnerLifetime>)? $crate::SelfCellExt for $StructName $(<$OwnerLifetime>)? {
+            type Owner = $Owner;
+
+            type DependentStatic = $crate::_stored_dependent!([$($Mode)?], $crate::_dependent!($Dependent, 'static));
+
+            unsafe fn unsafe_self_cell(
+                &self
+            ) -> &$crate::unsafe_self_cell::UnsafeSelfCell<Self::Owner, Self::DependentStatic> {
+                &self.unsafe_self_cell
+            }
+
+            unsafe fn unsafe_self_cell_mut(
+                &mut self
+            ) -> &mut $crate::unsafe_self_cell::UnsafeSelfCell<Self::Owner, Self::DependentStatic> {
+                &mut self.unsafe_self_cell
+            }
+        }
+    };
+    ([$AutomaticDerive:ident $(($($DeriveArgs:tt)*))?, $($Rest:tt)*], $Cell:tt) => {
+        $crate::_self_cell_ext!([$($Rest)*], $Cell);
+    };
+    ([], $Cell:tt) => {};
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! _impl_automatic_derives {
+    ($StructName:ident, $OwnerLifetime:tt, $Owner:ty, $Covariance:ident, []) => {};
+    // Only the plain Debug depends on the covariance.
+    ($StructName:ident, $OwnerLifetime:tt, $Owner:ty, $Covariance:ident, [Debug, $($Rest:tt)*]) => {
+        $crate::_impl_automatic_derive!(@debug $Covariance, $StructName, $OwnerLifetime, $Owner);
+
+        $crate::_impl_automatic_derives!($StructName, $OwnerLifetime, $Owner, $Covariance, [$($Rest)*]);
+    };
+    (
+        $StructName:ident,
+        $OwnerLifetime:tt,
+        $Owner:ty,
+        $Covariance:ident,
+        [$AutomaticDerive:ident $(($($DeriveArgs:tt)*))?, $($Rest:tt)*]
+    ) => {
+        $crate::_impl_automatic_derive!(
+            $AutomaticDerive $(($($DeriveArgs)*))?,
+            $StructName,
+            $OwnerLifetime,
+            $Owner
+        );
+
+        $crate::_impl_automatic_derives!($StructName, $OwnerLifetime, $Owner, $Covariance, [$($Rest)*]);
+    };
+}
+
 #[doc(hidden)]
 #[macro_export]
 macro_rules! _impl_automatic_derive {
-    (Debug, $StructName:ident) => {
-        impl core::fmt::Debug for $StructName {
+    (@debug covariant, $StructName:ident, [$($OwnerLifetime:lifetime)?], $Owner:ty) => {
+        impl<$($OwnerLifetime)?> core::fmt::Debug for $StructName<$($OwnerLifetime)?> {
             fn fmt(&self, fmt: &mut core::fmt::Formatter) -> Result<(), core::fmt::Error> {
                 self.with_dependent(|owner, dependent| {
-                    write!(
-                        fmt,
-                        concat!(
-                            stringify!($StructName),
-                            " {{ owner: {:?}, dependent: {:?} }}"
+                    fmt.debug_struct(stringify!($StructName))
+                        .field("owner", owner)
+                        .field("dependent", dependent)
+                        .finish()
+                })
+            }
+        }
+    };
+    (@debug $Covariance:ident, $StructName:ident, [$($OwnerLifetime:lifetime)?], $Owner:ty) => {
+        impl<$($OwnerLifetime)?> core::fmt::Debug for $StructName<$($OwnerLifetime)?> {
+            fn fmt(&self, fmt: &mut core::fmt::Formatter) -> Result<(), core::fmt::Error> {
+                // Dependents that aren't covariant, eg. holding a Cell, don't
+                // have to implement Debug. Debug($owner_fmt) prints them.
+                fmt.debug_struct(stringify!($StructName))
+                    .field("owner", self.borrow_owner())
+                    .field(
+                        "dependent",
+                        &$crate::unsafe_self_cell::DebugFn(
+                            |fmt: &mut core::fmt::Formatter| fmt.write_str("<non-covariant>")
                         ),
-                        owner, dependent
                     )
+                    .finish()
+            }
+        }
+    };
+    (Debug($owner_fmt:expr), $StructName:ident, [$($OwnerLifetime:lifetime)?], $Owner:ty) => {
+        impl<$($OwnerLifetime)?> core::fmt::Debug for $StructName<$($OwnerLifetime)?> {
+            fn fmt(&self, fmt: &mut core::fmt::Formatter) -> Result<(), core::fmt::Error> {
+                self.with_dependent(|owner, dependent| {
+                    fmt.debug_struct(stringify!($StructName))
+                        .field(
+                            "owner",
+                            &$crate::unsafe_self_cell::DebugFn(
+                                |fmt: &mut core::fmt::Formatter| ($owner_fmt)(owner, fmt)
+                            ),
+                        )
+                        .field("dependent", dependent)
+                        .finish()
                 })
             }
         }
     };
-    (PartialEq, $StructName:ident) => {
-        impl PartialEq for $StructName {
+    (PartialEq, $StructName:ident, [$($OwnerLifetime:lifetime)?], $Owner:ty) => {
+        impl<$($OwnerLifetime)?> PartialEq for $StructName<$($OwnerLifetime)?> {
             fn eq(&self, other: &Self) -> bool {
                 *self.borrow_owner() == *other.borrow_owner()
             }
         }
     };
-    (Eq, $StructName:ident) => {
+    (PartialEq($key:expr), $StructName:ident, [$($OwnerLifetime:lifetime)?], $Owner:ty) => {
+        impl<$($OwnerLifetime)?> PartialEq for $StructName<$($OwnerLifetime)?> {
+            fn eq(&self, other: &Self) -> bool {
+                ($key)(self.borrow_owner()) == ($key)(other.borrow_owner())
+            }
+        }
+    };
+    (Eq, $StructName:ident, [$($OwnerLifetime:lifetime)?], $Owner:ty) => {
         // TODO this should only be allowed if owner is Eq.
-        impl Eq for $StructName {}
+        impl<$($OwnerLifetime)?> Eq for $StructName<$($OwnerLifetime)?> {}
+    };
+    (PartialOrd, $StructName:ident, [$($OwnerLifetime:lifetime)?], $Owner:ty) => {
+        impl<$($OwnerLifetime)?> PartialOrd for $StructName<$($OwnerLifetime)?> {
+            fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
+                self.borrow_owner().partial_cmp(other.borrow_owner())
+            }
+        }
+    };
+    (PartialOrd($key:expr), $StructName:ident, [$($OwnerLifetime:lifetime)?], $Owner:ty) => {
+        impl<$($OwnerLifetime)?> PartialOrd for $StructName<$($OwnerLifetime)?> {
+            fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
+                ($key)(self.borrow_owner()).partial_cmp(&($key)(other.borrow_owner()))
+            }
+        }
     };
-    (Hash, $StructName:ident) => {
-        impl core::hash::Hash for $StructName {
+    (Ord, $StructName:ident, [$($OwnerLifetime:lifetime)?], $Owner:ty) => {
+        impl<$($OwnerLifetime)?> Ord for $StructName<$($OwnerLifetime)?> {
+            fn cmp(&self, other: &Self) -> core::cmp::Ordering {
+                self.borrow_owner().cmp(other.borrow_owner())
+            }
+        }
+    };
+    (Ord($key:expr), $StructName:ident, [$($OwnerLifetime:lifetime)?], $Owner:ty) => {
+        impl<$($OwnerLifetime)?> Ord for $StructName<$($OwnerLifetime)?> {
+            fn cmp(&self, other: &Self) -> core::cmp::Ordering {
+                ($key)(self.borrow_owner()).cmp(&($key)(other.borrow_owner()))
+            }
+        }
+    };
+    (Hash, $StructName:ident, [$($OwnerLifetime:lifetime)?], $Owner:ty) => {
+        impl<$($OwnerLifetime)?> core::hash::Hash for $StructName<$($OwnerLifetime)?> {
             fn hash<H: core::hash::Hasher>(&self, state: &mut H) {
                 self.borrow_owner().hash(state);
             }
         }
     };
-    ($x:ident, $StructName:ident) => {
+    (Hash($key:expr), $StructName:ident, [$($OwnerLifetime:lifetime)?], $Owner:ty) => {
+        impl<$($OwnerLifetime)?> core::hash::Hash for $StructName<$($OwnerLifetime)?> {
+            fn hash<H: core::hash::Hasher>(&self, state: &mut H) {
+                ($key)(self.borrow_owner()).hash(state);
+            }
+        }
+    };
+    (Default($builder:expr), $StructName:ident, [$($OwnerLifetime:lifetime)?], $Owner:ty) => {
+        impl<$($OwnerLifetime)?> Default for $StructName<$($OwnerLifetime)?> {
+            fn default() -> Self {
+                Self::new(<$Owner as Default>::default(), $builder)
+            }
+        }
+    };
+    (From($builder:expr), $StructName:ident, [$($OwnerLifetime:lifetime)?], $Owner:ty) => {
+        impl<$($OwnerLifetime)?> From<$Owner> for $StructName<$($OwnerLifetime)?> {
+            fn from(owner: $Owner) -> Self {
+                Self::new(owner, $builder)
+            }
+        }
+    };
+    (
+        TryFrom($builder:expr, $Err:ty),
+        $StructName:ident,
+        [$($OwnerLifetime:lifetime)?],
+        $Owner:ty
+    ) => {
+        impl<$($OwnerLifetime)?> core::convert::TryFrom<$Owner> for $StructName<$($OwnerLifetime)?> {
+            type Error = $Err;
+
+            fn try_from(owner: $Owner) -> Result<Self, $Err> {
+                Self::try_new(owner, $builder)
+            }
+        }
+    };
+    (Deref $(($($Args:tt)*))?, $StructName:ident, [$($OwnerLifetime:lifetime)?], $Owner:ty) => {
+        // Target would have to name a dependent lifetime, the only one
+        // available is 'static. A &'static str in the dependent could then be
+        // copied out and outlive the cell.
+        compile_error!(
+            "Deref can't be implemented soundly, Target would have to be the dependent with a \
+             'static lifetime, which lets borrows of owner outlive the cell. Use borrow_dependent \
+             or with_dependent instead"
+        );
+    };
+    (DerefMut $(($($Args:tt)*))?, $StructName:ident, [$($OwnerLifetime:lifetime)?], $Owner:ty) => {
+        $crate::_impl_automatic_derive!(Deref, $StructName, [$($OwnerLifetime)?], $Owner);
+    };
+    // Implemented by _drop_impl, which needs the dependent.
+    (Drop($finalizer:expr), $StructName:ident, [$($OwnerLifetime:lifetime)?], $Owner:ty) => {};
+    (SelfCellExt, $StructName:ident, [$($OwnerLifetime:lifetime)?], $Owner:ty) => {};
+    (AsDyn($($AsDynArgs:tt)*), $StructName:ident, [$($OwnerLifetime:lifetime)?], $Owner:ty) => {};
+    (Drop, $StructName:ident, [$($OwnerLifetime:lifetime)?], $Owner:ty) => {
+        compile_error!(
+            "Drop needs a finalizer, eg. `Drop(flush)` with `fn flush<'a>(owner: &'a Owner, dependent: Dependent<'a>)`"
+        );
+    };
+    ($x:ident $(($($Args:tt)*))?, $StructName:ident, [$($OwnerLifetime:lifetime)?], $Owner:ty) => {
         compile_error!(concat!(
             "No automatic trait impl for trait: ",
-            stringify!($x)
+            stringify!($x $(($($Args)*))?)
         ));
     };
 }
@@ -214,7 +2184,8 @@ macro_rules! _impl_automatic_derive {
 ///
 /// ### Example:
 ///
-/// ```rust
+#[cfg_attr(feature = "alloc", doc = "```rust")]
+#[cfg_attr(not(feature = "alloc"), doc = "```ignore")]
 /// use self_cell::self_cell;
 ///
 /// #[derive(Debug, Eq, PartialEq)]
@@ -239,36 +2210,236 @@ macro_rules! _impl_automatic_derive {
 ///
 /// The macro implements these constructors:
 ///
+/// Which of them are available depends on the dependent mode, see `$Mode`
+/// below:
+///
+/// * **Without mode**: All of them.
+/// * **rw_lock** and **mutex**: All but `new_uninit_dependent` and `new_arena`.
+/// * **small_owner** and **stable_deref**: `new`, `try_new_fallible_alloc`,
+///   `new_from_box`, `new_from`, `try_new`, `try_new_or_recover`,
+///   `new_with_ctx`, `try_new_with_ctx`, `try_new_or_recover_with_ctx` and
+///   `new_two_phase`.
+/// * **owner_mut**: `new`, `try_new` and `try_new_or_recover`.
+/// * **static_storage**: `new_in`, `try_new_in` and `try_new_or_recover_in`.
+///
+/// ```ignore
+/// fn new(
+///     owner: $Owner,
+///     dependent_builder: impl for<'a> FnOnce(&'a $Owner) -> $Dependent<'a>
+/// ) -> Self
+/// ```
+///
+/// ```ignore
+/// // Returns an error instead of calling handle_alloc_error, should
+/// // allocating the cell fail. Owner is dropped in that case.
+/// fn try_new_fallible_alloc(
+///     owner: $Owner,
+///     dependent_builder: impl for<'a> FnOnce(&'a $Owner) -> $Dependent<'a>
+/// ) -> Result<Self, AllocError>
+/// ```
+///
+/// ```ignore
+/// // Grows the allocation of the boxed owner to also hold the dependent,
+/// // instead of moving owner into a new one, which matters for large owners.
+/// // Moves owner anyway if the dependent needs a larger alignment than owner.
+/// fn new_from_box(
+///     owner: Box<$Owner>,
+///     dependent_builder: impl for<'a> FnOnce(&'a $Owner) -> $Dependent<'a>
+/// ) -> Self
+/// ```
+///
+/// ```ignore
+/// // Converts owner with Into, so that callers can pass eg. a &str or String
+/// // to a Box<str> owner, see the crate overview on unsized owners.
+/// fn new_from(
+///     owner: impl Into<$Owner>,
+///     dependent_builder: impl for<'a> FnOnce(&'a $Owner) -> $Dependent<'a>
+/// ) -> Self
+/// ```
+///
+/// ```ignore
+/// // The builder can mutate owner, eg. sort it, before freezing it with
+/// // OwnerMut::into_ref. The dependent can only hold shared borrows of owner.
+/// fn new_mut(
+///     owner: $Owner,
+///     dependent_builder: impl for<'a, 'brand> FnOnce(OwnerMut<'a, 'brand, $Owner>) -> $Dependent<'a>
+/// ) -> Self
+/// ```
+///
+/// ```ignore
+/// fn try_new<Err>(
+///     owner: $Owner,
+///     dependent_builder: impl for<'a> FnOnce(&'a $Owner) -> Result<$Dependent<'a>, Err>
+/// ) -> Result<Self, Err>
+/// ```
+///
+/// ```ignore
+/// // The error converts into BuildError<Err, $Owner>, which implements
+/// // std::error::Error with the `std` feature.
+/// fn try_new_or_recover<Err>(
+///     owner: $Owner,
+///     dependent_builder: impl for<'a> FnOnce(&'a $Owner) -> Result<$Dependent<'a>, Err>
+/// ) -> Result<Self, ($Owner, Err)>
+/// ```
+///
+/// ```ignore
+/// // Calls dependent_builder again as long as retry_policy, given the error
+/// // and the number of failed attempts, returns true.
+/// fn try_new_with_retry<Err>(
+///     owner: $Owner,
+///     dependent_builder: impl for<'a> FnMut(&'a $Owner) -> Result<$Dependent<'a>, Err>,
+///     retry_policy: impl FnMut(&Err, usize) -> bool
+/// ) -> Result<Self, ($Owner, Err)>
+/// ```
+///
+/// ```ignore
+/// // Hands ctx, eg. parser options, by value to the builder. This allows named
+/// // fn builders that need more than owner. Like captures of a closure, ctx
+/// // can only put 'static references into the dependent.
+/// fn new_with_ctx<Ctx>(
+///     owner: $Owner,
+///     ctx: Ctx,
+///     dependent_builder: impl for<'a> FnOnce(&'a $Owner, Ctx) -> $Dependent<'a>
+/// ) -> Self
+/// ```
+///
+/// ```ignore
+/// fn try_new_with_ctx<Ctx, Err>(
+///     owner: $Owner,
+///     ctx: Ctx,
+///     dependent_builder: impl for<'a> FnOnce(&'a $Owner, Ctx) -> Result<$Dependent<'a>, Err>
+/// ) -> Result<Self, Err>
+/// ```
+///
+/// ```ignore
+/// fn try_new_or_recover_with_ctx<Ctx, Err>(
+///     owner: $Owner,
+///     ctx: Ctx,
+///     dependent_builder: impl for<'a> FnOnce(&'a $Owner, Ctx) -> Result<$Dependent<'a>, Err>
+/// ) -> Result<Self, ($Owner, Err)>
+/// ```
+///
+/// ```ignore
+/// // Intermediate data that only the dependent builder needs, eg. an index
+/// // of owner. Scratch can't borrow owner, it's moved into dependent_builder
+/// // and dropped once the dependent is built, unless moved into it.
+/// fn new_two_phase<Scratch>(
+///     owner: $Owner,
+///     scratch_builder: impl for<'a> FnOnce(&'a $Owner) -> Scratch,
+///     dependent_builder: impl for<'a> FnOnce(&'a $Owner, Scratch) -> $Dependent<'a>
+/// ) -> Self
+/// ```
+///
+/// ```ignore
+/// // Only available without dependent mode. Moves owner into the final
+/// // allocation without building the dependent. PartialCell::owner_mut allows
+/// // mutating owner, eg. while streaming data into it, until
+/// // PartialCell::build or try_build turns it into the cell.
+/// fn new_uninit_dependent(owner: $Owner) -> PartialCell<Self>
+/// ```
+///
+/// ```ignore
+/// // Only available without dependent mode. Builds many cells at once with
+/// // one allocation for all their owners and dependents, and one for the
+/// // cells, instead of one allocation per cell. They can only be borrowed
+/// // from the CellArena, which derefs to [Self], and are dropped together
+/// // with it. A single cell can't outlive the arena, use new in a loop for
+/// // cells that are moved out or dropped on their own.
+/// fn new_arena(
+///     owners: Vec<$Owner>,
+///     dependent_builder: impl for<'a> FnMut(&'a $Owner) -> $Dependent<'a>
+/// ) -> CellArena<Self>
+/// ```
+///
 /// ```ignore
-/// fn new(
+/// // Only available with the `async` feature. Same as
+/// // try_new_or_recover_with_ctx, with a builder returning a boxed future.
+/// async fn try_new_with_ctx_async<Ctx, Err>(
 ///     owner: $Owner,
-///     dependent_builder: impl for<'a> FnOnce(&'a $Owner) -> $Dependent<'a>
-/// ) -> Self
+///     ctx: Ctx,
+///     dependent_builder: impl for<'a> FnOnce(
+///         &'a $Owner,
+///         Ctx
+///     ) -> Pin<Box<dyn Future<Output = Result<$Dependent<'a>, Err>> + Send + 'a>>
+/// ) -> Result<Self, ($Owner, Err)>
 /// ```
 ///
 /// ```ignore
-/// fn try_new<Err>(
+/// // Only available with the `async` feature. Same as try_new_with_retry,
+/// // with a builder returning a boxed future. The builder future borrows
+/// // owner and has to be Send, so $Owner has to be Sync. The returned future
+/// // is Send if $Owner, $Dependent, Err and both closures are.
+/// async fn try_new_with_retry_async<Err>(
 ///     owner: $Owner,
-///     dependent_builder: impl for<'a> FnOnce(&'a $Owner) -> Result<$Dependent<'a>, Err>
-/// ) -> Result<Self, Err>
+///     dependent_builder: impl for<'a> FnMut(
+///         &'a $Owner
+///     ) -> Pin<Box<dyn Future<Output = Result<$Dependent<'a>, Err>> + Send + 'a>>,
+///     retry_policy: impl FnMut(&Err, usize) -> bool
+/// ) -> Result<Self, ($Owner, Err)>
 /// ```
 ///
 /// ```ignore
-/// fn try_new_or_recover<Err>(
+/// // Only available with the `async` feature. Same as the two above, but the
+/// // builder future doesn't have to be Send, eg. for Rc or RefCell owners and
+/// // single threaded executors. The returned futures are never Send.
+/// async fn try_new_with_ctx_async_local<Ctx, Err>(
 ///     owner: $Owner,
-///     dependent_builder: impl for<'a> FnOnce(&'a $Owner) -> Result<$Dependent<'a>, Err>
+///     ctx: Ctx,
+///     dependent_builder: impl for<'a> FnOnce(
+///         &'a $Owner,
+///         Ctx
+///     ) -> Pin<Box<dyn Future<Output = Result<$Dependent<'a>, Err>> + 'a>>
+/// ) -> Result<Self, ($Owner, Err)>
+///
+/// async fn try_new_with_retry_async_local<Err>(
+///     owner: $Owner,
+///     dependent_builder: impl for<'a> FnMut(
+///         &'a $Owner
+///     ) -> Pin<Box<dyn Future<Output = Result<$Dependent<'a>, Err>> + 'a>>,
+///     retry_policy: impl FnMut(&Err, usize) -> bool
 /// ) -> Result<Self, ($Owner, Err)>
 /// ```
 ///
 /// The macro implements these methods:
 ///
 /// ```ignore
+/// // Returns func unchanged. The closure passed to a constructor is only
+/// // generic over the owner lifetime if it is written inside the call, this
+/// // allows storing it in a variable first, eg. to reuse it:
+/// // `let build = AstCell::builder(|owner| parse(owner));`. The owner_mut
+/// // mode takes &'a mut $Owner.
+/// fn builder<F>(func: F) -> F
+/// where
+///     F: for<'a> FnOnce(&'a $Owner) -> $Dependent<'a>
+/// ```
+///
+/// ```ignore
+/// // Same as builder, for try_new and the other fallible constructors.
+/// fn try_builder<F, Err>(func: F) -> F
+/// where
+///     F: for<'a> FnOnce(&'a $Owner) -> Result<$Dependent<'a>, Err>
+/// ```
+///
+/// ```ignore
 /// fn borrow_owner<'a>(&'a self) -> &'a $Owner
 /// ```
 ///
 /// ```ignore
-/// // Only available if dependent is covariant.
-/// fn borrow_dependent<'a>(&'a self) -> &'a $Dependent<'a>
+/// // Not available for the owner_mut dependent mode. See Address stability.
+/// fn owner_ptr(&self) -> *const $Owner
+/// ```
+///
+/// ```ignore
+/// // Not available for the rw_lock and mutex dependent modes. See Address
+/// // stability.
+/// fn dependent_ptr<'a>(&'a self) -> *const $Dependent<'a>
+/// ```
+///
+/// ```ignore
+/// // Only available if dependent is covariant. BorrowedDependent<'a, Self>
+/// // is $Dependent<'a>, the alias allows naming the return type outside of
+/// // the module declaring a private dependent.
+/// fn borrow_dependent<'a>(&'a self) -> &'a BorrowedDependent<'a, Self>
 /// ```
 ///
 /// ```ignore
@@ -279,6 +2450,19 @@ macro_rules! _impl_automatic_derive {
 /// ```
 ///
 /// ```ignore
+/// // Not available for the owner_mut, rw_lock and mutex dependent modes. The
+/// // DependentRef derefs to the dependent and DependentRef::owner returns
+/// // owner, so code only given the dependent can still reach owner.
+/// fn with_dependent_ref<Ret>(
+///     &self,
+///     func: impl for<'a> FnOnce(DependentRef<'a, $Owner, $Dependent<'a>>) -> Ret
+/// ) -> Ret
+/// ```
+///
+/// ```ignore
+/// // func is generic over 'a, which it can't name outside of its body. So
+/// // the dependent can only be changed to borrow owner, and neither of them
+/// // can leave func, eg. be swapped with the dependent of another cell.
 /// fn with_dependent_mut<Ret>(
 ///     &mut self,
 ///     func: impl for<'a> FnOnce(&'a $Owner, &'a mut $Dependent<'a>) -> Ret
@@ -289,6 +2473,127 @@ macro_rules! _impl_automatic_derive {
 /// fn into_owner(self) -> $Owner
 /// ```
 ///
+/// ```ignore
+/// // Not available for the rw_lock and mutex dependent modes. Drops nothing,
+/// // func gets the dependent by value and can return its owned parts, eg.
+/// // statistics gathered while parsing. Ret can't borrow owner.
+/// fn into_owner_and<Ret>(
+///     self,
+///     func: impl for<'a> FnOnce($Dependent<'a>) -> Ret
+/// ) -> ($Owner, Ret)
+/// ```
+///
+/// ```ignore
+/// // Only available without dependent mode. Turns the cell into another cell
+/// // type with the same owner, eg. to lower a raw AST into a typed one.
+/// // Owner stays where it is, so Self::joined_layout() and
+/// // Other::joined_layout() have to be the same, see below.
+/// fn map_dependent<Other>(
+///     self,
+///     func: impl for<'a> FnOnce(&'a $Owner, $Dependent<'a>)
+///         -> <Other as DependentOf<'a>>::Dependent
+/// ) -> Other
+/// ```
+///
+/// `map_dependent` reuses the allocation, owner can't be moved into a new one
+/// while the old dependent and the one built from it borrow owner in place.
+/// Dependents of different size are fine as long as the padding of the
+/// `JoinedCell` evens them out, otherwise building fails. The check runs when
+/// the call is compiled to machine code, eg. by `cargo build`, `cargo check`
+/// doesn't report it. Boxing both dependents gives them the same layout:
+///
+/// ```compile_fail
+/// use self_cell::self_cell;
+///
+/// type Words<'a> = Vec<&'a str>;
+/// type FirstWord<'a> = &'a str;
+///
+/// self_cell!(
+///     struct WordsCell {
+///         owner: String,
+///
+///         #[covariant]
+///         dependent: Words,
+///     }
+/// );
+///
+/// self_cell!(
+///     struct FirstWordCell {
+///         owner: String,
+///
+///         #[covariant]
+///         dependent: FirstWord,
+///     }
+/// );
+///
+/// let cell = WordsCell::new("fox cat".into(), |text| text.split(' ').collect());
+/// // Vec<&str> and &str result in different layouts.
+/// let _cell: FirstWordCell = cell.map_dependent(|_, words| words[0]);
+/// ```
+///
+/// ```ignore
+/// // O(1), exchanges the pointers to owner and dependent. Same as
+/// // core::mem::swap on the cells.
+/// fn swap(&mut self, other: &mut Self)
+/// ```
+///
+/// ```ignore
+/// // Not available for the owner_mut dependent mode. Swaps the cells like swap,
+/// // then replaces the dependent of each with a new one built by
+/// // dependent_builder from the owner the cell now holds. Neither owner moves.
+/// // Each old dependent is passed to the Drop($finalizer), if any, once its
+/// // replacement is built. Should dependent_builder panic, the cells stay
+/// // swapped and keep the dependents not yet rebuilt. With stable_deref the
+/// // builder gets &'a <$Owner as Deref>::Target.
+/// fn swap_and_rebuild(
+///     &mut self,
+///     other: &mut Self,
+///     dependent_builder: impl for<'a> FnMut(&'a $Owner) -> $Dependent<'a>
+/// )
+/// ```
+///
+/// ```ignore
+/// // Size and alignment of the allocation, or static storage, holding owner
+/// // and dependent. Usable in const contexts.
+/// const fn joined_layout() -> core::alloc::Layout
+/// ```
+///
+/// ### Memory layout:
+///
+/// The cell itself only stores a non-null pointer to owner and dependent, so
+/// `Option<$StructName>` is guaranteed to be the same size as `$StructName`.
+/// Both are pointer-sized, unless the `shadow_state` feature is enabled or the
+/// code runs under miri, which adds a flag to every cell. In debug builds the
+/// `debug-assertions` feature adds the same flag, and a canary after the
+/// dependent, so even zero sized owner and dependent pairs allocate.
+///
+/// Nothing assumes 64-bit pointers or a byte order, the layout is computed
+/// with `core::alloc::Layout` for the target. The tests run on 32 and 64-bit,
+/// little and big-endian targets, and the layout invariants are checked at
+/// compile time for every target, including wasm32 and 16-bit ones like AVR.
+///
+/// ### Address stability:
+///
+/// Owner and dependent never move while the cell exists, moving or swapping
+/// the cell only moves the pointer to them. The pointers returned by
+/// `owner_ptr` and `dependent_ptr` are guaranteed to stay the same and valid
+/// for reads from the construction of the cell until it is dropped or
+/// consumed, eg. by `into_owner`, so they can be registered with C callbacks.
+/// For the `small_owner` mode `owner_ptr` points to the owner on the heap, not
+/// the copy `borrow_owner` returns. Writing through these pointers, or reading
+/// the dependent while `with_dependent_mut` runs, is not allowed. Zero sized
+/// owners and dependents have no distinct address.
+///
+/// ### Panic safety:
+///
+/// The dependent is only written into the cell once `dependent_builder`
+/// returned. Should the builder panic, everything it created so far is dropped
+/// while unwinding out of it, like for any other function. The constructor
+/// then drops owner and frees the cell memory before the panic continues, a
+/// partially built dependent is never seen or dropped by the cell. The same
+/// holds for the retry constructors and `map_dependent`. `catch_unwind` is not
+/// needed for cleanup, use it only if you want to recover from the panic, see
+/// the `catch_panic_in_from` test.
 ///
 /// ### Parameters:
 ///
@@ -298,16 +2603,54 @@ macro_rules! _impl_automatic_derive {
 ///   and all functions implemented by the macro as public.
 ///
 ///   `$(#[$StructMeta:meta])*` allows you specify further meta items for this
-///   struct, eg. `#[doc(hidden)] struct AstCell`.
+///   struct, eg. `#[doc(hidden)] struct AstCell`. They are forwarded as is,
+///   doc comments included. The struct is always `#[repr(transparent)]`, other
+///   `repr` attributes are rejected by the compiler. `#[derive(...)]` can't see
+///   through the private field, use `impl {...}` described below instead. To
+///   `#[cfg(...)]` out the whole cell, put the attribute on the `self_cell!`
+///   invocation.
+///
+///   `#[sealed(module_name)]` as first attribute declares the struct in a
+///   hidden module of that name and re-exports it with `$Vis`, so its
+///   internals are private to the macro. Impls in the declaring module can
+///   then only use the generated API, unless the cell lists `SelfCellExt`
+///   with the automatic traits. The module imports `super::*`, so owner and
+///   dependent have to be declared at module level, not inside a function.
+///   `pub(in path)` visibilities need a `crate::` path.
+///
+///   The `owner` and `dependent` fields can be given their own visibility, eg.
+///   `pub(crate) owner: String`. It is used instead of `$Vis` for the functions
+///   accessing that field, `borrow_owner` and `into_owner` for `owner`, the
+///   `with_dependent*` and `borrow_dependent` functions for `dependent`. The
+///   constructors always use `$Vis`. Use `pub(self)` to make them private to
+///   the module of a `pub` struct.
+///
+///   Doc comments and attributes on the `owner` and `dependent` fields are
+///   applied to the same functions, after their default documentation, eg.
+///   `/// The source text.` or `#[deprecated]`. The ones of `dependent` go
+///   after the covariance attribute. The borrow functions are already
+///   `#[inline]`.
 ///
-/// - `$Owner:ty` Type of owner. This has to have a `'static` lifetime. Example:
-///   `String`.
+/// - `$Owner:ty` Type of owner. This has to have a `'static` lifetime, unless
+///   the struct is declared with a lifetime. Example: `String`.
+///
+///   `struct $StructName:ident<$OwnerLifetime:lifetime>` makes the struct
+///   generic over a lifetime, which the owner may use, eg. `struct
+///   FieldsView<'o> { owner: &'o [u8], ... }`. This allows self-referential
+///   views over borrowed data, the dependent can then only be accessed as long
+///   as `'o` lives. The lifetime can't be named `'a`, `'b`, `'x`, `'y` or
+///   `'_brand`, the generated functions use those.
 ///
 /// - `$Dependent:ident` Name of the dependent type without specified lifetime.
 ///   This can't be a nested type name. As workaround either create a type alias
 ///   `type Dep<'a> = Option<Vec<&'a str>>;` or create a new-type `struct
 ///   Dep<'a>(Option<Vec<&'a str>>);`. Example: `Ast`.
 ///
+///   Dependent types with more than one lifetime parameter list them, with
+///   `'_` in the positions that borrow owner, eg. `Parsed<'_, 'static>`. The
+///   other positions have to be `'static`. Only lifetimes are allowed in the
+///   list.
+///
 ///   `$Covariance:ident` Marker declaring if `$Dependent` is
 ///   [covariant](https://doc.rust-lang.org/nightly/nomicon/subtyping.html).
 ///   Possible Values:
@@ -330,12 +2673,82 @@ macro_rules! _impl_automatic_derive {
 ///   dependent value. This is safe to do because notionally you are replacing
 ///   pointers to a value not the other way around.
 ///
-/// - `impl {$($AutomaticDerive:ident),*},` Optional comma separated list of
+///   `$Mode:ident` Optional marker after the covariance, eg. `#[not_covariant,
+///   rw_lock]`, that changes how the dependent is stored. Possible Values:
+///
+///   * **rw_lock**: The dependent is wrapped in a library managed `RwLock`.
+///     `with_dependent` takes the read lock and the additional `fn
+///     write_dependent<Ret>(&self, func: impl for<'a> FnOnce(&'a $Owner, &'a
+///     mut $Dependent<'a>) -> Ret) -> Ret` takes the write lock, which allows
+///     mutating the dependent from `&self` across threads. `with_dependent_mut`
+///     needs no lock. `borrow_dependent` is not available, regardless of
+///     covariance.
+///
+///   * **mutex**: Same as `rw_lock`, but with a `Mutex`, both
+///     `with_dependent` and `write_dependent` take the exclusive lock. This
+///     makes the cell `Sync` for dependents that are only `Send`.
+///
+///   Both require the `std` or `parking_lot` feature of this crate, if both
+///   are enabled parking_lot is used. Lock poisoning is ignored.
+///
+///   * **static_storage**: The cell does not allocate, instead the caller
+///     provides the storage. This is the only mode available without the
+///     default `alloc` feature, eg. for firmware that can't link an allocator.
+///     The constructors get an additional first parameter and an `_in`
+///     suffix, eg. `fn new_in(storage: &'static mut
+///     JoinedStorage<$Owner, $Dependent<'static>>, owner: $Owner,
+///     dependent_builder: ...) -> Self`, `try_new_in` and
+///     `try_new_or_recover_in`. Dropping the cell drops owner and dependent,
+///     the storage is never reused.
+///
+///   * **owner_mut**: The dependent borrows owner uniquely, eg. `&'a mut
+///     Node<'a>` graphs allocated from an arena owner. The constructors pass
+///     `&'a mut $Owner` to the builder, eg. `fn new(owner: $Owner,
+///     dependent_builder: impl for<'a> FnOnce(&'a mut $Owner) -> $Dependent<'a>)
+///     -> Self`. There is no `borrow_owner`, and `with_dependent` and
+///     `with_dependent_mut` only pass the dependent to `func`, any reference to
+///     owner would alias the ones in dependent. `into_owner` is available, it
+///     drops dependent first. Automatic trait impls that access owner, like
+///     Debug or PartialEq, are not available. Dependents holding `&'a mut`
+///     references to data with lifetime `'a` are usually invariant and have to
+///     be marked `not_covariant`.
+///
+///   * **small_owner**: For small `Copy` owners, eg. `[u8; 32]` or a small
+///     config struct, read much more often than the dependent. The cell keeps
+///     a copy of owner next to the pointer to the heap allocation, and
+///     `borrow_owner` returns a reference to that copy instead of the heap.
+///     Owner is never mutated, so both are always equal, the dependent still
+///     borrows the one on the heap. The cell is larger by the size of owner.
+///     `new_mut`, `try_new_with_retry` and the async constructors are not
+///     available, see the list above. `into_owner` and `into_owner_and` are.
+///     Owners that aren't `Copy` are rejected at compile time.
+///
+///   * **stable_deref**: For owners implementing `StableDeref`, eg. `String`,
+///     `Vec<u8>`, `Box<T>` or `Arc<[u8]>`. The dependent borrows the data owner
+///     dereferences to, not the owner value itself, as with other owning
+///     reference types. The constructors pass `&'a <$Owner as Deref>::Target`
+///     to the builder, eg. `fn new(owner: Arc<[u8]>, dependent_builder: impl
+///     for<'a> FnOnce(&'a [u8]) -> $Dependent<'a>) -> Self`. Only the handle,
+///     eg. the pointer and length of the `Arc`, is stored next to the
+///     dependent, the data stays in its own allocation. `new_mut`,
+///     `try_new_with_retry` and the async constructors are not available, see
+///     the list above. `borrow_owner` and `with_dependent` still give access to
+///     owner. Requires the `stable_deref_trait` feature of this crate.
+///
+/// - `impl {$($AutomaticDerive:ident $(($key:expr))?),*},` Optional comma separated list of
 ///   optional automatic trait implementations. Possible Values:
 ///
 ///   * **Debug**: Prints the debug representation of owner and dependent.
 ///     Example: `AstCell { owner: "fox = cat + dog", dependent: Ast(["fox",
-///     "cat", "dog"]) }`
+///     "cat", "dog"]) }`. For `not_covariant` dependents the placeholder
+///     `<non-covariant>` is printed instead, they don't have to implement
+///     Debug. Supports the alternate `{:#?}` format.
+///
+///   * **Debug($owner_fmt)**: Prints the dependent regardless of covariance,
+///     and owner with `$owner_fmt(owner, fmt)`, eg. to summarize a huge owner.
+///     `$owner_fmt` is a function `fn(&$Owner, &mut core::fmt::Formatter) ->
+///     core::fmt::Result`, eg. `|owner: &String, fmt: &mut Formatter|
+///     write!(fmt, "{} bytes", owner.len())`.
 ///
 ///   * **PartialEq**: Logic `*self.borrow_owner() == *other.borrow_owner()`,
 ///     this assumes that `Dependent<'a>::From<&'a Owner>` is deterministic, so
@@ -349,239 +2762,362 @@ macro_rules! _impl_automatic_derive {
 ///     `Dependent<'a>::From<&'a Owner>` is deterministic, so that only hashing
 ///     owner is enough.
 ///
+///   * **PartialEq($key)** and **Hash($key)**: Same as above, but compare or
+///     hash `$key(self.borrow_owner())` instead of the whole owner. `$key` is a
+///     function taking `&$Owner`, eg. `Hash(source_path)` with `fn
+///     source_path(source: &Source) -> &str`. Closures work too, but can't
+///     return a borrow of owner. Useful for large owners that are identified by
+///     a small part of them. Use the same `$key` for both, so that equal cells
+///     have equal hashes.
+///
+///   * **PartialOrd** and **Ord**: Logic
+///     `self.borrow_owner().cmp(other.borrow_owner())`, with the same
+///     assumption as PartialEq. Ord requires Eq, declare all of `PartialEq, Eq,
+///     PartialOrd, Ord` to use the cell as `BTreeMap` key.
+///
+///   * **PartialOrd($key)** and **Ord($key)**: Compare `$key(self.borrow_owner())`
+///     like PartialEq($key). Use the same `$key` as for PartialEq, so that the
+///     ordering agrees with equality.
+///
+///   * **Default($builder)**: Logic `Self::new(Default::default(), $builder)`,
+///     requires `$Owner: Default`. `$builder` is the canonical dependent
+///     builder, eg. a function `fn(&'a $Owner) -> $Dependent<'a>` or a closure
+///     like `Default(|owner| owner.into())`.
+///
+///   * **From($builder)**: Implements `From<$Owner>` as `Self::new(owner,
+///     $builder)`, with the same kind of `$builder` as Default.
+///
+///   * **TryFrom($builder, $Err)**: Implements `TryFrom<$Owner>` with `type
+///     Error = $Err` as `Self::try_new(owner, $builder)`. `$builder` returns
+///     `Result<$Dependent<'a>, $Err>`. Owner is dropped on error, use
+///     `try_new_or_recover` directly to get it back. Can't be combined with
+///     From, which already implies TryFrom.
+///
+///   * **Drop($finalizer)**: Calls `$finalizer(owner, dependent)` with a
+///     reference to owner and the dependent by value when the cell is
+///     dropped, before owner is dropped, eg. to flush a write-ahead log kept
+///     in the dependent into a file handle in owner. `$finalizer` is a
+///     function `fn(&'a $Owner, $Dependent<'a>)` for any `'a`, or a closure
+///     like `Drop(|owner, log| log.flush_into(owner))`. `into_owner` and
+///     `into_owner_and` don't call it. Not available for the `rw_lock`,
+///     `mutex` and `owner_mut` dependent modes. Should the finalizer panic,
+///     owner is still dropped and the memory freed.
+///
+///   * **AsDyn($name, $DynType)**: Adds `fn $name(&self) -> &$DynType`,
+///     borrowing the dependent as trait object, eg. `AsDyn(as_parser, dyn
+///     Parser + Send)`. Allows hiding the dependent behind a trait in a public
+///     API. Also available for `not_covariant` dependents, the dependent has to
+///     coerce to `$DynType` for any lifetime, so the trait object can't expose
+///     it. `$DynType` and the dependent can't name the lifetime of a borrowed
+///     owner. Can be listed multiple times, not available for the `rw_lock`
+///     and `mutex` dependent modes.
+///
+///   * **SelfCellExt**: Implements [`SelfCellExt`], which gives unsafe access to
+///     the internals, also of `#[sealed]` cells. Code using it takes on the
+///     proof obligations documented on its methods.
+///
+///   Deref to the dependent is deliberately not supported. Its `Target` can't
+///   name the lifetime of the borrow, it would have to be `$Dependent<'static>`,
+///   and references copied out of it could outlive the cell.
+///
 ///   All `AutomaticDerive` are optional and you can implement you own version
 ///   of these traits. The declared struct is part of your module and you are
 ///   free to implement any trait in any way you want. Access to the unsafe
 ///   internals is only possible via unsafe functions, so you can't accidentally
-///   use them in safe code.
+///   use them in safe code. `#[sealed(module_name)]` takes them out of reach of
+///   your impls altogether.
 ///
 #[macro_export]
 macro_rules! self_cell {
+// The cell is declared in a hidden module, where its field is private to the
+// macro, and re-exported from there. Everything in the module is reachable
+// only through the re-export, so private visibilities become pub(crate) and
+// pub(super) has to skip the hidden module.
 (
+    #[sealed($Module:ident)]
     $(#[$StructMeta:meta])*
-    $Vis:vis struct $StructName:ident {
-        owner: $Owner:ty,
+    $(pub $(($($VisArgs:tt)*))?)? struct $StructName:ident $(<$OwnerLifetime:lifetime>)? {
+        $(#[$OwnerMeta:meta])*
+        $(pub $(($($OwnerVisArgs:tt)*))?)? owner: $Owner:ty,
 
-        #[$Covariance:ident]
-        dependent: $Dependent:ident,
+        #[$Covariance:ident $(, $Mode:ident)?]
+        $(#[$DependentMeta:meta])*
+        $(pub $(($($DependentVisArgs:tt)*))?)? dependent: $Dependent:ident $(<$($DependentLifetime:lifetime),+>)? $(,)?
     }
 
-    $(impl {$($AutomaticDerive:ident),*})?
+    $(impl {$($AutomaticDerives:tt)*})?
 ) => {
-    #[repr(transparent)]
-    $(#[$StructMeta])*
-    $Vis struct $StructName {
-        unsafe_self_cell: $crate::unsafe_self_cell::UnsafeSelfCell<
-            $Owner,
-            $Dependent<'static>
-        >
-    }
-
-    impl $StructName {
-        $Vis fn new(
-            owner: $Owner,
-            dependent_builder: impl for<'a> FnOnce(&'a $Owner) -> $Dependent<'a>
-        ) -> Self {
-            use core::ptr::NonNull;
-
-            unsafe {
-                // All this has to happen here, because there is not good way
-                // of passing the appropriate logic into UnsafeSelfCell::new
-                // short of assuming Dependent<'static> is the same as
-                // Dependent<'a>, which I'm not confident is safe.
-
-                // For this API to be safe there has to be no safe way to
-                // capture additional references in `dependent_builder` and then
-                // return them as part of Dependent. Eg. it should be impossible
-                // to express: 'a should outlive 'x here `fn
-                // bad<'a>(outside_ref: &'a String) -> impl for<'x> FnOnce(&'x
-                // Owner) -> Dependent<'x>`.
-
-                type JoinedCell<'a> = $crate::unsafe_self_cell::JoinedCell<$Owner, $Dependent<'a>>;
-
-                let layout = $crate::alloc::alloc::Layout::new::<JoinedCell>();
-                assert!(layout.size() != 0);
-
-                let joined_void_ptr = NonNull::new($crate::alloc::alloc::alloc(layout)).unwrap();
+    #[doc(hidden)]
+    mod $Module {
+        #[allow(unused_imports)]
+        use super::*;
 
-                let mut joined_ptr = core::mem::transmute::<NonNull<u8>, NonNull<JoinedCell>>(
-                    joined_void_ptr
-                );
-
-                let owner_ptr: *mut $Owner = &mut (*joined_ptr.as_ptr()).owner;
-                let dependent_ptr: *mut $Dependent = &mut (*joined_ptr.as_ptr()).dependent;
-
-                // Move owner into newly allocated space.
-                owner_ptr.write(owner);
-
-                // Drop guard that cleans up should building the dependent panic.
-                let mut drop_guard =
-                    $crate::unsafe_self_cell::OwnerAndCellDropGuard::new(joined_ptr);
-
-                // Initialize dependent with owner reference in final place.
-                dependent_ptr.write(dependent_builder(&*owner_ptr));
-                drop_guard.mark_fully_init();
-
-                Self {
-                    unsafe_self_cell: $crate::unsafe_self_cell::UnsafeSelfCell::new(
-                        joined_void_ptr,
-                    ),
-                }
+        $crate::_sealed!(
+            @vis [pub(crate)] [$(pub $(($($VisArgs)*))?)?] []
+            [[] [$(pub $(($($OwnerVisArgs)*))?)?]]
+            [[] [$(pub $(($($DependentVisArgs)*))?)?]]
+            @emit {
+                [$(#[$StructMeta])*], $StructName, [$($OwnerLifetime)?],
+                [$(#[$OwnerMeta])*], $Owner,
+                [#[$Covariance $(, $Mode)?] $(#[$DependentMeta])*], [$Dependent $(<$($DependentLifetime),+>)?],
+                [$(impl {$($AutomaticDerives)*})?]
             }
-        }
-
-        $Vis fn try_new<Err>(
-            owner: $Owner,
-            dependent_builder: impl for<'a> FnOnce(&'a $Owner) -> Result<$Dependent<'a>, Err>
-        ) -> Result<Self, Err> {
-            use core::ptr::NonNull;
-
-            unsafe {
-                // See fn new for more explanation.
-
-                type JoinedCell<'a> = $crate::unsafe_self_cell::JoinedCell<$Owner, $Dependent<'a>>;
-
-                let layout = $crate::alloc::alloc::Layout::new::<JoinedCell>();
-                assert!(layout.size() != 0);
-
-                let joined_void_ptr = NonNull::new($crate::alloc::alloc::alloc(layout)).unwrap();
-
-                let mut joined_ptr = core::mem::transmute::<NonNull<u8>, NonNull<JoinedCell>>(
-                    joined_void_ptr
-                );
+        );
+    }
 
-                let owner_ptr: *mut $Owner = &mut (*joined_ptr.as_ptr()).owner;
-                let dependent_ptr: *mut $Dependent = &mut (*joined_ptr.as_ptr()).dependent;
+    $(pub $(($($VisArgs)*))?)? use $Module::$StructName;
+};
+(
+    #[sealed $($SealedArgs:tt)*]
+    $($Rest:tt)*
+) => {
+    compile_error!(
+        "Expected `#[sealed(module_name)]` as the first attribute of a cell declaration"
+    );
 
-                // Move owner into newly allocated space.
-                owner_ptr.write(owner);
+    $crate::self_cell!($($Rest)*);
+};
+// A borrowed owner has to be 'static, any other lifetime would be undeclared in
+// the generated code and produce a flood of errors. 'static references are
+// wrapped in parentheses, which stops them from matching these arms again.
+(
+    $(#[$StructMeta:meta])*
+    $Vis:vis struct $StructName:ident {
+        $(#[$OwnerMeta:meta])*
+        $(pub $(($($OwnerVisArgs:tt)*))?)? owner: &'static mut $OwnerPointee:ty,
+        $($Fields:tt)*
+    }
 
-                // Drop guard that cleans up should building the dependent panic.
-                let mut drop_guard =
-                    $crate::unsafe_self_cell::OwnerAndCellDropGuard::new(joined_ptr);
+    $($Rest:tt)*
+) => {
+    $crate::self_cell!(
+        $(#[$StructMeta])*
+        $Vis struct $StructName {
+            $(#[$OwnerMeta])*
+            $(pub $(($($OwnerVisArgs)*))?)? owner: (&'static mut $OwnerPointee),
+            $($Fields)*
+        }
 
-                match dependent_builder(&*owner_ptr) {
-                    Ok(dependent) => {
-                        dependent_ptr.write(dependent);
-                        drop_guard.mark_fully_init();
+        $($Rest)*
+    );
+};
+(
+    $(#[$StructMeta:meta])*
+    $Vis:vis struct $StructName:ident {
+        $(#[$OwnerMeta:meta])*
+        $(pub $(($($OwnerVisArgs:tt)*))?)? owner: &'static $OwnerPointee:ty,
+        $($Fields:tt)*
+    }
 
-                        Ok(Self {
-                            unsafe_self_cell: $crate::unsafe_self_cell::UnsafeSelfCell::new(
-                                joined_void_ptr,
-                            ),
-                        })
-                    }
-                    Err(err) => Err(err)
-                }
-            }
+    $($Rest:tt)*
+) => {
+    $crate::self_cell!(
+        $(#[$StructMeta])*
+        $Vis struct $StructName {
+            $(#[$OwnerMeta])*
+            $(pub $(($($OwnerVisArgs)*))?)? owner: (&'static $OwnerPointee),
+            $($Fields)*
         }
 
-        $Vis fn try_new_or_recover<Err>(
-            owner: $Owner,
-            dependent_builder: impl for<'a> FnOnce(&'a $Owner) -> Result<$Dependent<'a>, Err>
-        ) -> Result<Self, ($Owner, Err)> {
-            use core::ptr::NonNull;
+        $($Rest)*
+    );
+};
+(
+    $(#[$StructMeta:meta])*
+    $Vis:vis struct $StructName:ident {
+        $(#[$OwnerMeta:meta])*
+        $(pub $(($($OwnerVisArgs:tt)*))?)? owner: & $OwnerLifetime:lifetime $($OwnerTail:tt)*
+    }
 
-            unsafe {
-                // See fn new for more explanation.
+    $($Rest:tt)*
+) => {
+    compile_error!(concat!(
+        "The owner has to be 'static, found lifetime `",
+        stringify!($OwnerLifetime),
+        "`. Store the borrowed data itself, eg. String instead of &str"
+    ));
+};
+(
+    $(#[$StructMeta:meta])*
+    $Vis:vis struct $StructName:ident<'a> $($Rest:tt)*
+) => {
+    $crate::_reserved_lifetime!('a);
+};
+(
+    $(#[$StructMeta:meta])*
+    $Vis:vis struct $StructName:ident<'b> $($Rest:tt)*
+) => {
+    $crate::_reserved_lifetime!('b);
+};
+(
+    $(#[$StructMeta:meta])*
+    $Vis:vis struct $StructName:ident<'x> $($Rest:tt)*
+) => {
+    $crate::_reserved_lifetime!('x);
+};
+(
+    $(#[$StructMeta:meta])*
+    $Vis:vis struct $StructName:ident<'y> $($Rest:tt)*
+) => {
+    $crate::_reserved_lifetime!('y);
+};
+(
+    $(#[$StructMeta:meta])*
+    $Vis:vis struct $StructName:ident<'_brand> $($Rest:tt)*
+) => {
+    $crate::_reserved_lifetime!('_brand);
+};
+(
+    $(#[$StructMeta:meta])*
+    $Vis:vis struct $StructName:ident $(<$OwnerLifetime:lifetime>)? {
+        $(#[$OwnerMeta:meta])*
+        $(pub $(($($OwnerVisArgs:tt)*))?)? owner: $Owner:ty,
 
-                type JoinedCell<'a> = $crate::unsafe_self_cell::JoinedCell<$Owner, $Dependent<'a>>;
+        #[$Covariance:ident $(, $Mode:ident)?]
+        $(#[$DependentMeta:meta])*
+        $(pub $(($($DependentVisArgs:tt)*))?)? dependent: $Dependent:ident $(<$($DependentLifetime:lifetime),+>)? $(,)?
+    }
 
-                let layout = $crate::alloc::alloc::Layout::new::<JoinedCell>();
-                assert!(layout.size() != 0);
+    $(impl {$($AutomaticDerive:ident $(($($DeriveArgs:tt)*))?),* $(,)?})?
+) => {
+    $crate::_check_dependent!([$($($DependentLifetime),+)?], $Dependent, {
+        $($crate::_check_mode!($Mode);)?
 
-                let joined_void_ptr = NonNull::new($crate::alloc::alloc::alloc(layout)).unwrap();
+        $crate::_cell_struct!(
+            [$($Mode)?], [$(#[$StructMeta])*], $Vis, $StructName, [$($OwnerLifetime)?], $Owner,
+            $crate::_stored_dependent!([$($Mode)?], $crate::_dependent!({Self}, 'static))
+        );
 
-                let mut joined_ptr = core::mem::transmute::<NonNull<u8>, NonNull<JoinedCell>>(
-                    joined_void_ptr
-                );
+        impl $(<$OwnerLifetime>)? $StructName $(<$OwnerLifetime>)? {
+            $crate::_constructors!([$($Mode)?], $Vis, $Owner, {Self});
 
-                let owner_ptr: *mut $Owner = &mut (*joined_ptr.as_ptr()).owner;
-                let dependent_ptr: *mut $Dependent = &mut (*joined_ptr.as_ptr()).dependent;
+            $crate::_builders!([$($Mode)?], $Vis, $Owner, {Self});
 
-                // Move owner into newly allocated space.
-                owner_ptr.write(owner);
+            $crate::_method_vis!(
+                [$(pub $(($($OwnerVisArgs)*))?)?], $Vis,
+                _owner_access, [[$(#[$OwnerMeta])*], [$($Mode)?],], [$Owner, {Self}]
+            );
 
-                // Drop guard that cleans up should building the dependent panic.
-                let mut drop_guard =
-                    $crate::unsafe_self_cell::OwnerAndCellDropGuard::new(joined_ptr);
+            $crate::_method_vis!(
+                [$(pub $(($($DependentVisArgs)*))?)?], $Vis,
+                _dependent_access, [[$(#[$DependentMeta])*], [$($Mode)?], $Covariance,], [$Owner, {Self}]
+            );
 
-                match dependent_builder(&*owner_ptr) {
-                    Ok(dependent) => {
-                        dependent_ptr.write(dependent);
-                        drop_guard.mark_fully_init();
+            $crate::_method_vis!(
+                [$(pub $(($($DependentVisArgs)*))?)?], $Vis,
+                _as_dyn, [[$($Mode)?],], [{Self}, [$($($AutomaticDerive $(($($DeriveArgs)*))?,)*)?]]
+            );
 
-                        Ok(Self {
-                            unsafe_self_cell: $crate::unsafe_self_cell::UnsafeSelfCell::new(
-                                joined_void_ptr,
-                            ),
-                        })
-                    }
-                    Err(err) => {
-                        let owner_on_err = core::ptr::read(owner_ptr);
+            $crate::_map_dependent!([$($Mode)?], $Vis, $Owner, {Self});
 
-                        // Allowing drop_guard to finish would let it double free owner.
-                        // So we dealloc the JoinedCell here manually.
-                        drop_guard.mark_fully_init();
-                        $crate::alloc::alloc::dealloc(joined_void_ptr.as_ptr(), layout);
+            $crate::_swap!([$($Mode)?], $Vis, $Owner, {Self});
 
-                        Err((owner_on_err, err))
-                    }
-                }
+            /// Layout of the memory holding owner and dependent.
+            $Vis const fn joined_layout() -> ::core::alloc::Layout {
+                $crate::unsafe_self_cell::UnsafeSelfCell::<
+                    $Owner,
+                    $crate::_stored_dependent!([$($Mode)?], $crate::_dependent!({Self}, 'static))
+                >::joined_layout()
             }
         }
 
-        $Vis fn borrow_owner<'a>(&'a self) -> &'a $Owner {
-            unsafe { self.unsafe_self_cell.borrow_owner::<$Dependent<'a>>() }
+        impl<'a $(, $OwnerLifetime)?> $crate::DependentOf<'a> for $StructName $(<$OwnerLifetime>)? {
+            type Dependent = $crate::_dependent!({$Dependent $(<$($DependentLifetime),+>)?}, 'a);
         }
 
-        $Vis fn with_dependent<Ret>(&self, func: impl for<'a> FnOnce(&'a $Owner, &'a $Dependent<'a>) -> Ret) -> Ret {
-            unsafe {
-                func(
-                    self.unsafe_self_cell.borrow_owner::<$Dependent>(),
-                    self.unsafe_self_cell.borrow_dependent()
-                )
-            }
-        }
+        $crate::_map_target!([$($Mode)?], $StructName, [$($OwnerLifetime)?], $Owner, {Self});
 
-        $Vis fn with_dependent_mut<Ret>(&mut self, func: impl for<'a> FnOnce(&'a $Owner, &'a mut $Dependent<'a>) -> Ret) -> Ret {
-            let joined_cell = unsafe {
-                    self.unsafe_self_cell.borrow_mut()
-            };
+        $crate::_drop_impl!(
+            [$($($AutomaticDerive $(($($DeriveArgs)*))?,)*)?],
+            [[$($Mode)?], $StructName, [$($OwnerLifetime)?], $Owner, {Self}]
+        );
 
-            func(&joined_cell.owner, &mut joined_cell.dependent)
-        }
+        $crate::_self_cell_ext!(
+            [$($($AutomaticDerive $(($($DeriveArgs)*))?,)*)?],
+            [[$($Mode)?], $StructName, [$($OwnerLifetime)?], $Owner, {Self}]
+        );
+
+        // The user has to choose which traits can and should be automatically
+        // implemented for the cell.
+        $crate::_impl_automatic_derives!(
+            $StructName,
+            [$($OwnerLifetime)?],
+            $Owner,
+            $Covariance,
+            [$($($AutomaticDerive $(($($DeriveArgs)*))?,)*)?]
+        );
+    });
+};
+(
+    $(#[$StructMeta:meta])*
+    $Vis:vis struct $StructName:ident $(<$OwnerLifetime:lifetime>)? {
+        $(#[$OwnerMeta:meta])*
+        $(pub $(($($OwnerVisArgs:tt)*))?)? owner: $Owner:ty,
 
-        $crate::_covariant_access!($Covariance, $Vis, $Dependent);
+        #[doc $($DocArgs:tt)*]
+        $($Fields:tt)*
+    }
 
-        $Vis fn into_owner(self) -> $Owner {
-            // This is only safe to do with repr(transparent).
-            let unsafe_self_cell = unsafe { core::mem::transmute::<
-                Self,
-                $crate::unsafe_self_cell::UnsafeSelfCell<
-                    $Owner,
-                    $Dependent<'static>
-                >
-            >(self) };
+    $($Rest:tt)*
+) => {
+    compile_error!(
+        "Doc comments and attributes of the dependent go after the covariance attribute"
+    );
+};
+(
+    $(#[$StructMeta:meta])*
+    $Vis:vis struct $StructName:ident $(<$OwnerLifetime:lifetime>)? {
+        $(#[$OwnerMeta:meta])*
+        $(pub $(($($OwnerVisArgs:tt)*))?)? owner: $Owner:ty,
+
+        $(pub $(($($DependentVisArgs:tt)*))?)? dependent: $($Dependent:tt)*
+    }
 
-            let owner = unsafe { unsafe_self_cell.into_owner::<$Dependent>() };
+    $($Rest:tt)*
+) => {
+    compile_error!(
+        "Missing covariance attribute on dependent, add `#[covariant]` or `#[not_covariant]`"
+    );
+};
+(
+    $(#[$StructMeta:meta])*
+    $Vis:vis struct $StructName:ident $(<$OwnerLifetime:lifetime>)? {
+        $(#[$OwnerMeta:meta])*
+        $(pub $(($($OwnerVisArgs:tt)*))?)? owner: $Owner:ty,
 
-            owner
-        }
+        #[$Covariance:ident $(, $Mode:ident)?]
+        $(#[$DependentMeta:meta])*
+        $(pub $(($($DependentVisArgs:tt)*))?)? dependent: $Dependent:ident $(<$($DependentLifetime:lifetime),+>)? $(,)?
     }
 
-    impl Drop for $StructName {
-        fn drop<'a>(&mut self) {
-            unsafe {
-                self.unsafe_self_cell.drop_joined::<$Dependent>();
-            }
-        }
+    $($Rest:tt)*
+) => {
+    compile_error!(concat!(
+        "Expected the automatically implemented traits after the struct, ",
+        "eg. `impl {Debug, PartialEq}`, found `",
+        stringify!($($Rest)*),
+        "`"
+    ));
+};
+(
+    $(#[$StructMeta:meta])*
+    $Vis:vis struct $StructName:ident $(<$OwnerLifetime:lifetime>)? {
+        $(#[$OwnerMeta:meta])*
+        $(pub $(($($OwnerVisArgs:tt)*))?)? owner: $Owner:ty,
+
+        #[$Covariance:ident $(, $Mode:ident)?]
+        $(#[$DependentMeta:meta])*
+        $(pub $(($($DependentVisArgs:tt)*))?)? dependent: $Dependent:ty $(,)?
     }
 
-    // The user has to choose which traits can and should be automatically
-    // implemented for the cell.
-    $($(
-        $crate::_impl_automatic_derive!($AutomaticDerive, $StructName);
-    )*)*
+    $($Rest:tt)*
+) => {
+    compile_error!(concat!(
+        "The dependent has to be the name of a type with exactly one lifetime parameter, ",
+        "eg. `type Ast<'a> = Vec<&'a str>;` and `dependent: Ast`, or list its lifetimes with `'_` ",
+        "for the ones borrowing owner, eg. `dependent: Parsed<'_, 'static>`, found `",
+        stringify!($Dependent),
+        "`"
+    ));
 };
 }
@@ -0,0 +1,150 @@
+use core::marker::PhantomData;
+
+use crate::generic_cell::HeapCell;
+use crate::DependentOf;
+
+/// Names the captures type of an [`OwnedCaptures`] for every lifetime.
+///
+/// Implement it for a marker type, eg. for regex:
+///
+/// ```ignore
+/// struct RegexCaptures;
+///
+/// impl<'a> CapturesFamily<'a> for RegexCaptures {
+///     type Captures = regex::Captures<'a>;
+/// }
+/// ```
+pub trait CapturesFamily<'a> {
+    type Captures: 'a;
+}
+
+/// Owned haystack together with the captures a matcher found in it.
+///
+/// Covers the common case of an owned input and a borrowed match object, eg.
+/// `String` and `regex::Captures`, without declaring a cell with
+/// [`self_cell`](crate::self_cell). The matcher may find nothing, in that case
+/// there are no captures.
+///
+/// Because `OwnedCaptures` is generic over the captures type, it can't know if it
+/// is covariant. All access to the captures happens inside closures, like the
+/// `not_covariant` cells generated by the macro.
+///
+/// ```
+/// use self_cell::{CapturesFamily, OwnedCaptures};
+///
+/// struct Words;
+///
+/// impl<'a> CapturesFamily<'a> for Words {
+///     type Captures = Vec<&'a str>;
+/// }
+///
+/// let haystack = OwnedCaptures::<String, Words>::new("fox cat dog".into(), |haystack| {
+///     Some(haystack.split(' ').collect())
+/// });
+///
+/// assert!(haystack.is_match());
+/// assert_eq!(haystack.map_captures(|_, words| words.len()), Some(3));
+/// ```
+pub struct OwnedCaptures<Owner: 'static, Family>
+where
+    Family: for<'a> CapturesFamily<'a> + 'static,
+{
+    heap_cell: HeapCell<Owner, MaybeCaptures<Family>>,
+}
+
+// Names the optional captures as DependentOf, for HeapCell.
+pub(crate) struct MaybeCaptures<Family>(PhantomData<Family>);
+
+impl<'a, Family> DependentOf<'a> for MaybeCaptures<Family>
+where
+    Family: CapturesFamily<'a>,
+{
+    type Dependent = Option<<Family as CapturesFamily<'a>>::Captures>;
+}
+
+impl<Owner, Family> OwnedCaptures<Owner, Family>
+where
+    Family: for<'a> CapturesFamily<'a> + 'static,
+{
+    /// Moves owner into the heap and runs matcher on it.
+    pub fn new(
+        owner: Owner,
+        matcher: impl for<'a> FnOnce(&'a Owner) -> Option<<Family as CapturesFamily<'a>>::Captures>,
+    ) -> Self {
+        Self {
+            heap_cell: HeapCell::<_, MaybeCaptures<Family>>::new(owner, matcher),
+        }
+    }
+
+    /// Like [`OwnedCaptures::new`], but the matcher can fail. On failure owner is
+    /// dropped and the error returned.
+    pub fn try_new<Err>(
+        owner: Owner,
+        matcher: impl for<'a> FnOnce(
+            &'a Owner,
+        )
+            -> Result<Option<<Family as CapturesFamily<'a>>::Captures>, Err>,
+    ) -> Result<Self, Err> {
+        Ok(Self {
+            heap_cell: HeapCell::<_, MaybeCaptures<Family>>::try_new(owner, matcher)?,
+        })
+    }
+
+    pub fn borrow_owner(&self) -> &Owner {
+        self.heap_cell.borrow_owner()
+    }
+
+    /// Returns true if the matcher found captures.
+    pub fn is_match(&self) -> bool {
+        self.with_captures(|_, captures| captures.is_some())
+    }
+
+    /// Calls func with owner and the captures, if any.
+    pub fn with_captures<Ret>(
+        &self,
+        func: impl for<'a> FnOnce(
+            &'a Owner,
+            Option<&'a <Family as CapturesFamily<'a>>::Captures>,
+        ) -> Ret,
+    ) -> Ret {
+        self.heap_cell
+            .with_dependent(|owner, captures| func(owner, captures.as_ref()))
+    }
+
+    /// Calls func with owner and the captures, if the matcher found any.
+    pub fn map_captures<Ret>(
+        &self,
+        func: impl for<'a> FnOnce(&'a Owner, &'a <Family as CapturesFamily<'a>>::Captures) -> Ret,
+    ) -> Option<Ret> {
+        self.with_captures(|owner, captures| captures.map(|captures| func(owner, captures)))
+    }
+
+    /// Calls func with owner and mutable access to the captures.
+    pub fn with_captures_mut<Ret>(
+        &mut self,
+        func: impl for<'a> FnOnce(
+            &'a Owner,
+            &'a mut Option<<Family as CapturesFamily<'a>>::Captures>,
+        ) -> Ret,
+    ) -> Ret {
+        self.heap_cell.with_dependent_mut(func)
+    }
+
+    /// Runs matcher against the same owner again, replacing the previous
+    /// captures. Returns true if the new matcher found captures.
+    ///
+    /// Should matcher panic, the previous captures are kept.
+    pub fn rematch(
+        &mut self,
+        matcher: impl for<'a> FnOnce(&'a Owner) -> Option<<Family as CapturesFamily<'a>>::Captures>,
+    ) -> bool {
+        self.with_captures_mut(|owner, captures| {
+            *captures = matcher(owner);
+            captures.is_some()
+        })
+    }
+
+    pub fn into_owner(self) -> Owner {
+        self.heap_cell.into_owner()
+    }
+}
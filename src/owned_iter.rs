@@ -0,0 +1,206 @@
+use core::marker::PhantomData;
+
+use alloc::vec::Vec;
+
+use crate::generic_cell::HeapCell;
+use crate::DependentOf;
+
+/// Names the item type of an [`OwnedIterCell`] for every lifetime.
+///
+/// Implement it with [`item_family`](crate::item_family), which declares a
+/// marker type and checks that the item is covariant:
+///
+/// ```ignore
+/// self_cell::item_family!(struct Words<'a> = &'a str);
+/// ```
+///
+/// # Safety
+///
+/// `Item` has to be covariant over `'a`, [`OwnedIterCell::iter`] hands out
+/// references to items with the lifetime of the cell borrow.
+pub unsafe trait ItemFamily<'a> {
+    type Item: 'a;
+}
+
+/// Declares a marker type implementing [`ItemFamily`].
+///
+/// `item_family!(pub struct Records<'a> = Record<'a>);` declares `Records`, the
+/// item type may use the lifetime `'a`. It only compiles for covariant item
+/// types.
+#[macro_export]
+macro_rules! item_family {
+    (
+        $(#[$FamilyMeta:meta])*
+        $Vis:vis struct $Family:ident<$ItemLifetime:lifetime> = $Item:ty $(;)?
+    ) => {
+        $(#[$FamilyMeta])*
+        $Vis struct $Family;
+
+        unsafe impl<$ItemLifetime> $crate::ItemFamily<$ItemLifetime> for $Family {
+            type Item = $Item;
+        }
+
+        const _: () = {
+            fn _assert_covariance<'x: 'y, 'y>(
+                x: <$Family as $crate::ItemFamily<'x>>::Item,
+            ) -> <$Family as $crate::ItemFamily<'y>>::Item {
+                //  This function only compiles for covariant types.
+                x
+            }
+        };
+    };
+}
+
+/// One owner and the items borrowed from it.
+///
+/// Covers the common case of an owned buffer parsed into records, without
+/// declaring a cell with a `Vec` dependent with [`self_cell`](crate::self_cell).
+/// The items can be iterated directly, because [`ItemFamily`] guarantees they
+/// are covariant.
+///
+/// ```
+/// use self_cell::{item_family, OwnedIterCell};
+///
+/// item_family!(struct Words<'a> = &'a str);
+///
+/// let words = OwnedIterCell::<String, Words>::new("fox cat dog".into(), |text| {
+///     text.split(' ').collect()
+/// });
+///
+/// assert_eq!(words.len(), 3);
+/// assert_eq!(words.iter().last(), Some(&"dog"));
+///
+/// for word in &words {
+///     assert_eq!(word.len(), 3);
+/// }
+/// ```
+pub struct OwnedIterCell<Owner: 'static, Family>
+where
+    Family: for<'a> ItemFamily<'a> + 'static,
+{
+    heap_cell: HeapCell<Owner, Items<Family>>,
+}
+
+// Names the Vec of items as DependentOf, for HeapCell.
+pub(crate) struct Items<Family>(PhantomData<Family>);
+
+impl<'a, Family> DependentOf<'a> for Items<Family>
+where
+    Family: ItemFamily<'a>,
+{
+    type Dependent = Vec<<Family as ItemFamily<'a>>::Item>;
+}
+
+impl<Owner, Family> OwnedIterCell<Owner, Family>
+where
+    Family: for<'a> ItemFamily<'a> + 'static,
+{
+    /// Moves owner into the heap and collects the items from it with
+    /// `items_builder`.
+    pub fn new(
+        owner: Owner,
+        items_builder: impl for<'a> FnOnce(&'a Owner) -> Vec<<Family as ItemFamily<'a>>::Item>,
+    ) -> Self {
+        Self {
+            heap_cell: HeapCell::<_, Items<Family>>::new(owner, items_builder),
+        }
+    }
+
+    /// Like [`OwnedIterCell::new`], but the builder can fail, eg. on the first
+    /// record that doesn't parse. On failure owner is dropped and the error
+    /// returned.
+    pub fn try_new<Err>(
+        owner: Owner,
+        items_builder: impl for<'a> FnOnce(
+            &'a Owner,
+        )
+            -> Result<Vec<<Family as ItemFamily<'a>>::Item>, Err>,
+    ) -> Result<Self, Err> {
+        Ok(Self {
+            heap_cell: HeapCell::<_, Items<Family>>::try_new(owner, items_builder)?,
+        })
+    }
+
+    pub fn borrow_owner(&self) -> &Owner {
+        self.heap_cell.borrow_owner()
+    }
+
+    /// Borrows the items.
+    pub fn as_slice<'a>(&'a self) -> &'a [<Family as ItemFamily<'a>>::Item] {
+        // Shortening the item lifetime to the one of the borrow is only
+        // possible because ItemFamily guarantees covariance.
+        unsafe { self.heap_cell.borrow_dependent() }
+    }
+
+    /// Iterates over references to the items.
+    pub fn iter<'a>(&'a self) -> core::slice::Iter<'a, <Family as ItemFamily<'a>>::Item> {
+        self.as_slice().iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.as_slice().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.as_slice().is_empty()
+    }
+
+    /// Calls func with owner and every item, in order.
+    pub fn for_each_dependent(
+        &self,
+        mut func: impl for<'a> FnMut(&'a Owner, &'a <Family as ItemFamily<'a>>::Item),
+    ) {
+        let owner = self.borrow_owner();
+        for item in self {
+            func(owner, item);
+        }
+    }
+
+    /// Like [`OwnedIterCell::for_each_dependent`], but stops at and returns the
+    /// first error of func.
+    pub fn try_for_each_dependent<Err>(
+        &self,
+        mut func: impl for<'a> FnMut(&'a Owner, &'a <Family as ItemFamily<'a>>::Item) -> Result<(), Err>,
+    ) -> Result<(), Err> {
+        let owner = self.borrow_owner();
+        self.iter().try_for_each(|item| func(owner, item))
+    }
+
+    /// Iterates over references to the items in parallel, with the `rayon`
+    /// feature.
+    #[cfg(feature = "rayon")]
+    pub fn par_iter_dependents<'a>(
+        &'a self,
+    ) -> rayon::slice::Iter<'a, <Family as ItemFamily<'a>>::Item>
+    where
+        <Family as ItemFamily<'a>>::Item: Sync,
+    {
+        use rayon::iter::IntoParallelRefIterator;
+
+        self.as_slice().par_iter()
+    }
+
+    /// Calls func with owner and mutable access to the items.
+    pub fn with_items_mut<Ret>(
+        &mut self,
+        func: impl for<'a> FnOnce(&'a Owner, &'a mut Vec<<Family as ItemFamily<'a>>::Item>) -> Ret,
+    ) -> Ret {
+        self.heap_cell.with_dependent_mut(func)
+    }
+
+    pub fn into_owner(self) -> Owner {
+        self.heap_cell.into_owner()
+    }
+}
+
+impl<'c, Owner, Family> IntoIterator for &'c OwnedIterCell<Owner, Family>
+where
+    Family: for<'a> ItemFamily<'a> + 'static,
+{
+    type Item = &'c <Family as ItemFamily<'c>>::Item;
+    type IntoIter = core::slice::Iter<'c, <Family as ItemFamily<'c>>::Item>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
@@ -0,0 +1,111 @@
+use crate::unsafe_self_cell::{MapTarget, OwnerAndCellDropGuard};
+use crate::DependentOf;
+
+/// Owner in the final allocation of a cell, before the dependent is built.
+///
+/// Returned by `new_uninit_dependent` of [`self_cell`](crate::self_cell). Owner
+/// can be mutated freely until [`PartialCell::build`] borrows it and turns
+/// the partial cell into `Cell`, without moving owner again. Fits owners that
+/// are filled incrementally, eg. a streamed download that is parsed once it
+/// is complete. Dropping the partial cell drops owner.
+///
+/// Only available for heap allocated cells without dependent mode.
+///
+/// ```
+/// use self_cell::self_cell;
+///
+/// type Lines<'a> = Vec<&'a str>;
+///
+/// self_cell!(
+///     struct LinesCell {
+///         owner: String,
+///
+///         #[covariant]
+///         dependent: Lines,
+///     }
+/// );
+///
+/// let mut partial = LinesCell::new_uninit_dependent(String::new());
+/// for chunk in ["fox\nc", "at\n", "dog"] {
+///     partial.owner_mut().push_str(chunk);
+/// }
+///
+/// let cell = partial.build(|text| text.lines().collect());
+/// assert_eq!(cell.borrow_dependent(), &["fox", "cat", "dog"]);
+/// ```
+pub struct PartialCell<Cell>
+where
+    Cell: for<'a> DependentOf<'a> + MapTarget,
+{
+    drop_guard: OwnerAndCellDropGuard<
+        <Cell as MapTarget>::Owner,
+        <Cell as DependentOf<'static>>::Dependent,
+    >,
+}
+
+impl<Cell> PartialCell<Cell>
+where
+    Cell: for<'a> DependentOf<'a> + MapTarget,
+{
+    /// Moves owner into the heap, the same as `new_uninit_dependent` of the
+    /// cell.
+    pub fn new(owner: <Cell as MapTarget>::Owner) -> Self {
+        Self {
+            drop_guard: unsafe { OwnerAndCellDropGuard::allocate(owner) },
+        }
+    }
+
+    pub fn borrow_owner(&self) -> &<Cell as MapTarget>::Owner {
+        unsafe { &*self.drop_guard.owner_ptr() }
+    }
+
+    /// Mutable access to owner, nothing borrows it yet.
+    pub fn owner_mut(&mut self) -> &mut <Cell as MapTarget>::Owner {
+        unsafe { &mut *self.drop_guard.owner_mut_ptr() }
+    }
+
+    /// Builds the dependent from owner in its final place.
+    pub fn build(
+        self,
+        dependent_builder: impl for<'a> FnOnce(
+            &'a <Cell as MapTarget>::Owner,
+        ) -> <Cell as DependentOf<'a>>::Dependent,
+    ) -> Cell {
+        unsafe {
+            // See the fn new generated by self_cell for why the builder can't
+            // smuggle references in or out.
+            let drop_guard = self.drop_guard.cast_dependent();
+
+            let dependent = dependent_builder(&*drop_guard.owner_ptr());
+
+            Cell::from_joined_void_ptr(drop_guard.init_dependent(dependent))
+        }
+    }
+
+    /// Same as [`PartialCell::build`], but the builder can fail. On failure
+    /// the partial cell is returned together with the error, so that owner
+    /// can be completed and building tried again.
+    pub fn try_build<Err>(
+        self,
+        dependent_builder: impl for<'a> FnOnce(
+            &'a <Cell as MapTarget>::Owner,
+        )
+            -> Result<<Cell as DependentOf<'a>>::Dependent, Err>,
+    ) -> Result<Cell, (Self, Err)> {
+        unsafe {
+            match dependent_builder(&*self.drop_guard.owner_ptr()) {
+                Ok(dependent) => {
+                    let drop_guard = self.drop_guard.cast_dependent();
+                    Ok(Cell::from_joined_void_ptr(
+                        drop_guard.init_dependent(dependent),
+                    ))
+                }
+                Err(err) => Err((self, err)),
+            }
+        }
+    }
+
+    pub fn into_owner(self) -> <Cell as MapTarget>::Owner {
+        unsafe { self.drop_guard.recover_owner() }
+    }
+}
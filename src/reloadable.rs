@@ -0,0 +1,89 @@
+use alloc::sync::Arc;
+
+use crate::sync::RwLock;
+
+/// Handle to a cell that can be replaced at runtime, eg. for config
+/// hot-reload.
+///
+/// Readers take a snapshot with [`ReloadableSelfCell::load`], an `Arc` of the
+/// cell that was current at that time. Replacing the cell doesn't affect
+/// existing snapshots, the old cell is dropped once the last snapshot of it
+/// is. The lock is only held to clone or swap the `Arc`, never while a cell is
+/// built or used.
+///
+/// ```
+/// use self_cell::{self_cell, ReloadableSelfCell};
+///
+/// type Keys<'a> = Vec<&'a str>;
+///
+/// self_cell!(
+///     struct Config {
+///         owner: String,
+///
+///         #[covariant]
+///         dependent: Keys,
+///     }
+/// );
+///
+/// fn parse(text: &str) -> Config {
+///     Config::new(text.into(), |text| text.split(',').collect())
+/// }
+///
+/// static CONFIG: ReloadableSelfCell<Config> = ReloadableSelfCell::new();
+///
+/// CONFIG.store(parse("a,b"));
+/// let snapshot = CONFIG.load().unwrap();
+///
+/// CONFIG.store(parse("c"));
+/// assert_eq!(snapshot.borrow_dependent(), &vec!["a", "b"]);
+/// assert_eq!(CONFIG.load().unwrap().borrow_dependent(), &vec!["c"]);
+/// ```
+pub struct ReloadableSelfCell<T> {
+    current: RwLock<Option<Arc<T>>>,
+}
+
+impl<T> ReloadableSelfCell<T> {
+    /// Creates an empty handle, usable in a `static`.
+    pub const fn new() -> Self {
+        Self {
+            current: RwLock::new(None),
+        }
+    }
+
+    /// Returns a snapshot of the current cell, if any.
+    pub fn load(&self) -> Option<Arc<T>> {
+        self.current.read().clone()
+    }
+
+    /// Replaces the current cell, returning the previous one.
+    pub fn store(&self, cell: T) -> Option<Arc<T>> {
+        // Built before taking the lock, so readers never wait for it.
+        let cell = Arc::new(cell);
+        self.current.write().replace(cell)
+    }
+
+    /// Returns a snapshot of the current cell, storing the result of init
+    /// first if there is none.
+    ///
+    /// init runs without holding the lock. Should another thread store a cell
+    /// in the meantime, that one is returned and the result of init dropped.
+    pub fn get_or_init(&self, init: impl FnOnce() -> T) -> Arc<T> {
+        if let Some(cell) = self.load() {
+            return cell;
+        }
+
+        let cell = Arc::new(init());
+        self.current.write().get_or_insert(cell).clone()
+    }
+
+    /// Removes the current cell, returning it.
+    pub fn take(&self) -> Option<Arc<T>> {
+        self.current.write().take()
+    }
+}
+
+impl<T> Default for ReloadableSelfCell<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,114 @@
+// Locks used by the `rw_lock` and `mutex` dependent modes.
+//
+// The backend is chosen when self_cell is compiled, not when the macro is
+// expanded. parking_lot takes precedence if both backends are enabled.
+//
+// Poisoning is ignored with the std backend, so both backends behave the
+// same. A panic while the dependent is locked can leave it in whatever state
+// the panicking closure left it, but never in an unsound one.
+
+use core::ops::{Deref, DerefMut};
+
+#[cfg(feature = "parking_lot")]
+mod backend {
+    pub use parking_lot::{Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+    pub fn read<T>(lock: &RwLock<T>) -> RwLockReadGuard<'_, T> {
+        lock.read()
+    }
+
+    pub fn write<T>(lock: &RwLock<T>) -> RwLockWriteGuard<'_, T> {
+        lock.write()
+    }
+
+    pub fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+        mutex.lock()
+    }
+
+    pub fn rw_lock_get_mut<T>(lock: &mut RwLock<T>) -> &mut T {
+        lock.get_mut()
+    }
+
+    pub fn mutex_get_mut<T>(mutex: &mut Mutex<T>) -> &mut T {
+        mutex.get_mut()
+    }
+}
+
+#[cfg(all(feature = "std", not(feature = "parking_lot")))]
+mod backend {
+    pub use std::sync::{Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+    use std::sync::PoisonError;
+
+    pub fn read<T>(lock: &RwLock<T>) -> RwLockReadGuard<'_, T> {
+        lock.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn write<T>(lock: &RwLock<T>) -> RwLockWriteGuard<'_, T> {
+        lock.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+        mutex.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn rw_lock_get_mut<T>(lock: &mut RwLock<T>) -> &mut T {
+        lock.get_mut().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn mutex_get_mut<T>(mutex: &mut Mutex<T>) -> &mut T {
+        mutex.get_mut().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[doc(hidden)]
+pub struct RwLock<T> {
+    lock: backend::RwLock<T>,
+}
+
+impl<T> RwLock<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            lock: backend::RwLock::new(value),
+        }
+    }
+
+    pub fn read(&self) -> impl Deref<Target = T> + '_ {
+        backend::read(&self.lock)
+    }
+
+    pub fn write(&self) -> impl DerefMut<Target = T> + '_ {
+        backend::write(&self.lock)
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        backend::rw_lock_get_mut(&mut self.lock)
+    }
+}
+
+// Mutex offers the same interface as RwLock, so that the macro can treat both
+// modes the same. Both read and write take the exclusive lock.
+#[doc(hidden)]
+pub struct Mutex<T> {
+    lock: backend::Mutex<T>,
+}
+
+impl<T> Mutex<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            lock: backend::Mutex::new(value),
+        }
+    }
+
+    pub fn read(&self) -> impl Deref<Target = T> + '_ {
+        backend::lock(&self.lock)
+    }
+
+    pub fn write(&self) -> impl DerefMut<Target = T> + '_ {
+        backend::lock(&self.lock)
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        backend::mutex_get_mut(&mut self.lock)
+    }
+}
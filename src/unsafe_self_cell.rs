@@ -1,10 +1,16 @@
+use core::alloc::Layout;
 use core::marker::PhantomData;
-use core::mem::transmute;
-use core::ptr::{drop_in_place, read, NonNull};
+use core::mem::{align_of, forget, offset_of, size_of, MaybeUninit};
+use core::ops::{Deref, DerefMut};
+use core::ptr::{addr_of, addr_of_mut, drop_in_place, read, NonNull};
 
+#[cfg(feature = "alloc")]
 extern crate alloc;
 
-use alloc::alloc::{dealloc, Layout};
+#[cfg(feature = "alloc")]
+use alloc::alloc::{alloc, dealloc, handle_alloc_error, realloc};
+#[cfg(feature = "alloc")]
+use alloc::boxed::Box;
 
 // Self referential structs are currently not supported with safe vanilla Rust.
 // The only reasonable safe alternative is to expect the user to juggle 2 separate
@@ -18,21 +24,382 @@ use alloc::alloc::{dealloc, Layout};
 // 4. The only access to owner and dependent is as immutable reference.
 // 5. owner lives longer than dependent.
 
+// repr(C) puts owner at offset 0, which allows growing the allocation of a
+// boxed owner into a JoinedCell, see allocate_from_box.
 #[doc(hidden)]
+#[repr(C)]
 pub struct JoinedCell<Owner, Dependent> {
     pub owner: Owner,
     pub dependent: Dependent,
+
+    // CANARY_ALIVE while owner and dependent are initialized, CANARY_POISONED
+    // once the JoinedCell was torn down. A u32 and not usize, the patterns
+    // wouldn't fit and collapse into one value on 16-bit targets.
+    #[cfg(all(feature = "debug-assertions", debug_assertions))]
+    pub canary: u32,
+}
+
+#[cfg(all(feature = "debug-assertions", debug_assertions))]
+const CANARY_ALIVE: u32 = 0x5e1f_ce11;
+
+#[cfg(all(feature = "debug-assertions", debug_assertions))]
+const CANARY_POISONED: u32 = 0xdead_ce11;
+
+// Where the JoinedCell lives. The macro knows this statically, passing it at
+// runtime keeps UnsafeSelfCell free of an extra type parameter.
+#[doc(hidden)]
+#[derive(Clone, Copy)]
+pub enum Storage {
+    // Allocated and deallocated with the global allocator.
+    #[cfg(feature = "alloc")]
+    Heap,
+    // Provided by the caller as JoinedStorage, never deallocated.
+    Static,
+}
+
+/// Uninitialized storage for a cell declared with the `static_storage` mode.
+///
+/// The cell takes the `&'static mut` reference, so a storage can only ever be
+/// used by one cell. Obtain it for example from a `static mut` or a crate like
+/// `static_cell`.
+///
+/// `DependentStatic` is the dependent type with a `'static` lifetime, eg.
+/// `JoinedStorage<String, Ast<'static>>`.
+pub struct JoinedStorage<Owner, DependentStatic> {
+    joined: MaybeUninit<JoinedCell<Owner, DependentStatic>>,
+}
+
+impl<Owner, DependentStatic> JoinedStorage<Owner, DependentStatic> {
+    pub const fn new() -> Self {
+        Self {
+            joined: MaybeUninit::uninit(),
+        }
+    }
+
+    #[doc(hidden)]
+    pub fn joined_void_ptr(&'static mut self) -> NonNull<u8> {
+        NonNull::from(&mut self.joined).cast()
+    }
+}
+
+impl<Owner, DependentStatic> Default for JoinedStorage<Owner, DependentStatic> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The allocation for a cell failed, returned by `try_new_fallible_alloc` of
+/// [`self_cell`](crate::self_cell).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AllocError;
+
+impl core::fmt::Display for AllocError {
+    fn fmt(&self, fmt: &mut core::fmt::Formatter) -> core::fmt::Result {
+        fmt.write_str("memory allocation for self_cell failed")
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for AllocError {}
+
+/// Error of a dependent builder, together with the owner if it was recovered.
+///
+/// Converts from the error of `try_new_or_recover` of
+/// [`self_cell`](crate::self_cell), which allows propagating it with `?`:
+///
+/// ```ignore
+/// let cell = AstCell::try_new_or_recover(owner, parse).map_err(BuildError::from)?;
+/// ```
+///
+/// `Debug` doesn't print the owner, so that `Owner` doesn't have to implement
+/// it.
+pub struct BuildError<Err, Owner> {
+    error: Err,
+    owner: Option<Owner>,
+}
+
+impl<Err, Owner> BuildError<Err, Owner> {
+    /// Error without owner, eg. from `try_new`, which drops it.
+    pub fn new(error: Err) -> Self {
+        Self { error, owner: None }
+    }
+
+    pub fn with_owner(error: Err, owner: Owner) -> Self {
+        Self {
+            error,
+            owner: Some(owner),
+        }
+    }
+
+    pub fn error(&self) -> &Err {
+        &self.error
+    }
+
+    pub fn owner(&self) -> Option<&Owner> {
+        self.owner.as_ref()
+    }
+
+    pub fn into_error(self) -> Err {
+        self.error
+    }
+
+    pub fn into_owner(self) -> Option<Owner> {
+        self.owner
+    }
+
+    pub fn into_parts(self) -> (Err, Option<Owner>) {
+        (self.error, self.owner)
+    }
+}
+
+impl<Err, Owner> From<(Owner, Err)> for BuildError<Err, Owner> {
+    fn from((owner, error): (Owner, Err)) -> Self {
+        Self::with_owner(error, owner)
+    }
+}
+
+impl<Err: core::fmt::Debug, Owner> core::fmt::Debug for BuildError<Err, Owner> {
+    fn fmt(&self, fmt: &mut core::fmt::Formatter) -> core::fmt::Result {
+        fmt.debug_struct("BuildError")
+            .field("error", &self.error)
+            .field("owner_recovered", &self.owner.is_some())
+            .finish()
+    }
+}
+
+impl<Err: core::fmt::Display, Owner> core::fmt::Display for BuildError<Err, Owner> {
+    fn fmt(&self, fmt: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(fmt, "building the dependent failed: {}", self.error)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<Err, Owner> std::error::Error for BuildError<Err, Owner>
+where
+    Err: std::error::Error + 'static,
+{
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// Names the dependent type of a cell for every lifetime.
+///
+/// Implemented by all cells declared with [`self_cell`](crate::self_cell), eg.
+/// `<AstCell as DependentOf<'a>>::Dependent` is `Ast<'a>`.
+pub trait DependentOf<'a> {
+    type Dependent;
+}
+
+/// The dependent of `Cell` for the lifetime `'a`, `borrow_dependent` of
+/// [`self_cell`](crate::self_cell) returns `&'a BorrowedDependent<'a, Cell>`.
+///
+/// Names the return type without restating the dependent, which can stay
+/// private to the module declaring the cell:
+///
+/// ```ignore
+/// pub fn first_word<'a>(cell: &'a AstCell) -> &'a BorrowedDependent<'a, AstCell> {
+///     cell.borrow_dependent()
+/// }
+/// ```
+pub type BorrowedDependent<'a, Cell> = <Cell as DependentOf<'a>>::Dependent;
+
+/// Reference to the dependent of a cell that also leads back to its owner,
+/// see `with_dependent_ref` of [`self_cell`](crate::self_cell).
+///
+/// Derefs to the dependent and [`DependentRef::owner`] returns the owner, so
+/// that code handed only the dependent, eg. a callback reporting an error,
+/// can still reach the text it borrows from without a second parameter. Both
+/// are associated functions, so they don't shadow methods of the dependent.
+///
+/// There is no way back from a plain `&Dependent`. That reference is only
+/// valid for the bytes of the dependent, deriving a reference to owner from
+/// it with pointer arithmetic is undefined behavior, even though owner sits
+/// right before the dependent in the same allocation. `DependentRef` holds
+/// the pointer to both instead.
+pub struct DependentRef<'a, Owner, Dependent> {
+    joined_void_ptr: NonNull<u8>,
+
+    marker: PhantomData<(&'a Owner, &'a Dependent)>,
+}
+
+impl<'a, Owner, Dependent> DependentRef<'a, Owner, Dependent> {
+    // The pointer has to point to a JoinedCell of owner and dependent that is
+    // borrowed for 'a.
+    #[doc(hidden)]
+    pub unsafe fn new(joined_void_ptr: NonNull<u8>) -> Self {
+        Self {
+            joined_void_ptr,
+            marker: PhantomData,
+        }
+    }
+
+    pub fn owner(this: &Self) -> &'a Owner {
+        let joined_ptr = this.joined_void_ptr.cast::<JoinedCell<Owner, Dependent>>();
+        unsafe { &*addr_of!((*joined_ptr.as_ptr()).owner) }
+    }
+
+    /// The dependent for all of `'a`, Deref only borrows it as long as the
+    /// handle.
+    pub fn get(this: &Self) -> &'a Dependent {
+        let joined_ptr = this.joined_void_ptr.cast::<JoinedCell<Owner, Dependent>>();
+        unsafe { &*addr_of!((*joined_ptr.as_ptr()).dependent) }
+    }
+}
+
+impl<Owner, Dependent> Clone for DependentRef<'_, Owner, Dependent> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<Owner, Dependent> Copy for DependentRef<'_, Owner, Dependent> {}
+
+impl<Owner, Dependent> Deref for DependentRef<'_, Owner, Dependent> {
+    type Target = Dependent;
+
+    fn deref(&self) -> &Dependent {
+        DependentRef::get(self)
+    }
+}
+
+// Shares owner and dependent like a pair of references.
+unsafe impl<Owner: Sync, Dependent: Sync> Send for DependentRef<'_, Owner, Dependent> {}
+
+unsafe impl<Owner: Sync, Dependent: Sync> Sync for DependentRef<'_, Owner, Dependent> {}
+
+/// Mutable access to the owner while building the dependent, see `new_mut`
+/// of [`self_cell`](crate::self_cell).
+///
+/// The owner can be mutated through the handle for as long as the handle is
+/// borrowed. [`OwnerMut::into_ref`] freezes it, the returned shared reference
+/// is the only way to borrow owner for `'a`.
+///
+/// `'brand` is unique to every call of the builder and can't be named by the
+/// dependent, so the handle itself can't become part of the dependent.
+pub struct OwnerMut<'a, 'brand, Owner> {
+    owner: &'a mut Owner,
+
+    // Invariant, so that 'brand can't be turned into 'a.
+    brand_marker: PhantomData<fn(&'brand ()) -> &'brand ()>,
+}
+
+impl<'a, 'brand, Owner> OwnerMut<'a, 'brand, Owner> {
+    #[doc(hidden)]
+    pub fn new(owner: &'a mut Owner) -> Self {
+        Self {
+            owner,
+            brand_marker: PhantomData,
+        }
+    }
+
+    pub fn into_ref(self) -> &'a Owner {
+        self.owner
+    }
+}
+
+impl<Owner> Deref for OwnerMut<'_, '_, Owner> {
+    type Target = Owner;
+
+    fn deref(&self) -> &Owner {
+        self.owner
+    }
+}
+
+impl<Owner> DerefMut for OwnerMut<'_, '_, Owner> {
+    fn deref_mut(&mut self) -> &mut Owner {
+        self.owner
+    }
+}
+
+// Debug output produced by a function, used for the owner formatter of the
+// Debug($owner_fmt) automatic trait impl and the placeholder of Debug.
+#[doc(hidden)]
+pub struct DebugFn<F>(pub F);
+
+impl<F> core::fmt::Debug for DebugFn<F>
+where
+    F: Fn(&mut core::fmt::Formatter) -> core::fmt::Result,
+{
+    fn fmt(&self, fmt: &mut core::fmt::Formatter) -> core::fmt::Result {
+        (self.0)(fmt)
+    }
+}
+
+// Implemented by the cells map_dependent can produce, which are the heap
+// allocated ones without a dependent mode.
+#[doc(hidden)]
+pub unsafe trait MapTarget {
+    type Owner;
+
+    // The pointer has to point to a fully initialized JoinedCell of owner
+    // and the dependent of Self.
+    unsafe fn from_joined_void_ptr(joined_void_ptr: NonNull<u8>) -> Self;
+
+    // Drops owner and dependent without freeing the JoinedCell, as if it was
+    // in caller provided storage. The cell must not be used or dropped after.
+    unsafe fn drop_joined_in_place(&mut self);
+
+    // Gives up ownership of the JoinedCell without dropping it, the inverse
+    // of from_joined_void_ptr.
+    fn into_joined_void_ptr(self) -> NonNull<u8>;
+}
+
+/// Raw access to the internals of a cell declared with
+/// [`self_cell`](crate::self_cell), for crates that extend a cell in ways the
+/// generated API can't express.
+///
+/// Implemented by listing it with the automatic traits, `impl {SelfCellExt}`.
+/// A cell declared with `#[sealed(module)]` and without it keeps its
+/// internals private to the macro. The methods of `UnsafeSelfCell` aren't part
+/// of the stable API of this crate, they may change with any release.
+///
+/// # Safety
+///
+/// Only the macro implements this trait, the returned `UnsafeSelfCell` is the
+/// one the cell stores.
+pub unsafe trait SelfCellExt {
+    type Owner;
+
+    /// The dependent as stored in the cell, with a `'static` lifetime.
+    type DependentStatic: 'static;
+
+    /// # Safety
+    ///
+    /// The caller upholds the invariants of the cell: owner is never changed
+    /// and outlives the dependent, and references to owner and dependent
+    /// never outlive the borrow of the cell. The dependent has to be accessed
+    /// with the lifetime of that borrow, not `DependentStatic`.
+    unsafe fn unsafe_self_cell(&self) -> &UnsafeSelfCell<Self::Owner, Self::DependentStatic>;
+
+    /// # Safety
+    ///
+    /// Same as [`SelfCellExt::unsafe_self_cell`]. Additionally the
+    /// `UnsafeSelfCell` must not be moved out or replaced, and the cell must
+    /// not be used after its JoinedCell was dropped through it.
+    unsafe fn unsafe_self_cell_mut(
+        &mut self,
+    ) -> &mut UnsafeSelfCell<Self::Owner, Self::DependentStatic>;
 }
 
 // Library controlled struct that marks all accesses as unsafe.
 // Because the macro generated struct impl can be extended, could be unsafe.
 #[doc(hidden)]
-pub struct UnsafeSelfCell<Owner: 'static, DependentStatic: 'static> {
+pub struct UnsafeSelfCell<Owner, DependentStatic: 'static> {
     joined_void_ptr: NonNull<u8>,
 
     owner_marker: PhantomData<Owner>,
     // DependentStatic is only used to correctly derive Send and Sync.
     dependent_marker: PhantomData<DependentStatic>,
+
+    // Tracks whether the JoinedCell was already dropped or moved out, so that
+    // misuse of this raw layer panics instead of being UB.
+    #[cfg(any(
+        miri,
+        feature = "shadow_state",
+        all(feature = "debug-assertions", debug_assertions)
+    ))]
+    alive: bool,
 }
 
 impl<Owner, DependentStatic> UnsafeSelfCell<Owner, DependentStatic> {
@@ -41,59 +408,354 @@ impl<Owner, DependentStatic> UnsafeSelfCell<Owner, DependentStatic> {
             joined_void_ptr,
             owner_marker: PhantomData,
             dependent_marker: PhantomData,
+            #[cfg(any(
+                miri,
+                feature = "shadow_state",
+                all(feature = "debug-assertions", debug_assertions)
+            ))]
+            alive: true,
         }
     }
 
-    pub unsafe fn borrow_owner<'a, Dependent>(&'a self) -> &'a Owner {
-        let joined_ptr =
-            transmute::<NonNull<u8>, NonNull<JoinedCell<Owner, Dependent>>>(self.joined_void_ptr);
+    // Moving the pointer out doesn't end the life of the JoinedCell, the
+    // caller has to forget self and rebuild a cell from the pointer later.
+    pub fn joined_void_ptr(&self) -> NonNull<u8> {
+        self.joined_void_ptr
+    }
+
+    // Layout of the JoinedCell, the lifetime of the dependent doesn't change
+    // it.
+    pub const fn joined_layout() -> Layout {
+        Layout::new::<JoinedCell<Owner, DependentStatic>>()
+    }
+
+    // The flag is checked first, the canary can only be read while the
+    // JoinedCell wasn't freed yet.
+    #[cfg(any(
+        miri,
+        feature = "shadow_state",
+        all(feature = "debug-assertions", debug_assertions)
+    ))]
+    #[track_caller]
+    fn assert_alive<Dependent>(&self) {
+        assert!(
+            self.alive,
+            "UnsafeSelfCell used after its JoinedCell was dropped or moved out"
+        );
+
+        #[cfg(all(feature = "debug-assertions", debug_assertions))]
+        {
+            let joined_ptr = self.joined_ptr::<Dependent>();
+            let canary = unsafe { (*joined_ptr.as_ptr()).canary };
+            assert!(
+                canary == CANARY_ALIVE,
+                "UnsafeSelfCell points to a JoinedCell that isn't alive, found canary {:#x}",
+                canary
+            );
+        }
+    }
+
+    #[cfg(not(any(
+        miri,
+        feature = "shadow_state",
+        all(feature = "debug-assertions", debug_assertions)
+    )))]
+    #[inline(always)]
+    fn assert_alive<Dependent>(&self) {}
+
+    // The cast keeps the provenance of the allocation, the pointer is never
+    // turned into an integer and back.
+    #[inline(always)]
+    fn joined_ptr<Dependent>(&self) -> NonNull<JoinedCell<Owner, Dependent>> {
+        self.joined_void_ptr.cast()
+    }
+
+    #[inline(always)]
+    pub unsafe fn borrow_owner<Dependent>(&self) -> &Owner {
+        self.assert_alive::<Dependent>();
+
+        let joined_ptr = self.joined_ptr::<Dependent>();
 
         &(*joined_ptr.as_ptr()).owner
     }
 
-    pub unsafe fn borrow_dependent<'a, Dependent>(&'a self) -> &'a Dependent {
-        let joined_ptr =
-            transmute::<NonNull<u8>, NonNull<JoinedCell<Owner, Dependent>>>(self.joined_void_ptr);
+    #[inline(always)]
+    pub unsafe fn borrow_dependent<Dependent>(&self) -> &Dependent {
+        self.assert_alive::<Dependent>();
+
+        let joined_ptr = self.joined_ptr::<Dependent>();
 
         &(*joined_ptr.as_ptr()).dependent
     }
 
-    pub unsafe fn borrow_mut<'a, Dependent>(&'a mut self) -> &'a mut JoinedCell<Owner, Dependent> {
-        let joined_ptr =
-            transmute::<NonNull<u8>, NonNull<JoinedCell<Owner, Dependent>>>(self.joined_void_ptr);
+    #[inline(always)]
+    pub unsafe fn borrow_mut<Dependent>(&mut self) -> (&Owner, &mut Dependent) {
+        self.assert_alive::<Dependent>();
+
+        let joined_ptr = self.joined_ptr::<Dependent>();
+
+        // A unique reference to the whole JoinedCell would invalidate the
+        // references dependent holds into owner, so only dependent is borrowed
+        // mutably.
+        (
+            &(*joined_ptr.as_ptr()).owner,
+            &mut *addr_of_mut!((*joined_ptr.as_ptr()).dependent),
+        )
+    }
+
+    // Only borrows dependent, for dependents that hold unique references into
+    // owner.
+    #[inline(always)]
+    pub unsafe fn borrow_dependent_mut<Dependent>(&mut self) -> &mut Dependent {
+        self.assert_alive::<Dependent>();
 
-        &mut (*joined_ptr.as_ptr())
+        let joined_ptr = self.joined_ptr::<Dependent>();
+
+        &mut *addr_of_mut!((*joined_ptr.as_ptr()).dependent)
+    }
+
+    // Owner and dependent stay at these addresses until the JoinedCell is
+    // dropped or moved out, moving UnsafeSelfCell only moves the pointer to
+    // them. No reference is created, so these don't alias any borrow.
+    #[inline(always)]
+    pub unsafe fn owner_ptr<Dependent>(&self) -> *const Owner {
+        self.assert_alive::<Dependent>();
+
+        let joined_ptr = self.joined_ptr::<Dependent>();
+
+        addr_of!((*joined_ptr.as_ptr()).owner)
+    }
+
+    #[inline(always)]
+    pub unsafe fn dependent_ptr<Dependent>(&self) -> *const Dependent {
+        self.assert_alive::<Dependent>();
+
+        let joined_ptr = self.joined_ptr::<Dependent>();
+
+        addr_of!((*joined_ptr.as_ptr()).dependent)
     }
 
     // Any subsequent use of this struct other than dropping it is UB.
-    pub unsafe fn drop_joined<Dependent>(&mut self) {
-        let joined_ptr =
-            transmute::<NonNull<u8>, NonNull<JoinedCell<Owner, Dependent>>>(self.joined_void_ptr);
+    pub unsafe fn drop_joined<Dependent>(&mut self, storage: Storage) {
+        self.assert_alive::<Dependent>();
+        #[cfg(any(
+            miri,
+            feature = "shadow_state",
+            all(feature = "debug-assertions", debug_assertions)
+        ))]
+        {
+            self.alive = false;
+        }
 
-        drop_in_place(joined_ptr.as_ptr());
+        let joined_ptr = self.joined_ptr::<Dependent>();
 
-        let layout = Layout::new::<JoinedCell<Owner, Dependent>>();
+        // Dropping the JoinedCell as a whole would drop owner first, and
+        // create a unique reference to owner while dependent still borrows
+        // it. Instead drop dependent through a pointer to only that field, the
+        // guard then drops owner and frees the JoinedCell, even if dropping
+        // dependent panics.
+        let drop_guard =
+            OwnerAndCellDropGuard::<Owner, Dependent>::adopt(self.joined_void_ptr, storage);
+
+        drop_in_place(addr_of_mut!((*joined_ptr.as_ptr()).dependent));
 
-        dealloc(self.joined_void_ptr.as_ptr(), layout);
+        drop(drop_guard);
     }
 
-    pub unsafe fn into_owner<Dependent>(self) -> Owner {
-        let joined_ptr =
-            transmute::<NonNull<u8>, NonNull<JoinedCell<Owner, Dependent>>>(self.joined_void_ptr);
+    // Same as drop_joined, but moves dependent into func together with a
+    // reference to owner, which is dropped once func returned. Should func
+    // panic, the guard still drops owner and frees the JoinedCell.
+    pub unsafe fn drop_joined_with<'x, Dependent>(
+        &mut self,
+        storage: Storage,
+        func: impl FnOnce(&'x Owner, Dependent),
+    ) where
+        Owner: 'x,
+    {
+        self.assert_alive::<Dependent>();
+        #[cfg(any(
+            miri,
+            feature = "shadow_state",
+            all(feature = "debug-assertions", debug_assertions)
+        ))]
+        {
+            self.alive = false;
+        }
+
+        let joined_ptr = self.joined_ptr::<Dependent>();
+
+        let dependent = read(&(*joined_ptr.as_ptr()).dependent);
+
+        let drop_guard =
+            OwnerAndCellDropGuard::<Owner, Dependent>::adopt(self.joined_void_ptr, storage);
+
+        func(&*drop_guard.owner_ptr(), dependent);
+
+        drop(drop_guard);
+    }
+
+    pub unsafe fn into_owner<Dependent>(self, storage: Storage) -> Owner {
+        self.assert_alive::<Dependent>();
+
+        let joined_ptr = self.joined_ptr::<Dependent>();
+
+        // Dependent may still use owner while being dropped.
+        drop_in_place(addr_of_mut!((*joined_ptr.as_ptr()).dependent));
 
         let owner_ptr: *const Owner = &(*joined_ptr.as_ptr()).owner;
 
         // Move owner out so it can be returned.
         let owner = read(owner_ptr);
 
-        // Clean up rest of JoinedCell
-        drop_in_place(&mut (*joined_ptr.as_ptr()).dependent);
-
-        let layout = Layout::new::<JoinedCell<Owner, Dependent>>();
-        dealloc(self.joined_void_ptr.as_ptr(), layout);
+        free_joined::<Owner, Dependent>(self.joined_void_ptr, storage);
 
         owner
     }
+
+    // Same as into_owner, but moves dependent into func instead of dropping
+    // it. Owner is moved out only after func returned.
+    pub unsafe fn into_owner_and<Dependent, Ret>(
+        self,
+        storage: Storage,
+        func: impl FnOnce(Dependent) -> Ret,
+    ) -> (Owner, Ret) {
+        self.assert_alive::<Dependent>();
+
+        let joined_ptr = self.joined_ptr::<Dependent>();
+
+        let dependent = read(&(*joined_ptr.as_ptr()).dependent);
+
+        // From here on the JoinedCell only holds owner, should func panic the
+        // guard drops it and frees the JoinedCell.
+        let drop_guard =
+            OwnerAndCellDropGuard::<Owner, Dependent>::adopt(self.joined_void_ptr, storage);
+
+        let ret = func(dependent);
+
+        (drop_guard.recover_owner(), ret)
+    }
+
+    // Replaces the dependent with the result of func, keeping owner in place.
+    // Returns the pointer for the UnsafeSelfCell of the new cell.
+    pub unsafe fn map_dependent<'x, Dependent, NewDependent>(
+        self,
+        storage: Storage,
+        func: impl FnOnce(&'x Owner, Dependent) -> NewDependent,
+    ) -> NonNull<u8>
+    where
+        Owner: 'x,
+    {
+        #[allow(clippy::let_unit_value)]
+        let () = SameLayout::<Owner, Dependent, NewDependent>::ASSERT;
+
+        self.assert_alive::<Dependent>();
+
+        let joined_ptr = self.joined_ptr::<Dependent>();
+
+        let dependent = read(&(*joined_ptr.as_ptr()).dependent);
+
+        // From here on the JoinedCell only holds owner, should func panic the
+        // guard drops it and frees the JoinedCell.
+        let drop_guard =
+            OwnerAndCellDropGuard::<Owner, NewDependent>::adopt(self.joined_void_ptr, storage);
+
+        let new_dependent = func(&*drop_guard.owner_ptr(), dependent);
+
+        drop_guard.init_dependent(new_dependent)
+    }
+}
+
+// Both dependents have to result in the same JoinedCell layout, so that owner
+// stays where it is and the memory is freed with the layout it was allocated
+// with. Supporting other layouts would need a new allocation, but the old and
+// new dependent borrow owner in place, so owner can't be moved into it. The
+// assert fails when map_dependent is monomorphized, eg. by cargo build, not by
+// cargo check.
+struct SameLayout<Owner, Dependent, NewDependent>(PhantomData<(Owner, Dependent, NewDependent)>);
+
+impl<Owner, Dependent, NewDependent> SameLayout<Owner, Dependent, NewDependent> {
+    const ASSERT: () = assert!(
+        size_of::<JoinedCell<Owner, Dependent>>() == size_of::<JoinedCell<Owner, NewDependent>>()
+            && align_of::<JoinedCell<Owner, Dependent>>()
+                == align_of::<JoinedCell<Owner, NewDependent>>()
+            && offset_of!(JoinedCell<Owner, Dependent>, owner)
+                == offset_of!(JoinedCell<Owner, NewDependent>, owner),
+        "map_dependent needs both cells to have the same joined_layout, owner can't move while \
+         the dependents borrow it. Boxing both dependents gives them the same layout"
+    );
+}
+
+// Nothing in here assumes a pointer width or byte order. These hold on every
+// target and are checked whenever the crate is built for one, including 16-bit
+// targets like AVR that can't run the test suite.
+const _: () = {
+    type Joined = JoinedCell<u8, &'static u8>;
+
+    // repr(C) keeps owner at offset 0 for allocate_from_box, the dependent is
+    // placed after it with the padding its alignment needs. The exact offsets
+    // are compared against Layout::extend in the joined_layout tests, which
+    // isn't usable in const on the minimum supported Rust version.
+    assert!(offset_of!(Joined, owner) == 0);
+    assert!(offset_of!(Joined, dependent) >= size_of::<u8>());
+    assert!(offset_of!(Joined, dependent) % align_of::<&u8>() == 0);
+    assert!(align_of::<Joined>() >= align_of::<&u8>());
+
+    // free_joined deallocates with the same layout allocate_joined used.
+    let layout = Layout::new::<Joined>();
+    assert!(layout.size() == size_of::<Joined>() && layout.align() == align_of::<Joined>());
+
+    // The NonNull niche keeps Option<UnsafeSelfCell> the size of the cell,
+    // with or without the shadow state.
+    assert!(
+        size_of::<Option<UnsafeSelfCell<u8, &'static u8>>>()
+            == size_of::<UnsafeSelfCell<u8, &'static u8>>()
+    );
+};
+
+// Caller provided storage and reused boxes could in theory be misaligned. addr
+// keeps the provenance of the pointer, unlike a cast to usize.
+#[cfg(feature = "strict_provenance")]
+#[clippy::msrv = "1.84"]
+#[inline(always)]
+fn debug_assert_aligned<T>(ptr: NonNull<T>) {
+    debug_assert_eq!(
+        ptr.as_ptr().addr() % align_of::<T>(),
+        0,
+        "misaligned JoinedCell"
+    );
+}
+
+#[cfg(not(feature = "strict_provenance"))]
+#[inline(always)]
+fn debug_assert_aligned<T>(_ptr: NonNull<T>) {}
+
+// Releases the memory of a JoinedCell whose fields have already been dropped
+// or moved out.
+#[cfg_attr(
+    not(feature = "alloc"),
+    allow(unused_variables, clippy::extra_unused_type_parameters)
+)]
+unsafe fn free_joined<Owner, Dependent>(joined_void_ptr: NonNull<u8>, storage: Storage) {
+    // Poisoned even if the memory is deallocated right after, a stale pointer
+    // into it is then likely to still see the poison.
+    #[cfg(all(feature = "debug-assertions", debug_assertions))]
+    {
+        let joined_ptr = joined_void_ptr.cast::<JoinedCell<Owner, Dependent>>();
+        addr_of_mut!((*joined_ptr.as_ptr()).canary).write(CANARY_POISONED);
+    }
+
+    match storage {
+        #[cfg(feature = "alloc")]
+        Storage::Heap => {
+            let layout = Layout::new::<JoinedCell<Owner, Dependent>>();
+            if layout.size() != 0 {
+                dealloc(joined_void_ptr.as_ptr(), layout);
+            }
+        }
+        // The caller provided storage is left uninitialized, the &'static mut
+        // given to the cell can't be used to access it again.
+        Storage::Static => {}
+    }
 }
 
 unsafe impl<Owner, DependentStatic> Send for UnsafeSelfCell<Owner, DependentStatic>
@@ -116,40 +778,177 @@ where
 // construction fails.
 #[doc(hidden)]
 pub struct OwnerAndCellDropGuard<Owner, Dependent> {
-    fully_init: bool,
     joined_ptr: NonNull<JoinedCell<Owner, Dependent>>,
+    storage: Storage,
 }
 
 impl<Owner, Dependent> OwnerAndCellDropGuard<Owner, Dependent> {
-    pub fn new(joined_ptr: NonNull<JoinedCell<Owner, Dependent>>) -> Self {
+    // Allocates the JoinedCell and moves owner into it. Allocation failure is
+    // reported with handle_alloc_error.
+    #[cfg(feature = "alloc")]
+    pub unsafe fn allocate(owner: Owner) -> Self {
+        match Self::allocate_joined() {
+            Some(joined_void_ptr) => Self::in_place(joined_void_ptr, owner, Storage::Heap),
+            None => handle_alloc_error(Layout::new::<JoinedCell<Owner, Dependent>>()),
+        }
+    }
+
+    // Same as allocate, but drops owner and returns an error if allocation
+    // fails.
+    #[cfg(feature = "alloc")]
+    pub unsafe fn try_allocate(owner: Owner) -> Result<Self, AllocError> {
+        match Self::allocate_joined() {
+            Some(joined_void_ptr) => Ok(Self::in_place(joined_void_ptr, owner, Storage::Heap)),
+            None => Err(AllocError),
+        }
+    }
+
+    // Same as allocate, but reuses the allocation of the boxed owner instead
+    // of moving owner into a new one. Owner stays at offset 0 and the
+    // allocation is only grown by the dependent, which realloc may do without
+    // copying. Falls back to allocate if the JoinedCell needs a larger
+    // alignment than owner or owner is zero sized, Box doesn't allocate then.
+    #[cfg(feature = "alloc")]
+    pub unsafe fn allocate_from_box(owner: Box<Owner>) -> Self {
+        let owner_layout = Layout::new::<Owner>();
+        let joined_layout = Layout::new::<JoinedCell<Owner, Dependent>>();
+
+        if owner_layout.size() == 0 || owner_layout.align() != joined_layout.align() {
+            return Self::allocate(*owner);
+        }
+
+        let owner_void_ptr = Box::into_raw(owner).cast::<u8>();
+
+        let joined_void_ptr = if joined_layout.size() == owner_layout.size() {
+            owner_void_ptr
+        } else {
+            realloc(owner_void_ptr, owner_layout, joined_layout.size())
+        };
+
+        match NonNull::new(joined_void_ptr) {
+            Some(joined_void_ptr) => Self::adopt(joined_void_ptr, Storage::Heap),
+            None => {
+                // The original allocation is left untouched if realloc fails.
+                drop(Box::from_raw(owner_void_ptr.cast::<Owner>()));
+                handle_alloc_error(joined_layout)
+            }
+        }
+    }
+
+    #[cfg(feature = "alloc")]
+    unsafe fn allocate_joined() -> Option<NonNull<u8>> {
+        let layout = Layout::new::<JoinedCell<Owner, Dependent>>();
+
+        // A zero sized JoinedCell needs no memory, any non-null and aligned
+        // pointer is valid for it. free_joined skips these as well.
+        if layout.size() == 0 {
+            Some(NonNull::<JoinedCell<Owner, Dependent>>::dangling().cast())
+        } else {
+            NonNull::new(alloc(layout))
+        }
+    }
+
+    // Moves owner into caller provided storage.
+    pub unsafe fn in_storage(joined_void_ptr: NonNull<u8>, owner: Owner) -> Self {
+        Self::in_place(joined_void_ptr, owner, Storage::Static)
+    }
+
+    // Takes over a JoinedCell with initialized owner and uninitialized or
+    // moved out dependent.
+    pub unsafe fn adopt(joined_void_ptr: NonNull<u8>, storage: Storage) -> Self {
+        let joined_ptr = joined_void_ptr.cast::<JoinedCell<Owner, Dependent>>();
+        debug_assert_aligned(joined_ptr);
+
         Self {
-            fully_init: false,
             joined_ptr,
+            storage,
         }
     }
 
-    pub fn mark_fully_init(&mut self) {
-        self.fully_init = true;
+    unsafe fn in_place(joined_void_ptr: NonNull<u8>, owner: Owner, storage: Storage) -> Self {
+        let joined_ptr = joined_void_ptr.cast::<JoinedCell<Owner, Dependent>>();
+        debug_assert_aligned(joined_ptr);
+
+        // Move owner into its final place.
+        let owner_ptr: *mut Owner = &mut (*joined_ptr.as_ptr()).owner;
+        owner_ptr.write(owner);
+
+        Self {
+            joined_ptr,
+            storage,
+        }
+    }
+
+    // Pointer to the owner in its final place, which is valid until the guard
+    // is consumed or dropped.
+    pub fn owner_ptr(&self) -> *const Owner {
+        unsafe { &(*self.joined_ptr.as_ptr()).owner }
+    }
+
+    // Same as owner_ptr, but allows mutating owner before the dependent is
+    // built.
+    pub fn owner_mut_ptr(&mut self) -> *mut Owner {
+        unsafe { addr_of_mut!((*self.joined_ptr.as_ptr()).owner) }
+    }
+
+    // Completes the JoinedCell, from here on UnsafeSelfCell is responsible
+    // for cleaning up.
+    pub unsafe fn init_dependent(self, dependent: Dependent) -> NonNull<u8> {
+        let dependent_ptr: *mut Dependent = &mut (*self.joined_ptr.as_ptr()).dependent;
+        dependent_ptr.write(dependent);
+
+        #[cfg(all(feature = "debug-assertions", debug_assertions))]
+        addr_of_mut!((*self.joined_ptr.as_ptr()).canary).write(CANARY_ALIVE);
+
+        let joined_void_ptr = self.joined_ptr.cast();
+        forget(self);
+
+        joined_void_ptr
+    }
+
+    // The same guard for a dependent type that only differs in its lifetime,
+    // eg. to build the dependent for a guard that was stored with 'static.
+    pub unsafe fn cast_dependent<NewDependent>(self) -> OwnerAndCellDropGuard<Owner, NewDependent> {
+        let drop_guard = OwnerAndCellDropGuard {
+            joined_ptr: self.joined_ptr.cast(),
+            storage: self.storage,
+        };
+        forget(self);
+
+        drop_guard
+    }
+
+    // Moves the owner back out and frees the JoinedCell, used if dependent
+    // construction failed.
+    pub unsafe fn recover_owner(self) -> Owner {
+        let owner = read(self.owner_ptr());
+
+        // Allowing drop to run would let it double free owner.
+        free_joined::<Owner, Dependent>(self.joined_ptr.cast(), self.storage);
+        forget(self);
+
+        owner
     }
 }
 
+// The guard owns owner and later dependent, which allows holding it across an
+// await point.
+unsafe impl<Owner, Dependent> Send for OwnerAndCellDropGuard<Owner, Dependent>
+where
+    Owner: Send,
+    Dependent: Send,
+{
+}
+
 impl<Owner, Dependent> Drop for OwnerAndCellDropGuard<Owner, Dependent> {
     fn drop(&mut self) {
-        if self.fully_init {
-            // We took over ownership and no cleanup should be done.
-            return;
-        }
-
         unsafe {
             // We must only drop owner and the struct itself,
             // The whole point of this drop guard is to clean up the partially
             // initialized struct should building the dependent fail.
             drop_in_place(&mut (*self.joined_ptr.as_ptr()).owner);
 
-            let layout = Layout::new::<JoinedCell<Owner, Dependent>>();
-            let joined_void_ptr =
-                transmute::<*mut JoinedCell<Owner, Dependent>, *mut u8>(self.joined_ptr.as_ptr());
-            dealloc(joined_void_ptr, layout);
+            free_joined::<Owner, Dependent>(self.joined_ptr.cast(), self.storage);
         }
     }
 }
@@ -0,0 +1,354 @@
+// Counts the heap allocations done by cells. Lives in its own test binary,
+// because it replaces the global allocator.
+#![cfg(feature = "alloc")]
+// The zero sized cells are unused with the debug-assertions feature, its canary
+// makes every JoinedCell sized.
+#![cfg_attr(all(feature = "debug-assertions", debug_assertions), allow(dead_code))]
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::marker::PhantomData;
+
+use self_cell::self_cell;
+
+struct CountingAllocator;
+
+thread_local! {
+    // Per thread, so that tests running in parallel don't see each others
+    // allocations.
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+    static DEALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+    // Makes every allocation of the thread fail while set.
+    static FAIL_ALLOCATIONS: Cell<bool> = const { Cell::new(false) };
+    // Layouts of the last allocation and deallocation of the thread.
+    static ALLOC_LAYOUT: Cell<Option<Layout>> = const { Cell::new(None) };
+    static DEALLOC_LAYOUT: Cell<Option<Layout>> = const { Cell::new(None) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|allocations| allocations.set(allocations.get() + 1));
+        let _ = ALLOC_LAYOUT.try_with(|last| last.set(Some(layout)));
+        if FAIL_ALLOCATIONS.try_with(Cell::get).unwrap_or(false) {
+            return std::ptr::null_mut();
+        }
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let _ = DEALLOCATIONS.try_with(|deallocations| deallocations.set(deallocations.get() + 1));
+        let _ = DEALLOC_LAYOUT.try_with(|last| last.set(Some(layout)));
+        System.dealloc(ptr, layout)
+    }
+
+    // Growing an allocation is not counted, the default implementation would
+    // count it as a new allocation.
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        if FAIL_ALLOCATIONS.try_with(Cell::get).unwrap_or(false) {
+            return std::ptr::null_mut();
+        }
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+fn count_allocations<T>(func: impl FnOnce() -> T) -> (T, usize) {
+    let before = ALLOCATIONS.with(Cell::get);
+    let ret = func();
+    (ret, ALLOCATIONS.with(Cell::get) - before)
+}
+
+fn count_deallocations<T>(func: impl FnOnce() -> T) -> (T, usize) {
+    let before = DEALLOCATIONS.with(Cell::get);
+    let ret = func();
+    (ret, DEALLOCATIONS.with(Cell::get) - before)
+}
+
+fn with_failing_allocations<T>(func: impl FnOnce() -> T) -> T {
+    FAIL_ALLOCATIONS.with(|fail| fail.set(true));
+    let ret = func();
+    FAIL_ALLOCATIONS.with(|fail| fail.set(false));
+    ret
+}
+
+#[derive(Debug, PartialEq)]
+struct ZeroSizeRef<'a>(PhantomData<&'a ()>);
+
+#[derive(Debug, PartialEq)]
+struct Marker;
+
+self_cell!(
+    struct ZeroSizeCell {
+        owner: Marker,
+
+        #[covariant]
+        dependent: ZeroSizeRef,
+    }
+);
+
+type Words<'a> = Vec<&'a str>;
+
+self_cell!(
+    struct WordsCell {
+        owner: String,
+
+        #[covariant]
+        dependent: Words,
+    }
+);
+
+type NumberRef<'a> = &'a u64;
+
+self_cell!(
+    struct NumberCell {
+        owner: u64,
+
+        #[covariant]
+        dependent: NumberRef,
+    }
+);
+
+// The canary of the debug-assertions feature makes every JoinedCell sized.
+#[cfg(not(all(feature = "debug-assertions", debug_assertions)))]
+#[test]
+fn zero_size_cell_does_not_allocate() {
+    let (cell, allocations) =
+        count_allocations(|| ZeroSizeCell::new(Marker, |_| ZeroSizeRef(PhantomData)));
+    assert_eq!(allocations, 0);
+
+    let (owner, allocations) = count_allocations(|| {
+        assert_eq!(cell.borrow_dependent(), &ZeroSizeRef(PhantomData));
+        cell.into_owner()
+    });
+    assert_eq!(owner, Marker);
+    assert_eq!(allocations, 0);
+
+    let (cell, allocations) = count_allocations(|| {
+        ZeroSizeCell::try_new_or_recover(Marker, |_| -> Result<ZeroSizeRef, ()> { Err(()) })
+    });
+    assert!(cell.is_err());
+    assert_eq!(allocations, 0);
+
+    let ((), allocations) = count_allocations(|| {
+        drop(ZeroSizeCell::new(Marker, |_| ZeroSizeRef(PhantomData)));
+    });
+    assert_eq!(allocations, 0);
+}
+
+#[test]
+fn sized_cell_allocates() {
+    let owner = String::from("fox cat");
+
+    // One for the cell and one for the Vec of the dependent.
+    let (cell, allocations) =
+        count_allocations(|| WordsCell::new(owner, |owner| owner.split(' ').collect()));
+    assert_eq!(allocations, 2);
+    assert_eq!(cell.borrow_dependent().len(), 2);
+}
+
+#[test]
+fn fallible_alloc() {
+    use std::rc::Rc;
+
+    use self_cell::AllocError;
+
+    type SharedWords<'a> = Vec<&'a str>;
+
+    self_cell!(
+        struct RcCell {
+            owner: Rc<String>,
+
+            #[covariant]
+            dependent: SharedWords,
+        }
+    );
+
+    let owner = Rc::new(String::from("fox cat"));
+    let mut builder_called = false;
+
+    let cell = with_failing_allocations(|| {
+        RcCell::try_new_fallible_alloc(owner.clone(), |owner| {
+            builder_called = true;
+            owner.split(' ').collect()
+        })
+    });
+    assert_eq!(cell.err(), Some(AllocError));
+    assert!(!builder_called);
+    // The owner was dropped.
+    assert_eq!(Rc::strong_count(&owner), 1);
+
+    let cell = RcCell::try_new_fallible_alloc(owner.clone(), |owner| owner.split(' ').collect());
+    assert_eq!(cell.unwrap().borrow_dependent(), &["fox", "cat"]);
+
+    // Zero sized cells never allocate, so they can't fail.
+    #[cfg(not(all(feature = "debug-assertions", debug_assertions)))]
+    {
+        let cell = with_failing_allocations(|| {
+            ZeroSizeCell::try_new_fallible_alloc(Marker, |_| ZeroSizeRef(PhantomData))
+        });
+        assert!(cell.is_ok());
+    }
+}
+
+#[test]
+fn new_from_box_reuses_allocation() {
+    type Block = [u64; 512];
+    type BlockRef<'a> = &'a [u64];
+
+    self_cell!(
+        struct BlockCell {
+            owner: Block,
+
+            #[covariant]
+            dependent: BlockRef,
+        }
+    );
+
+    let mut block = Box::new([0; 512]);
+    block[7] = 7;
+
+    // Owner and dependent have the same alignment, so the box is grown.
+    let (cell, allocations) =
+        count_allocations(|| BlockCell::new_from_box(block, |owner| &owner[7..]));
+    assert_eq!(allocations, 0);
+    assert_eq!(cell.borrow_dependent()[0], 7);
+    assert_eq!(cell.borrow_dependent().len(), 505);
+    drop(cell);
+
+    // The dependent needs a larger alignment than owner, owner is moved into a
+    // new allocation.
+    type Bytes = [u8; 64];
+    type BytesRef<'a> = &'a [u8];
+
+    self_cell!(
+        struct BytesCell {
+            owner: Bytes,
+
+            #[covariant]
+            dependent: BytesRef,
+        }
+    );
+
+    let bytes = Box::new([1; 64]);
+    let (cell, allocations) =
+        count_allocations(|| BytesCell::new_from_box(bytes, |owner| &owner[..]));
+    assert_eq!(allocations, 1);
+    assert_eq!(cell.borrow_dependent(), &[1; 64]);
+
+    // Boxed zero sized owners have no allocation.
+    #[cfg(not(all(feature = "debug-assertions", debug_assertions)))]
+    {
+        let (cell, allocations) = count_allocations(|| {
+            ZeroSizeCell::new_from_box(Box::new(Marker), |_| ZeroSizeRef(PhantomData))
+        });
+        assert_eq!(allocations, 0);
+        assert_eq!(cell.into_owner(), Marker);
+    }
+}
+
+#[test]
+fn borrows_do_not_allocate() {
+    let (mut cell, allocations) = count_allocations(|| NumberCell::new(7, |owner| owner));
+    assert_eq!(allocations, 1);
+
+    let ((), allocations) = count_allocations(|| {
+        assert_eq!(*cell.borrow_owner(), 7);
+        assert_eq!(**cell.borrow_dependent(), 7);
+        assert_eq!(
+            cell.with_dependent(|owner, dependent| *owner + **dependent),
+            14
+        );
+        cell.with_dependent_mut(|owner, dependent| *dependent = owner);
+    });
+    assert_eq!(allocations, 0);
+
+    let ((owner, allocations), deallocations) =
+        count_deallocations(|| count_allocations(|| cell.into_owner()));
+    assert_eq!(owner, 7);
+    assert_eq!(allocations, 0);
+    assert_eq!(deallocations, 1);
+}
+
+#[test]
+fn drop_and_failed_builds_free_the_cell() {
+    let ((), deallocations) = count_deallocations(|| drop(NumberCell::new(7, |owner| owner)));
+    assert_eq!(deallocations, 1);
+
+    let ((cell, allocations), deallocations) = count_deallocations(|| {
+        count_allocations(|| NumberCell::try_new(7, |_| -> Result<NumberRef, ()> { Err(()) }))
+    });
+    assert!(cell.is_err());
+    assert_eq!(allocations, 1);
+    assert_eq!(deallocations, 1);
+
+    let ((cell, allocations), deallocations) = count_deallocations(|| {
+        count_allocations(|| {
+            NumberCell::try_new_or_recover(7, |_| -> Result<NumberRef, ()> { Err(()) })
+        })
+    });
+    assert_eq!(cell.err(), Some((7, ())));
+    assert_eq!(allocations, 1);
+    assert_eq!(deallocations, 1);
+}
+
+// u64 is only 4 byte aligned on some 32-bit targets, and the dependent is a
+// pointer of the target width. Either way the cell is freed with the layout
+// it was allocated with.
+#[test]
+fn dealloc_uses_the_allocated_layout() {
+    let cell = NumberCell::new(7, |owner| owner);
+    assert_eq!(
+        ALLOC_LAYOUT.with(Cell::get),
+        Some(NumberCell::joined_layout())
+    );
+
+    drop(cell);
+    assert_eq!(
+        DEALLOC_LAYOUT.with(Cell::get),
+        Some(NumberCell::joined_layout())
+    );
+
+    let cell = WordsCell::new("a b".into(), |owner| owner.split(' ').collect());
+    let owner = cell.into_owner();
+    assert_eq!(owner, "a b");
+    assert_eq!(
+        DEALLOC_LAYOUT.with(Cell::get),
+        Some(WordsCell::joined_layout())
+    );
+}
+
+#[test]
+fn partial_cell_allocates_once() {
+    let ((cell, allocations), deallocations) = count_deallocations(|| {
+        count_allocations(|| {
+            let mut partial = NumberCell::new_uninit_dependent(6);
+            *partial.owner_mut() += 1;
+            partial.build(|owner| owner)
+        })
+    });
+    assert_eq!(**cell.borrow_dependent(), 7);
+    assert_eq!(allocations, 1);
+    assert_eq!(deallocations, 0);
+
+    let (owner, deallocations) =
+        count_deallocations(|| NumberCell::new_uninit_dependent(7).into_owner());
+    assert_eq!(owner, 7);
+    assert_eq!(deallocations, 1);
+}
+
+#[test]
+fn new_arena_allocates_twice() {
+    let owners = (0..100).collect();
+    let ((cells, allocations), deallocations) =
+        count_deallocations(|| count_allocations(|| NumberCell::new_arena(owners, |owner| owner)));
+    assert_eq!(**cells[42].borrow_dependent(), 42);
+    // One block for all owners and dependents, one for the cells.
+    assert_eq!(allocations, 2);
+    // The owners vec.
+    assert_eq!(deallocations, 1);
+
+    let ((), deallocations) = count_deallocations(|| drop(cells));
+    assert_eq!(deallocations, 2);
+}
@@ -0,0 +1,268 @@
+// Checks the manual Send and Sync impls and the sharing of cells between
+// threads. The regular tests run natively and under miri, with
+// -Zmiri-strict-provenance in the CI. Run the loom tests with:
+// RUSTFLAGS="--cfg loom" cargo test --release --test concurrency
+#![cfg(feature = "alloc")]
+
+use std::marker::PhantomData;
+
+use impls::impls;
+
+use self_cell::self_cell;
+
+#[derive(Debug, PartialEq)]
+struct Words<'a>(Vec<&'a str>);
+
+self_cell!(
+    struct WordsCell {
+        owner: String,
+
+        #[covariant]
+        dependent: Words,
+    }
+);
+
+fn words_cell(text: &str) -> WordsCell {
+    WordsCell::new(text.into(), |owner| Words(owner.split(' ').collect()))
+}
+
+// Owner that counts how often it was dropped.
+struct DropCounter<'a> {
+    text: String,
+    drops: &'a std::sync::atomic::AtomicUsize,
+}
+
+impl Drop for DropCounter<'_> {
+    fn drop(&mut self) {
+        self.drops.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+    }
+}
+
+self_cell!(
+    struct CountedCell<'o> {
+        owner: DropCounter<'o>,
+
+        #[covariant]
+        dependent: Words,
+    }
+);
+
+fn counted_cell<'o>(text: &str, drops: &'o std::sync::atomic::AtomicUsize) -> CountedCell<'o> {
+    CountedCell::new(
+        DropCounter {
+            text: text.into(),
+            drops,
+        },
+        |owner| Words(owner.text.split(' ').collect()),
+    )
+}
+
+#[derive(Debug)]
+struct SendNotSync(PhantomData<std::cell::Cell<()>>);
+
+#[derive(Debug)]
+struct SyncNotSend(PhantomData<*const ()>);
+
+unsafe impl Sync for SyncNotSend {}
+
+type BorrowsSendNotSync<'a> = &'a SendNotSync;
+
+self_cell!(
+    struct SendNotSyncCell {
+        owner: SendNotSync,
+
+        #[covariant]
+        dependent: BorrowsSendNotSync,
+    }
+);
+
+type BorrowsSyncNotSend<'a> = &'a SyncNotSend;
+
+self_cell!(
+    struct SyncNotSendCell {
+        owner: SyncNotSend,
+
+        #[covariant]
+        dependent: BorrowsSyncNotSend,
+    }
+);
+
+#[test]
+fn auto_traits() {
+    assert!(impls!(WordsCell: Send & Sync));
+
+    // A dependent borrowing a Send but not Sync owner is neither.
+    assert!(impls!(SendNotSync: Send & !Sync));
+    assert!(impls!(SendNotSyncCell: !Send & !Sync));
+
+    // A dependent borrowing a Sync but not Send owner is Send and Sync, but the
+    // cell moves the owner along with it, so it can only be Sync.
+    assert!(impls!(SyncNotSend: !Send & Sync));
+    assert!(impls!(SyncNotSendCell: !Send & Sync));
+}
+
+#[cfg(not(loom))]
+mod native {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Barrier};
+    use std::thread;
+
+    use super::*;
+
+    const THREADS: usize = 4;
+
+    #[test]
+    fn concurrent_reads() {
+        let cell = words_cell("a b c");
+        let owner_ptr = cell.borrow_owner().as_ptr() as usize;
+
+        thread::scope(|s| {
+            for _ in 0..THREADS {
+                s.spawn(|| {
+                    for _ in 0..10 {
+                        assert_eq!(cell.borrow_dependent(), &Words(vec!["a", "b", "c"]));
+                        cell.with_dependent(|owner, dependent| {
+                            assert_eq!(owner.as_ptr() as usize, owner_ptr);
+                            assert_eq!(dependent.0[0].as_ptr() as usize, owner_ptr);
+                        });
+                    }
+                });
+            }
+        });
+    }
+
+    #[test]
+    fn drop_race() {
+        let drops = AtomicUsize::new(0);
+        let cell = Arc::new(counted_cell("a b", &drops));
+        let barrier = Barrier::new(THREADS);
+
+        thread::scope(|s| {
+            for _ in 0..THREADS {
+                let cell = Arc::clone(&cell);
+                let barrier = &barrier;
+                s.spawn(move || {
+                    assert_eq!(cell.borrow_dependent().0.len(), 2);
+                    // All threads drop their handle at roughly the same time.
+                    barrier.wait();
+                    drop(cell);
+                });
+            }
+            drop(cell);
+        });
+
+        assert_eq!(drops.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn into_owner_after_sharing() {
+        let drops = AtomicUsize::new(0);
+        let cell = Arc::new(counted_cell("a b c", &drops));
+
+        thread::scope(|s| {
+            let handles: Vec<_> = (0..THREADS)
+                .map(|_| {
+                    let cell = Arc::clone(&cell);
+                    s.spawn(move || cell.borrow_dependent().0.len())
+                })
+                .collect();
+
+            for handle in handles {
+                assert_eq!(handle.join().unwrap(), 3);
+            }
+        });
+
+        let cell = Arc::try_unwrap(cell).ok().unwrap();
+        let owner = cell.into_owner();
+        assert_eq!(owner.text, "a b c");
+        assert_eq!(drops.load(Ordering::SeqCst), 0);
+
+        drop(owner);
+        assert_eq!(drops.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn move_between_threads() {
+        let cell = words_cell("a b");
+        let cell = thread::spawn(move || {
+            assert_eq!(cell.borrow_dependent().0.len(), 2);
+            cell
+        })
+        .join()
+        .unwrap();
+
+        assert_eq!(cell.into_owner(), "a b");
+    }
+}
+
+#[cfg(loom)]
+mod loom_model {
+    use loom::sync::atomic::{AtomicUsize, Ordering};
+    use loom::sync::Arc;
+    use loom::thread;
+
+    use super::*;
+
+    #[test]
+    fn concurrent_reads() {
+        loom::model(|| {
+            let cell = Arc::new(words_cell("a b"));
+
+            let reader = {
+                let cell = Arc::clone(&cell);
+                thread::spawn(move || cell.borrow_dependent().0.len())
+            };
+
+            assert_eq!(cell.borrow_dependent(), &Words(vec!["a", "b"]));
+            assert_eq!(reader.join().unwrap(), 2);
+        });
+    }
+
+    #[test]
+    fn drop_race() {
+        loom::model(|| {
+            // Leaked so the owner can borrow it from all threads.
+            let drops: &'static std::sync::atomic::AtomicUsize =
+                Box::leak(Box::new(std::sync::atomic::AtomicUsize::new(0)));
+            let done = Arc::new(AtomicUsize::new(0));
+
+            let cell = Arc::new(counted_cell("a b", drops));
+
+            let handles: Vec<_> = (0..2)
+                .map(|_| {
+                    let cell = Arc::clone(&cell);
+                    let done = Arc::clone(&done);
+                    thread::spawn(move || {
+                        assert_eq!(cell.borrow_dependent().0.len(), 2);
+                        drop(cell);
+                        done.fetch_add(1, Ordering::SeqCst);
+                    })
+                })
+                .collect();
+
+            drop(cell);
+            for handle in handles {
+                handle.join().unwrap();
+            }
+
+            assert_eq!(done.load(Ordering::SeqCst), 2);
+            assert_eq!(drops.load(std::sync::atomic::Ordering::SeqCst), 1);
+        });
+    }
+
+    #[test]
+    fn into_owner_after_sharing() {
+        loom::model(|| {
+            let cell = Arc::new(words_cell("a b"));
+
+            let reader = {
+                let cell = Arc::clone(&cell);
+                thread::spawn(move || cell.borrow_dependent().0.len())
+            };
+            assert_eq!(reader.join().unwrap(), 2);
+
+            let cell = Arc::try_unwrap(cell).ok().unwrap();
+            assert_eq!(cell.into_owner(), "a b");
+        });
+    }
+}
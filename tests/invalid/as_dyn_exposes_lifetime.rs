@@ -0,0 +1,37 @@
+use std::cell::Cell;
+
+use self_cell::self_cell;
+
+trait Current {
+    type Word;
+
+    fn current(&self) -> Self::Word;
+}
+
+struct Cursor<'a>(Cell<&'a str>);
+
+impl<'a> Current for Cursor<'a> {
+    type Word = &'a str;
+
+    fn current(&self) -> &'a str {
+        self.0.get()
+    }
+}
+
+self_cell!(
+    struct CursorCell {
+        owner: String,
+
+        #[not_covariant]
+        dependent: Cursor,
+    }
+
+    impl {AsDyn(as_current, dyn Current<Word = &'static str>)}
+);
+
+fn main() {
+    let cell = CursorCell::new("fox".into(), |owner| Cursor(Cell::new(owner)));
+    let word: &'static str = cell.as_current().current();
+    drop(cell);
+    println!("{}", word);
+}
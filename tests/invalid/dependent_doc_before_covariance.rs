@@ -0,0 +1,15 @@
+use self_cell::self_cell;
+
+type Ast<'a> = Vec<&'a str>;
+
+self_cell!(
+    struct DocBeforeCovariance {
+        owner: String,
+
+        /// The parsed text.
+        #[covariant]
+        dependent: Ast,
+    }
+);
+
+fn main() {}
@@ -0,0 +1,25 @@
+use self_cell::self_cell;
+
+struct Parsed<'src, 'meta> {
+    words: Vec<&'src str>,
+    kind: &'meta str,
+}
+
+self_cell!(
+    struct ParsedCell {
+        owner: String,
+
+        #[covariant]
+        dependent: Parsed<'_, 'static>,
+    }
+);
+
+fn main() {
+    let kind = String::from("words");
+
+    // Only the '_ lifetime borrows owner, the other one stays 'static.
+    let _cell = ParsedCell::new("fox cat".into(), |owner| Parsed {
+        words: owner.split(' ').collect(),
+        kind: &kind,
+    });
+}
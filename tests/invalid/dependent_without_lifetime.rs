@@ -0,0 +1,12 @@
+use self_cell::self_cell;
+
+self_cell!(
+    struct DependentWithoutLifetime {
+        owner: String,
+
+        #[covariant]
+        dependent: String,
+    }
+);
+
+fn main() {}
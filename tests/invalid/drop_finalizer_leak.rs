@@ -0,0 +1,22 @@
+use std::cell::RefCell;
+
+use self_cell::self_cell;
+
+type Ast<'a> = Vec<&'a str>;
+
+thread_local! {
+    static LEAKED: RefCell<Vec<&'static str>> = RefCell::new(Vec::new());
+}
+
+self_cell!(
+    struct AstCell {
+        owner: String,
+
+        #[covariant]
+        dependent: Ast,
+    }
+
+    impl {Drop(|_owner, ast| LEAKED.with(|leaked| leaked.borrow_mut().extend(ast)))}
+);
+
+fn main() {}
@@ -0,0 +1,18 @@
+use std::cell::Cell;
+
+use self_cell::{DependentBuilder, SelfCell};
+
+struct NotCovariant;
+
+impl<'a> DependentBuilder<'a, String> for NotCovariant {
+    type Output = Cell<&'a String>;
+
+    fn build(owner: &'a String) -> Self::Output {
+        Cell::new(owner)
+    }
+}
+
+fn main() {
+    let cell = SelfCell::<String, NotCovariant>::new("hi this is no good".into());
+    let _leaked_ref = cell.with_dependent(|_, dependent| dependent);
+}
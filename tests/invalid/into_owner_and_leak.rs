@@ -0,0 +1,19 @@
+use self_cell::self_cell;
+
+type Ast<'a> = Vec<&'a str>;
+
+self_cell!(
+    struct AstCell {
+        owner: String,
+
+        #[covariant]
+        dependent: Ast,
+    }
+);
+
+fn main() {
+    let cell = AstCell::new("fox cat".into(), |owner| owner.split(' ').collect());
+
+    // The words borrow owner, which is only valid inside func.
+    let (_owner, _words) = cell.into_owner_and(|ast| ast);
+}
@@ -0,0 +1,16 @@
+use self_cell::self_cell;
+
+type Ast<'a> = Vec<&'a str>;
+
+self_cell!(
+    struct InvalidDeriveList {
+        owner: String,
+
+        #[covariant]
+        dependent: Ast,
+    }
+
+    impl Debug
+);
+
+fn main() {}
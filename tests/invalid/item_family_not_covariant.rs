@@ -0,0 +1,7 @@
+use std::cell::Cell;
+
+use self_cell::item_family;
+
+item_family!(struct Cells<'a> = Cell<&'a str>);
+
+fn main() {}
@@ -0,0 +1,28 @@
+use self_cell::self_cell;
+
+struct Dependent<'a> {
+    good: &'a String,
+    bad: &'a String,
+}
+
+self_cell!(
+    struct NoCov {
+        owner: String,
+
+        #[covariant]
+        dependent: Dependent,
+    }
+);
+
+fn main() {
+    let outside_string = String::from("outside string");
+
+    let _cell = NoCov::new_with_ctx(
+        "hi this is no good".into(),
+        &outside_string,
+        |owner, outside| Dependent {
+            good: owner,
+            bad: outside,
+        },
+    );
+}
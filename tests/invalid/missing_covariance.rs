@@ -0,0 +1,13 @@
+use self_cell::self_cell;
+
+type Ast<'a> = Vec<&'a str>;
+
+self_cell!(
+    struct NoCovarianceAttr {
+        owner: String,
+
+        dependent: Ast,
+    }
+);
+
+fn main() {}
@@ -0,0 +1,31 @@
+use self_cell::{self_cell, OwnerMut};
+
+type Handle<'a> = OwnerMut<'a, 'a, Vec<u8>>;
+
+self_cell!(
+    struct HandleCell {
+        owner: Vec<u8>,
+
+        #[not_covariant]
+        dependent: Handle,
+    }
+);
+
+type Bytes<'a> = &'a mut Vec<u8>;
+
+self_cell!(
+    struct BytesCell {
+        owner: Vec<u8>,
+
+        #[covariant]
+        dependent: Bytes,
+    }
+);
+
+fn main() {
+    // The handle can't be stored, it would allow mutating owner while
+    // borrow_owner hands out shared references.
+    let _handle_cell = HandleCell::new_mut(vec![1, 2], |owner| owner);
+
+    let _bytes_cell = BytesCell::new_mut(vec![1, 2], |mut owner| &mut *owner);
+}
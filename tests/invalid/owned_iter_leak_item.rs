@@ -0,0 +1,15 @@
+use self_cell::{item_family, OwnedIterCell};
+
+item_family!(struct Words<'a> = &'a str);
+
+fn main() {
+    let first = {
+        let words = OwnedIterCell::<String, Words>::new("fox cat".into(), |text| {
+            text.split(' ').collect()
+        });
+
+        *words.iter().next().unwrap()
+    };
+
+    println!("{first}");
+}
@@ -0,0 +1,14 @@
+use self_cell::self_cell;
+
+type Fields<'a> = Vec<&'a [u8]>;
+
+self_cell!(
+    struct FieldsView<'a> {
+        owner: &'a [u8],
+
+        #[covariant]
+        dependent: Fields,
+    }
+);
+
+fn main() {}
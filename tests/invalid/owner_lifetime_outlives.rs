@@ -0,0 +1,20 @@
+use self_cell::self_cell;
+
+type Fields<'a> = Vec<&'a [u8]>;
+
+self_cell!(
+    struct FieldsView<'o> {
+        owner: &'o [u8],
+
+        #[covariant]
+        dependent: Fields,
+    }
+);
+
+fn main() {
+    let view = {
+        let input = vec![b'a', b'b'];
+        FieldsView::new(&input, |owner| vec![&owner[..1]])
+    };
+    assert_eq!(view.borrow_dependent().len(), 1);
+}
@@ -0,0 +1,23 @@
+use self_cell::self_cell;
+
+type Fields<'a> = Vec<&'a [u8]>;
+
+self_cell!(
+    struct XView<'x> {
+        owner: &'x [u8],
+
+        #[covariant]
+        dependent: Fields,
+    }
+);
+
+self_cell!(
+    struct BrandView<'_brand> {
+        owner: &'_brand [u8],
+
+        #[covariant]
+        dependent: Fields,
+    }
+);
+
+fn main() {}
@@ -0,0 +1,19 @@
+use self_cell::self_cell;
+
+type Values<'a> = Vec<&'a mut u32>;
+
+self_cell!(
+    struct ValuesCell {
+        owner: Vec<u32>,
+
+        #[covariant, owner_mut]
+        dependent: Values,
+    }
+);
+
+fn main() {
+    let cell = ValuesCell::new(vec![1, 2], |owner| owner.iter_mut().collect());
+
+    // Would alias the unique references held by the dependent.
+    let _owner: &Vec<u32> = cell.borrow_owner();
+}
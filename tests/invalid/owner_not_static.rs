@@ -0,0 +1,14 @@
+use self_cell::self_cell;
+
+type Ast<'a> = Vec<&'a str>;
+
+self_cell!(
+    struct OwnerNotStatic {
+        owner: &'a str,
+
+        #[covariant]
+        dependent: Ast,
+    }
+);
+
+fn main() {}
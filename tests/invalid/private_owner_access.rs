@@ -0,0 +1,20 @@
+mod cell {
+    use self_cell::self_cell;
+
+    type Ast<'a> = Vec<&'a str>;
+
+    self_cell!(
+        pub struct RestrictedCell {
+            pub(self) owner: String,
+
+            #[covariant]
+            dependent: Ast,
+        }
+    );
+}
+
+fn main() {
+    let cell = cell::RestrictedCell::new("abc".into(), |owner| vec![owner.as_str()]);
+    let _dependent = cell.borrow_dependent();
+    let _owner = cell.borrow_owner();
+}
@@ -0,0 +1,24 @@
+use self_cell::self_cell;
+
+type Ast<'a> = Vec<&'a str>;
+
+self_cell!(
+    #[sealed(ast_cell)]
+    struct AstCell {
+        owner: String,
+
+        #[covariant]
+        dependent: Ast,
+    }
+);
+
+impl AstCell {
+    fn internals(&self) {
+        let _ = &self.unsafe_self_cell;
+    }
+}
+
+fn main() {
+    let cell = AstCell::new("a b".into(), |owner| owner.split(' ').collect());
+    cell.internals();
+}
@@ -0,0 +1,14 @@
+use self_cell::self_cell;
+
+type Ast<'a> = Vec<&'a str>;
+
+self_cell!(
+    struct StringCell {
+        owner: String,
+
+        #[covariant, small_owner]
+        dependent: Ast,
+    }
+);
+
+fn main() {}
@@ -0,0 +1,14 @@
+use self_cell::self_cell;
+
+type Ast<'a> = Vec<&'a str>;
+
+self_cell!(
+    struct UnknownMode {
+        owner: String,
+
+        #[covariant, ref_cell]
+        dependent: Ast,
+    }
+);
+
+fn main() {}
@@ -0,0 +1,21 @@
+use self_cell::self_cell;
+
+type Dependent<'a> = &'a String;
+
+self_cell!(
+    struct LeakMut {
+        owner: String,
+
+        #[covariant]
+        dependent: Dependent,
+    }
+);
+
+fn main() {
+    let mut cell = LeakMut::new("Crackle that thunder".into(), |owner| owner);
+
+    let leaked = cell.with_dependent_mut(|_, dependent| dependent);
+
+    drop(cell);
+    println!("{}", leaked);
+}
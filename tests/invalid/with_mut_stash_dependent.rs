@@ -0,0 +1,24 @@
+use self_cell::self_cell;
+
+type Dependent<'a> = &'a String;
+
+self_cell!(
+    struct StashMut {
+        owner: String,
+
+        #[covariant]
+        dependent: Dependent,
+    }
+);
+
+fn main() {
+    let mut cell = StashMut::new("Crackle that thunder".into(), |owner| owner);
+
+    let mut stash = None;
+    cell.with_dependent_mut(|_, dependent| {
+        stash = Some(*dependent);
+    });
+
+    drop(cell);
+    println!("{:?}", stash);
+}
@@ -0,0 +1,25 @@
+use self_cell::self_cell;
+
+type Dependent<'a> = &'a String;
+
+self_cell!(
+    struct SwapCells {
+        owner: String,
+
+        #[covariant]
+        dependent: Dependent,
+    }
+);
+
+fn main() {
+    let mut cell_a = SwapCells::new("a".into(), |owner| owner);
+    let mut cell_b = SwapCells::new("b".into(), |owner| owner);
+
+    // After the swap each dependent would point into the owner of the other
+    // cell, which dangles once that cell is dropped.
+    cell_a.with_dependent_mut(|_, dependent_a| {
+        cell_b.with_dependent_mut(|_, dependent_b| {
+            core::mem::swap(dependent_a, dependent_b);
+        });
+    });
+}
@@ -1,6 +1,9 @@
 // The unsafe being used gets tested with miri in the CI.
 
-#![deny(private_in_public)]
+// Nearly every cell tested here is heap allocated, without alloc only the
+// doctests run.
+#![cfg(feature = "alloc")]
+#![deny(private_interfaces, private_bounds)]
 
 use std::fmt::Debug;
 use std::marker::PhantomData;
@@ -54,7 +57,7 @@ impl PackedAst {
         }
     }
 
-    fn get_body<'a>(&'a self) -> &'a String {
+    fn get_body(&self) -> &String {
         self.ast_cell.borrow_owner()
     }
 
@@ -186,7 +189,7 @@ fn from_fn() {
 
     let expected_str = "small pink bike";
 
-    let fn_cell = FnCell::new(expected_str.clone().into(), |owner| {
+    let fn_cell = FnCell::new(expected_str.into(), |owner| {
         // Make sure it only gets called once.
         extra_outside_state = if let Some(x) = extra_outside_state {
             Some(x + 5)
@@ -203,6 +206,100 @@ fn from_fn() {
     assert_eq!(extra_outside_state, Some(66));
 }
 
+#[test]
+fn builder_panic_cleanup() {
+    use std::panic::AssertUnwindSafe;
+
+    // Holds a resource and a reference into owner, like a partially built
+    // dependent would.
+    #[allow(dead_code)]
+    struct Parsed<'a> {
+        resource: Rc<()>,
+        words: Vec<&'a str>,
+    }
+
+    self_cell!(
+        struct ParsedCell {
+            owner: Rc<String>,
+
+            #[covariant]
+            dependent: Parsed,
+        }
+    );
+
+    let owner = Rc::new(String::from("a b c"));
+    let resource = Rc::new(());
+
+    // Creates parts of the dependent and then panics.
+    fn panicking_builder<'a>(owner: &'a Rc<String>, resource: &Rc<()>) -> Parsed<'a> {
+        let _partial = Parsed {
+            resource: resource.clone(),
+            words: owner.split(' ').collect(),
+        };
+        let _more_resource = resource.clone();
+        assert_eq!(Rc::strong_count(resource), 3);
+        panic!("builder failed");
+    }
+
+    let assert_cleaned_up = || {
+        assert_eq!(Rc::strong_count(&owner), 1);
+        assert_eq!(Rc::strong_count(&resource), 1);
+    };
+
+    let result = catch_unwind(AssertUnwindSafe(|| {
+        ParsedCell::new(owner.clone(), |owner| panicking_builder(owner, &resource))
+    }));
+    assert!(result.is_err());
+    assert_cleaned_up();
+
+    let result = catch_unwind(AssertUnwindSafe(|| {
+        ParsedCell::try_new::<()>(owner.clone(), |owner| {
+            Ok(panicking_builder(owner, &resource))
+        })
+    }));
+    assert!(result.is_err());
+    assert_cleaned_up();
+
+    let result = catch_unwind(AssertUnwindSafe(|| {
+        ParsedCell::try_new_or_recover::<()>(owner.clone(), |owner| {
+            Ok(panicking_builder(owner, &resource))
+        })
+    }));
+    assert!(result.is_err());
+    assert_cleaned_up();
+
+    // Panics in the second attempt, after the first one failed.
+    let mut attempts = 0;
+    let result = catch_unwind(AssertUnwindSafe(|| {
+        ParsedCell::try_new_with_retry(
+            owner.clone(),
+            |owner| {
+                attempts += 1;
+                if attempts == 1 {
+                    Err(())
+                } else {
+                    Ok(panicking_builder(owner, &resource))
+                }
+            },
+            |_, _| true,
+        )
+    }));
+    assert!(result.is_err());
+    assert_eq!(attempts, 2);
+    assert_cleaned_up();
+
+    // Panics in the retry policy.
+    let result = catch_unwind(AssertUnwindSafe(|| {
+        ParsedCell::try_new_with_retry(
+            owner.clone(),
+            |_| Err::<Parsed, _>(()),
+            |_, _| panic!("policy failed"),
+        )
+    }));
+    assert!(result.is_err());
+    assert_cleaned_up();
+}
+
 #[test]
 fn catch_panic_in_from() {
     // This pattern allows users to opt into not leaking memory on panic during
@@ -212,10 +309,12 @@ fn catch_panic_in_from() {
     struct Owner(String);
 
     #[derive(Debug)]
+    #[allow(dead_code)]
     struct PanicCtor<'a>(&'a i32);
 
     impl<'a> PanicCtor<'a> {
         fn new(_: &'a Owner) -> Self {
+            #[allow(clippy::useless_vec)]
             let _stack_vec = vec![23, 44, 5];
             panic!()
         }
@@ -236,7 +335,7 @@ fn catch_panic_in_from() {
     let owner = Owner("This string is no trout".into());
 
     let ast_cell_result = NoLeakCell::try_new(owner.clone(), |owner| {
-        catch_unwind(|| PanicCtor::new(&owner))
+        catch_unwind(|| PanicCtor::new(owner))
     });
     assert!(ast_cell_result.is_err());
 }
@@ -359,6 +458,319 @@ fn custom_drop() {
     assert_eq!(cell.borrow_dependent(), &expected_dependent);
 }
 
+#[test]
+fn drop_order() {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    type Log = Rc<RefCell<Vec<&'static str>>>;
+
+    struct Owner(Log);
+
+    impl Drop for Owner {
+        fn drop(&mut self) {
+            self.0.borrow_mut().push("owner");
+        }
+    }
+
+    struct Dependent<'a>(&'a Owner);
+
+    impl Drop for Dependent<'_> {
+        fn drop(&mut self) {
+            // Reads owner, which must still be alive.
+            (self.0).0.borrow_mut().push("dependent");
+        }
+    }
+
+    self_cell!(
+        struct LogCell {
+            owner: Owner,
+
+            #[covariant]
+            dependent: Dependent,
+        }
+    );
+
+    let log = Log::default();
+
+    drop(LogCell::new(Owner(log.clone()), |owner| Dependent(owner)));
+    assert_eq!(*log.borrow(), ["dependent", "owner"]);
+
+    log.borrow_mut().clear();
+    let owner = LogCell::new(Owner(log.clone()), |owner| Dependent(owner)).into_owner();
+    assert_eq!(*log.borrow(), ["dependent"]);
+    drop(owner);
+    assert_eq!(*log.borrow(), ["dependent", "owner"]);
+}
+
+#[test]
+fn dependent_borrows_owner_struct() {
+    // Borrows owner itself instead of memory it points to, which uncovers
+    // creating unique references to owner while dependent is alive, when
+    // running under miri.
+    type OwnerRef<'a> = &'a (u64, u64);
+
+    self_cell!(
+        struct PairCell {
+            owner: (u64, u64),
+
+            #[covariant]
+            dependent: OwnerRef,
+        }
+    );
+
+    let mut cell = PairCell::new((1, 2), |owner| owner);
+
+    cell.with_dependent_mut(|owner, dependent| {
+        assert_eq!(dependent.0, 1);
+        assert_eq!(owner.1, 2);
+    });
+    assert_eq!(cell.borrow_dependent().1, 2);
+    assert_eq!(cell.into_owner(), (1, 2));
+}
+
+#[test]
+fn new_from_box() {
+    use std::panic::AssertUnwindSafe;
+
+    type Words<'a> = Vec<&'a str>;
+
+    self_cell!(
+        struct BoxedOwnerCell {
+            owner: Rc<String>,
+
+            #[covariant]
+            dependent: Words,
+        }
+    );
+
+    let owner = Rc::new(String::from("fox cat"));
+
+    let cell =
+        BoxedOwnerCell::new_from_box(Box::new(owner.clone()), |owner| owner.split(' ').collect());
+    assert_eq!(cell.borrow_dependent(), &["fox", "cat"]);
+    assert_eq!(Rc::strong_count(&owner), 2);
+    drop(cell);
+    assert_eq!(Rc::strong_count(&owner), 1);
+
+    // Owner is dropped and the grown allocation is freed should building the
+    // dependent panic.
+    let result = catch_unwind(AssertUnwindSafe(|| {
+        BoxedOwnerCell::new_from_box(Box::new(owner.clone()), |_| panic!("build failed"))
+    }));
+    assert!(result.is_err());
+    assert_eq!(Rc::strong_count(&owner), 1);
+
+    let cell = BoxedOwnerCell::new_from_box(Box::new(owner.clone()), |owner| vec![&owner[..3]]);
+    assert_eq!(*cell.into_owner(), "fox cat");
+    assert_eq!(Rc::strong_count(&owner), 1);
+}
+
+#[test]
+fn unsized_owners() {
+    use std::ops::Range;
+    use std::sync::Arc;
+
+    fn payload_range<T>(payload: &[T]) -> Range<*const T> {
+        payload.as_ptr_range()
+    }
+
+    type Words<'a> = Vec<&'a str>;
+
+    self_cell!(
+        struct BoxStrCell {
+            owner: Box<str>,
+
+            #[covariant]
+            dependent: Words,
+        }
+    );
+
+    let cell = BoxStrCell::new_from("fox cat", |text| text.split(' ').collect());
+    let payload = payload_range(cell.borrow_owner().as_bytes());
+
+    // Moving the cell only moves the handle, the borrows point into the
+    // payload of the box.
+    let moved = vec![cell];
+    let cell = Box::new(moved.into_iter().next().unwrap());
+    assert_eq!(cell.borrow_dependent(), &["fox", "cat"]);
+    for word in cell.borrow_dependent() {
+        assert!(payload.contains(&word.as_ptr()));
+    }
+
+    let owner = cell.into_owner();
+    assert_eq!(payload_range(owner.as_bytes()), payload);
+
+    let cell = BoxStrCell::new_from(String::from("dog"), |text| vec![text]);
+    assert_eq!(cell.borrow_dependent(), &["dog"]);
+
+    type Header<'a> = (&'a [u8], &'a [u8]);
+
+    self_cell!(
+        struct PacketCell {
+            owner: Box<[u8]>,
+
+            #[covariant]
+            dependent: Header,
+        }
+    );
+
+    let cell = PacketCell::new_from(vec![2, 7, 7, 9], |bytes| bytes.split_at(1));
+    let payload = payload_range(cell.borrow_owner());
+    let (len, body) = cell.borrow_dependent();
+    assert_eq!((*len, *body), (&[2][..], &[7, 7, 9][..]));
+    assert!(payload.contains(&body.as_ptr()));
+
+    type Token<'a> = &'a str;
+
+    self_cell!(
+        struct ArcStrCell {
+            owner: Arc<str>,
+
+            #[covariant]
+            dependent: Token,
+        }
+
+        impl {Debug}
+    );
+
+    // Cells sharing one payload, each borrowing from its own clone of the Arc.
+    let text: Arc<str> = Arc::from("fox cat");
+    let fox = ArcStrCell::new(text.clone(), |text| &text[..3]);
+    let cat = ArcStrCell::new_from(text.clone(), |text| &text[4..]);
+    assert_eq!(
+        (*fox.borrow_dependent(), *cat.borrow_dependent()),
+        ("fox", "cat")
+    );
+    assert_eq!(cat.borrow_dependent().as_ptr(), text[4..].as_ptr());
+    assert_eq!(Arc::strong_count(&text), 3);
+
+    drop((fox, cat));
+    assert_eq!(Arc::strong_count(&text), 1);
+}
+
+#[test]
+fn new_mut() {
+    type Sorted<'a> = Vec<&'a str>;
+
+    self_cell!(
+        struct SortedWords {
+            owner: Vec<String>,
+
+            #[covariant]
+            dependent: Sorted,
+        }
+    );
+
+    let words = vec!["fox".to_string(), "cat".into(), "dog".into()];
+    let cell = SortedWords::new_mut(words, |mut owner| {
+        owner.sort();
+        owner.push("elk".into());
+
+        let owner = owner.into_ref();
+        owner.iter().map(String::as_str).collect()
+    });
+
+    assert_eq!(cell.borrow_dependent(), &["cat", "dog", "fox", "elk"]);
+    assert_eq!(cell.borrow_owner().len(), 4);
+    assert_eq!(
+        cell.borrow_owner()[0].as_ptr(),
+        cell.borrow_dependent()[0].as_ptr()
+    );
+
+    // The dependent can also borrow the owner struct itself.
+    type OwnerRef<'a> = &'a (u64, u64);
+
+    self_cell!(
+        struct PairCell {
+            owner: (u64, u64),
+
+            #[covariant]
+            dependent: OwnerRef,
+        }
+    );
+
+    let mut cell = PairCell::new_mut((1, 2), |mut owner| {
+        owner.0 = 10;
+        owner.into_ref()
+    });
+    cell.with_dependent_mut(|owner, dependent| assert_eq!(dependent.0, owner.0));
+    assert_eq!(cell.borrow_dependent(), &&(10, 2));
+    assert_eq!(cell.into_owner(), (10, 2));
+}
+
+#[test]
+fn owner_mut_mode() {
+    use std::cell::Cell;
+
+    #[derive(Debug, PartialEq)]
+    struct Halves<'a> {
+        left: &'a mut [u32],
+        right: &'a mut [u32],
+    }
+
+    self_cell!(
+        struct HalvesCell {
+            owner: Vec<u32>,
+
+            #[covariant, owner_mut]
+            dependent: Halves,
+        }
+
+        impl {From(|owner: &mut Vec<u32>| {
+            let (left, right) = owner.split_at_mut(1);
+            Halves { left, right }
+        })}
+    );
+
+    let mut cell = HalvesCell::from(vec![1, 2, 3]);
+    assert_eq!(cell.borrow_dependent().right, &[2, 3]);
+
+    cell.with_dependent_mut(|halves| {
+        halves.left[0] = 10;
+        halves.right[1] = 30;
+    });
+    cell.with_dependent(|halves| assert_eq!(halves.left, &[10]));
+    assert_eq!(cell.into_owner(), [10, 2, 30]);
+
+    // Unique references to data with the dependent lifetime are invariant.
+    type Slots<'a> = Vec<Cell<Option<&'a mut u32>>>;
+
+    self_cell!(
+        struct SlotsCell {
+            owner: Vec<u32>,
+
+            #[not_covariant, owner_mut]
+            dependent: Slots,
+        }
+    );
+
+    let mut cell = SlotsCell::new(vec![1, 2], |owner| {
+        owner
+            .iter_mut()
+            .map(|value| Cell::new(Some(value)))
+            .collect()
+    });
+    cell.with_dependent_mut(|slots| {
+        let first = slots[0].take().unwrap();
+        *first += 1;
+        slots[1].set(Some(first));
+    });
+    cell.with_dependent(|slots| assert!(slots[0].take().is_none()));
+    assert_eq!(cell.into_owner(), [2, 2]);
+
+    let err = SlotsCell::try_new_or_recover(vec![1], |owner| {
+        owner.push(2);
+        Err::<Slots, _>("no slots")
+    });
+    assert_eq!(err.err(), Some((vec![1, 2], "no slots")));
+
+    let cell = SlotsCell::try_new(vec![1], |owner| {
+        Ok::<_, ()>(vec![Cell::new(owner.first_mut())])
+    });
+    assert!(cell.is_ok());
+}
+
 #[test]
 fn dependent_mutate() {
     let mut ast_cell = PackedAstCell::new("Egal in welchen Farben ihr den ..".into(), |owner| {
@@ -443,8 +855,139 @@ fn into_owner() {
     // assert_eq!(ast_cell.borrow_owner(), &expected_body);
 }
 
+#[test]
+fn into_owner_and() {
+    use std::panic::AssertUnwindSafe;
+
+    #[derive(Debug, PartialEq)]
+    struct Stats {
+        words: usize,
+        longest: usize,
+    }
+
+    struct Parsed<'a> {
+        words: Vec<&'a str>,
+        stats: Stats,
+    }
+
+    self_cell!(
+        struct ParsedCell {
+            owner: Rc<String>,
+
+            #[covariant]
+            dependent: Parsed,
+        }
+    );
+
+    fn parse(owner: &Rc<String>) -> Parsed<'_> {
+        let words: Vec<&str> = owner.split(' ').collect();
+        let stats = Stats {
+            words: words.len(),
+            longest: words.iter().map(|word| word.len()).max().unwrap_or(0),
+        };
+        Parsed { words, stats }
+    }
+
+    let body = Rc::new(String::from("fox jumps over"));
+
+    let cell = ParsedCell::new(Rc::clone(&body), parse);
+    let (owner, stats) = cell.into_owner_and(|parsed| {
+        // The dependent can still be used here, owner is moved out afterwards.
+        assert_eq!(parsed.words, ["fox", "jumps", "over"]);
+        parsed.stats
+    });
+    assert!(Rc::ptr_eq(&owner, &body));
+    assert_eq!(
+        stats,
+        Stats {
+            words: 3,
+            longest: 5
+        }
+    );
+    drop(owner);
+    assert_eq!(Rc::strong_count(&body), 1);
+
+    // Should func panic, owner is dropped.
+    let cell = ParsedCell::new(Rc::clone(&body), parse);
+    let result = catch_unwind(AssertUnwindSafe(|| {
+        cell.into_owner_and(|_| panic!("salvaging failed"))
+    }));
+    assert!(result.is_err());
+    assert_eq!(Rc::strong_count(&body), 1);
+}
+
+#[test]
+fn swap() {
+    use std::panic::AssertUnwindSafe;
+
+    struct Indexed<'a> {
+        words: Vec<&'a str>,
+        generation: usize,
+    }
+
+    self_cell!(
+        struct IndexedCell {
+            owner: String,
+
+            #[covariant]
+            dependent: Indexed,
+        }
+    );
+
+    #[allow(clippy::ptr_arg)]
+    fn index(owner: &String) -> Indexed<'_> {
+        Indexed {
+            words: owner.split(' ').collect(),
+            generation: 0,
+        }
+    }
+
+    let mut front = IndexedCell::new("a b".into(), index);
+    let mut back = IndexedCell::new("c d e".into(), index);
+    let front_owner_ptr = front.borrow_owner().as_ptr();
+    let back_owner_ptr = back.borrow_owner().as_ptr();
+
+    // Neither owner nor dependent are moved.
+    front.swap(&mut back);
+    assert_eq!(front.borrow_owner().as_ptr(), back_owner_ptr);
+    assert_eq!(back.borrow_owner().as_ptr(), front_owner_ptr);
+    assert_eq!(front.borrow_dependent().words, ["c", "d", "e"]);
+    assert_eq!(back.borrow_dependent().words, ["a", "b"]);
+    assert_eq!(
+        front.borrow_dependent().words[0].as_ptr(),
+        front.borrow_owner().as_ptr()
+    );
+
+    let mut generation = 0;
+    front.swap_and_rebuild(&mut back, |owner| {
+        generation += 1;
+        Indexed {
+            words: owner.split(' ').rev().collect(),
+            generation,
+        }
+    });
+    assert_eq!(front.borrow_owner(), "a b");
+    assert_eq!(front.borrow_dependent().words, ["b", "a"]);
+    assert_eq!(front.borrow_dependent().generation, 1);
+    assert_eq!(back.borrow_owner(), "c d e");
+    assert_eq!(back.borrow_dependent().words, ["e", "d", "c"]);
+    assert_eq!(back.borrow_dependent().generation, 2);
+
+    // Should the builder panic, the cells stay swapped and each keeps the
+    // dependent that moved with it.
+    let result = catch_unwind(AssertUnwindSafe(|| {
+        front.swap_and_rebuild(&mut back, |_| panic!("rebuild failed"))
+    }));
+    assert!(result.is_err());
+    assert_eq!(front.borrow_owner(), "c d e");
+    assert_eq!(front.borrow_dependent().words, ["e", "d", "c"]);
+    assert_eq!(back.borrow_owner(), "a b");
+    assert_eq!(back.borrow_dependent().words, ["b", "a"]);
+}
+
 #[test]
 fn zero_size_cell() {
+    #[derive(Debug, PartialEq)]
     struct ZeroSizeRef<'a>(PhantomData<&'a ()>);
 
     self_cell!(
@@ -454,23 +997,38 @@ fn zero_size_cell() {
             #[covariant]
             dependent: ZeroSizeRef,
         }
+
+        impl {Debug}
     );
 
-    assert!(catch_unwind(|| ZeroSizeCell::new((), |_| ZeroSizeRef(PhantomData))).is_err());
+    let cell = ZeroSizeCell::new((), |_| ZeroSizeRef(PhantomData));
+    assert_eq!(cell.borrow_dependent(), &ZeroSizeRef(PhantomData));
+    assert_eq!(
+        format!("{:?}", cell),
+        "ZeroSizeCell { owner: (), dependent: ZeroSizeRef(PhantomData<&()>) }"
+    );
+    cell.into_owner();
+
+    let cell = ZeroSizeCell::try_new((), |_| -> Result<_, i32> { Ok(ZeroSizeRef(PhantomData)) });
+    assert!(cell.is_ok());
+
+    let cell = ZeroSizeCell::try_new_or_recover((), |_| -> Result<ZeroSizeRef, i32> { Err(-1) });
+    assert_eq!(cell.unwrap_err(), ((), -1));
+
+    // Only one of both being zero sized still needs memory.
+    type Words<'a> = Vec<&'a str>;
+
+    self_cell!(
+        struct ZeroSizeOwnerCell {
+            owner: (),
 
-    assert!(
-        catch_unwind(|| ZeroSizeCell::try_new((), |_| -> Result<_, i32> {
-            Ok(ZeroSizeRef(PhantomData))
-        }))
-        .is_err()
+            #[covariant]
+            dependent: Words,
+        }
     );
 
-    assert!(catch_unwind(
-        || ZeroSizeCell::try_new_or_recover((), |_| -> Result<_, i32> {
-            Ok(ZeroSizeRef(PhantomData))
-        })
-    )
-    .is_err());
+    let cell = ZeroSizeOwnerCell::new((), |_| vec!["a", "b"]);
+    assert_eq!(cell.borrow_dependent().len(), 2);
 }
 
 #[test]
@@ -583,12 +1141,2625 @@ fn lazy_ast() {
     });
 }
 
+#[cfg(feature = "std")]
 #[test]
-fn cell_mem_size() {
-    use std::mem::size_of;
+fn rw_lock_dependent() {
+    #[derive(Debug, Default)]
+    struct Memo<'a> {
+        words: Vec<&'a str>,
+        longest: Option<&'a str>,
+    }
 
-    assert_eq!(size_of::<PackedAstCell>(), size_of::<*const u8>());
-    assert_eq!(size_of::<Option<PackedAstCell>>(), size_of::<*const u8>());
+    self_cell!(
+        struct MemoCell {
+            owner: String,
+
+            #[covariant, rw_lock]
+            dependent: Memo,
+        }
+
+        impl {Debug}
+    );
+
+    let memo_cell = MemoCell::new("fox jumps over lazy dog".into(), |owner| Memo {
+        words: owner.split(' ').collect(),
+        longest: None,
+    });
+
+    assert!(impls!(MemoCell: Send & Sync));
+    assert!(!impls!(NotSendCell: Send | Sync));
+
+    thread::scope(|s| {
+        for _ in 0..3 {
+            s.spawn(|_| {
+                let longest_len = memo_cell.write_dependent(|_, memo| {
+                    let words = &memo.words;
+                    memo.longest
+                        .get_or_insert_with(|| words.iter().max_by_key(|w| w.len()).unwrap())
+                        .len()
+                });
+                assert_eq!(longest_len, 5);
+            });
+        }
+    })
+    .unwrap();
+
+    memo_cell.with_dependent(|owner, memo| {
+        assert_eq!(owner, "fox jumps over lazy dog");
+        assert_eq!(memo.longest, Some("jumps"));
+    });
+
+    assert_eq!(
+        format!("{:?}", &memo_cell),
+        "MemoCell { owner: \"fox jumps over lazy dog\", dependent: Memo { words: [\"fox\", \"jumps\", \"over\", \"lazy\", \"dog\"], longest: Some(\"jumps\") } }"
+    );
+}
+
+#[test]
+fn build_error() {
+    use self_cell::BuildError;
+
+    let err: BuildError<i32, String> =
+        PackedAstCell::try_new_or_recover("a b".into(), |_| Err::<Ast, _>(-1))
+            .map_err(BuildError::from)
+            .unwrap_err();
+    assert_eq!(*err.error(), -1);
+    assert_eq!(err.owner().map(String::as_str), Some("a b"));
+    assert_eq!(err.to_string(), "building the dependent failed: -1");
+    assert_eq!(
+        format!("{:?}", err),
+        "BuildError { error: -1, owner_recovered: true }"
+    );
+    assert_eq!(err.into_parts(), (-1, Some(String::from("a b"))));
+
+    let err = PackedAstCell::try_new("a b".into(), |_| Err::<Ast, _>(-2))
+        .map_err(BuildError::<_, String>::new)
+        .unwrap_err();
+    assert_eq!(err.owner(), None);
+    assert_eq!(err.into_error(), -2);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn build_error_propagation() {
+    use std::error::Error;
+    use std::fmt;
+
+    use self_cell::BuildError;
+
+    #[derive(Debug)]
+    struct ParseError;
+
+    impl fmt::Display for ParseError {
+        fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+            fmt.write_str("unexpected end of input")
+        }
+    }
+
+    impl Error for ParseError {}
+
+    fn parse(input: &str) -> Result<PackedAstCell, Box<dyn Error + Send + Sync>> {
+        let cell = PackedAstCell::try_new_or_recover(input.into(), |owner| {
+            if owner.is_empty() {
+                Err(ParseError)
+            } else {
+                Ok(Ast(owner.split(' ').collect()))
+            }
+        })
+        .map_err(BuildError::from)?;
+        Ok(cell)
+    }
+
+    assert_eq!(
+        parse("a b").unwrap().borrow_dependent(),
+        &Ast(vec!["a", "b"])
+    );
+
+    let err = parse("").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "building the dependent failed: unexpected end of input"
+    );
+    assert!(err.source().unwrap().is::<ParseError>());
+
+    let err = err.downcast::<BuildError<ParseError, String>>().unwrap();
+    assert_eq!(err.into_owner().unwrap(), "");
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn mutex_dependent() {
+    use std::cell::Cell;
+
+    // Cell is not Sync, the Mutex makes the cell Sync again.
+    type Cursor<'a> = Cell<&'a str>;
+
+    self_cell!(
+        struct CursorCell {
+            owner: String,
+
+            #[not_covariant, mutex]
+            dependent: Cursor,
+        }
+    );
+
+    let mut cursor_cell = CursorCell::new("abc def".into(), |owner| Cell::new(&owner[..3]));
+
+    assert!(impls!(CursorCell: Send & Sync));
+
+    cursor_cell.write_dependent(|owner, cursor| cursor.set(&owner[4..]));
+    cursor_cell.with_dependent(|_, cursor| assert_eq!(cursor.get(), "def"));
+
+    cursor_cell.with_dependent_mut(|owner, cursor| cursor.set(owner));
+    cursor_cell.with_dependent(|_, cursor| assert_eq!(cursor.get(), "abc def"));
+
+    assert_eq!(cursor_cell.into_owner(), "abc def");
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn reloadable_self_cell() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    use self_cell::ReloadableSelfCell;
+
+    type Keys<'a> = Vec<&'a str>;
+
+    self_cell!(
+        struct Config {
+            owner: String,
+
+            #[covariant]
+            dependent: Keys,
+        }
+    );
+
+    fn parse(generation: usize) -> Config {
+        Config::new(format!("{0},{0}", generation), |text| {
+            text.split(',').collect()
+        })
+    }
+
+    static CONFIG: ReloadableSelfCell<Config> = ReloadableSelfCell::new();
+
+    assert!(CONFIG.load().is_none());
+    let first = CONFIG.get_or_init(|| parse(0));
+    assert!(Arc::ptr_eq(&first, &CONFIG.get_or_init(|| parse(1))));
+
+    let done = AtomicBool::new(false);
+
+    thread::scope(|s| {
+        for _ in 0..4 {
+            s.spawn(|_| {
+                while !done.load(Ordering::Acquire) {
+                    // Owner and dependent of a snapshot always belong together.
+                    let snapshot = CONFIG.load().unwrap();
+                    let keys = snapshot.borrow_dependent();
+                    assert_eq!(keys.len(), 2);
+                    assert_eq!(keys[0], keys[1]);
+                    assert_eq!(format!("{},{}", keys[0], keys[1]), *snapshot.borrow_owner());
+                }
+            });
+        }
+
+        for generation in 1..50 {
+            CONFIG.store(parse(generation));
+        }
+        done.store(true, Ordering::Release);
+    })
+    .unwrap();
+
+    // Replacing the cell leaves existing snapshots untouched.
+    assert_eq!(first.borrow_dependent(), &vec!["0", "0"]);
+    assert_eq!(CONFIG.load().unwrap().borrow_dependent(), &vec!["49", "49"]);
+
+    let last = CONFIG.take().unwrap();
+    assert_eq!(Arc::strong_count(&last), 1);
+    assert!(CONFIG.load().is_none());
+}
+
+#[test]
+fn static_storage() {
+    use self_cell::JoinedStorage;
+
+    self_cell!(
+        struct StaticAstCell {
+            owner: Rc<String>,
+
+            #[covariant, static_storage]
+            dependent: Ast,
+        }
+
+        impl {Debug, PartialEq, Eq}
+    );
+
+    type Storage = JoinedStorage<Rc<String>, Ast<'static>>;
+
+    // Every expansion is a new static, which is only ever borrowed once.
+    macro_rules! static_storage {
+        () => {{
+            static mut STORAGE: Storage = Storage::new();
+            unsafe { &mut *std::ptr::addr_of_mut!(STORAGE) }
+        }};
+    }
+
+    let body = Rc::new(String::from("Ein Hund kam in die Küche"));
+    let expected_ast = Ast::from(&*body);
+
+    let cell = StaticAstCell::new_in(static_storage!(), Rc::clone(&body), |owner| {
+        Ast::from(&**owner)
+    });
+    assert_eq!(cell.borrow_owner(), &body);
+    assert_eq!(cell.borrow_dependent(), &expected_ast);
+    assert_eq!(Rc::strong_count(&body), 2);
+
+    // Owner is dropped with the cell, even though the storage is not freed.
+    drop(cell);
+    assert_eq!(Rc::strong_count(&body), 1);
+
+    let cell = StaticAstCell::new_in(static_storage!(), Rc::clone(&body), |owner| {
+        Ast::from(&**owner)
+    });
+    assert_eq!(cell.into_owner(), body);
+    assert_eq!(Rc::strong_count(&body), 1);
+
+    let cell = StaticAstCell::new_in(static_storage!(), Rc::clone(&body), |owner| {
+        Ast::from(&**owner)
+    });
+    let (owner, words) = cell.into_owner_and(|ast| ast.0.len());
+    assert_eq!(owner, body);
+    assert_eq!(words, expected_ast.0.len());
+    drop(owner);
+    assert_eq!(Rc::strong_count(&body), 1);
+
+    let err = StaticAstCell::try_new_in(static_storage!(), Rc::clone(&body), |_| Err(-1));
+    assert_eq!(err.unwrap_err(), -1);
+    assert_eq!(Rc::strong_count(&body), 1);
+
+    let (owner, err) =
+        StaticAstCell::try_new_or_recover_in(static_storage!(), Rc::clone(&body), |_| Err(-2))
+            .unwrap_err();
+    assert_eq!(owner, body);
+    assert_eq!(err, -2);
+    assert_eq!(Rc::strong_count(&body), 2);
+}
+
+#[derive(Debug, PartialEq)]
+enum ResolveError {
+    Transient,
+    Permanent,
+}
+
+// Fails with Transient the first fail_count times.
+fn flaky_resolve<'a>(
+    owner: &'a String,
+    attempts: &mut usize,
+    fail_count: usize,
+) -> Result<Ast<'a>, ResolveError> {
+    *attempts += 1;
+    if *attempts <= fail_count {
+        Err(ResolveError::Transient)
+    } else {
+        Ok(owner.into())
+    }
+}
+
+#[test]
+fn try_new_with_retry() {
+    let retry_transient = |err: &ResolveError, failed_attempts: usize| {
+        *err == ResolveError::Transient && failed_attempts < 3
+    };
+
+    let mut attempts = 0;
+    let cell = PackedAstCell::try_new_with_retry(
+        "a=b+c".into(),
+        |owner| flaky_resolve(owner, &mut attempts, 2),
+        retry_transient,
+    )
+    .unwrap();
+    assert_eq!(attempts, 3);
+    assert_eq!(cell.borrow_dependent(), &Ast(vec!["b+c", "=b"]));
+
+    let mut attempts = 0;
+    let (owner, err) = PackedAstCell::try_new_with_retry(
+        "a=b+c".into(),
+        |owner| flaky_resolve(owner, &mut attempts, 5),
+        retry_transient,
+    )
+    .unwrap_err();
+    assert_eq!(attempts, 3);
+    assert_eq!(owner, "a=b+c");
+    assert_eq!(err, ResolveError::Transient);
+
+    let mut attempts = 0;
+    let (_, err) = PackedAstCell::try_new_with_retry(
+        "a=b+c".into(),
+        |_| {
+            attempts += 1;
+            Err::<Ast, _>(ResolveError::Permanent)
+        },
+        retry_transient,
+    )
+    .unwrap_err();
+    assert_eq!(attempts, 1);
+    assert_eq!(err, ResolveError::Permanent);
+}
+
+#[cfg(feature = "async")]
+#[test]
+fn try_new_with_retry_async() {
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+    fn poll_once<F: Future>(future: Pin<&mut F>) -> Poll<F::Output> {
+        fn noop_raw_waker() -> RawWaker {
+            fn clone(_: *const ()) -> RawWaker {
+                noop_raw_waker()
+            }
+            fn noop(_: *const ()) {}
+
+            static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+            RawWaker::new(std::ptr::null(), &VTABLE)
+        }
+
+        let waker = unsafe { Waker::from_raw(noop_raw_waker()) };
+        future.poll(&mut Context::from_waker(&waker))
+    }
+
+    // The builder futures are always ready, so polling once is enough.
+    fn block_on<F: Future>(future: F) -> F::Output {
+        match poll_once(Box::pin(future).as_mut()) {
+            Poll::Ready(output) => output,
+            Poll::Pending => panic!("future not ready"),
+        }
+    }
+
+    fn assert_send<T: Send>(value: T) -> T {
+        value
+    }
+
+    let mut attempts = 0;
+    let cell = block_on(assert_send(PackedAstCell::try_new_with_retry_async(
+        "a=b+c".into(),
+        |owner| {
+            let result = flaky_resolve(owner, &mut attempts, 1);
+            Box::pin(async move { result })
+        },
+        |_, failed_attempts| failed_attempts < 2,
+    )))
+    .unwrap();
+    assert_eq!(attempts, 2);
+    assert_eq!(cell.borrow_dependent(), &Ast(vec!["b+c", "=b"]));
+
+    let mut attempts = 0;
+    let (owner, err) = block_on(PackedAstCell::try_new_with_retry_async(
+        "a=b+c".into(),
+        |owner| {
+            let result = flaky_resolve(owner, &mut attempts, 5);
+            Box::pin(async move { result })
+        },
+        |_, failed_attempts| failed_attempts < 2,
+    ))
+    .unwrap_err();
+    assert_eq!(attempts, 2);
+    assert_eq!(owner, "a=b+c");
+    assert_eq!(err, ResolveError::Transient);
+
+    self_cell!(
+        struct RcAstCell {
+            owner: Rc<String>,
+
+            #[covariant]
+            dependent: Ast,
+        }
+
+        impl {Debug}
+    );
+
+    // Dropping the constructor future while the builder is pending drops
+    // owner.
+    let owner = Rc::new(String::from("a=b+c"));
+    let mut pending = Box::pin(RcAstCell::try_new_with_retry_async(
+        owner.clone(),
+        |_| Box::pin(std::future::pending::<Result<Ast, ()>>()),
+        |_, _| false,
+    ));
+    assert!(poll_once(pending.as_mut()).is_pending());
+    assert_eq!(Rc::strong_count(&owner), 2);
+    drop(pending);
+    assert_eq!(Rc::strong_count(&owner), 1);
+
+    // The local constructors take builder futures borrowing owners that
+    // aren't Sync.
+    let cell = block_on(RcAstCell::try_new_with_retry_async_local(
+        owner.clone(),
+        |owner| Box::pin(async move { Ok::<_, ()>(Ast(owner.split('+').collect())) }),
+        |_, _| false,
+    ))
+    .unwrap();
+    assert_eq!(cell.borrow_dependent(), &Ast(vec!["a=b", "c"]));
+
+    let (owner, err) = block_on(RcAstCell::try_new_with_ctx_async_local(
+        owner,
+        ResolveError::Transient,
+        |owner, err| {
+            Box::pin(async move {
+                assert_eq!(owner.len(), 5);
+                Err::<Ast, _>(err)
+            })
+        },
+    ))
+    .unwrap_err();
+    assert_eq!(Rc::strong_count(&owner), 2);
+    assert_eq!(err, ResolveError::Transient);
+    drop(cell);
+
+    let (owner, err) = block_on(assert_send(PackedAstCell::try_new_with_ctx_async(
+        "a=b+c".into(),
+        ResolveError::Permanent,
+        |_, err| Box::pin(async move { Err::<Ast, _>(err) }),
+    )))
+    .unwrap_err();
+    assert_eq!(owner, "a=b+c");
+    assert_eq!(err, ResolveError::Permanent);
+
+    let cell = block_on(PackedAstCell::try_new_with_ctx_async(
+        "a b".into(),
+        ' ',
+        |owner, separator| {
+            Box::pin(async move { Ok::<_, ()>(Ast(owner.split(separator).collect())) })
+        },
+    ))
+    .unwrap();
+    assert_eq!(cell.borrow_dependent(), &Ast(vec!["a", "b"]));
+}
+
+#[test]
+fn new_with_ctx() {
+    // Named builders can't capture, everything besides owner comes in via ctx.
+    #[allow(clippy::ptr_arg)]
+    fn split_by<'a>(owner: &'a String, separator: char) -> Ast<'a> {
+        Ast(owner.split(separator).collect())
+    }
+
+    #[allow(clippy::ptr_arg)]
+    fn split_non_empty<'a>(owner: &'a String, separator: char) -> Result<Ast<'a>, String> {
+        if owner.is_empty() {
+            Err(format!("nothing to split by {:?}", separator))
+        } else {
+            Ok(split_by(owner, separator))
+        }
+    }
+
+    let cell = PackedAstCell::new_with_ctx("a,b".into(), ',', split_by);
+    assert_eq!(cell.borrow_dependent(), &Ast(vec!["a", "b"]));
+
+    let cell = PackedAstCell::try_new_with_ctx("a b".into(), ' ', split_non_empty).unwrap();
+    assert_eq!(cell.borrow_dependent(), &Ast(vec!["a", "b"]));
+
+    let err = PackedAstCell::try_new_with_ctx(String::new(), ' ', split_non_empty).unwrap_err();
+    assert_eq!(err, "nothing to split by ' '");
+
+    let (owner, err) =
+        PackedAstCell::try_new_or_recover_with_ctx(String::new(), ';', split_non_empty)
+            .unwrap_err();
+    assert_eq!(owner, "");
+    assert_eq!(err, "nothing to split by ';'");
+
+    // Ctx is moved into the builder, it doesn't have to be Copy or Clone.
+    struct Interner(Vec<String>);
+
+    let owner = String::from("x y");
+    let cell = PackedAstCell::new_with_ctx(owner, Interner(vec!["x".into()]), |owner, interner| {
+        Ast(owner
+            .split(' ')
+            .filter(|word| !interner.0.iter().any(|known| known == word))
+            .collect())
+    });
+    assert_eq!(cell.borrow_dependent(), &Ast(vec!["y"]));
+}
+
+#[cfg(feature = "stable_deref_trait")]
+#[test]
+fn stable_deref() {
+    use std::sync::Arc;
+
+    type Fields<'a> = Vec<&'a [u8]>;
+
+    self_cell!(
+        struct FieldsCell {
+            owner: Arc<[u8]>,
+
+            #[covariant, stable_deref]
+            dependent: Fields,
+        }
+
+        impl {Debug, PartialEq}
+    );
+
+    let data: Arc<[u8]> = Arc::from(&b"fox,cat"[..]);
+    let cell = FieldsCell::new(data.clone(), |bytes| bytes.split(|&b| b == b',').collect());
+    assert_eq!(cell.borrow_dependent(), &[&b"fox"[..], &b"cat"[..]]);
+
+    // The dependent borrows the shared data, not the Arc in the cell.
+    assert!(std::ptr::eq(
+        cell.borrow_dependent()[0].as_ptr(),
+        data.as_ptr()
+    ));
+    assert!(Arc::ptr_eq(cell.borrow_owner(), &data));
+
+    let err = FieldsCell::try_new_or_recover(data.clone(), |_| Err("nope")).unwrap_err();
+    assert!(Arc::ptr_eq(&err.0, &data));
+    assert_eq!(err.1, "nope");
+
+    let mut other =
+        FieldsCell::try_new(Arc::from(&b"dog"[..]), |bytes| Ok::<_, ()>(vec![bytes])).unwrap();
+    other.swap_and_rebuild(
+        &mut FieldsCell::new(data.clone(), |_| Vec::new()),
+        |bytes| bytes.chunks(3).collect(),
+    );
+    assert_eq!(other.borrow_dependent().len(), 3);
+
+    drop((cell, other, err));
+    assert_eq!(Arc::strong_count(&data), 1);
+
+    type Words<'a> = Vec<&'a str>;
+
+    self_cell!(
+        struct WordsCell {
+            owner: Box<str>,
+
+            #[covariant, stable_deref]
+            dependent: Words,
+        }
+    );
+
+    let build = WordsCell::builder(|text| text.split(' ').collect());
+    let cell = WordsCell::new("a b".into(), build);
+    assert_eq!(cell.borrow_dependent(), &["a", "b"]);
+    assert_eq!(&*cell.into_owner(), "a b");
+
+    // The wrapping constructors pass the deref target too.
+    let cell = WordsCell::new_from("a b c", |text| text.split(' ').collect());
+    assert_eq!(cell.borrow_dependent(), &["a", "b", "c"]);
+    let cell = WordsCell::new_from_box(Box::new("d e".into()), |text| text.split(' ').collect());
+    assert_eq!(cell.borrow_dependent(), &["d", "e"]);
+    let cell = WordsCell::try_new_fallible_alloc("f".into(), |text| vec![text]).unwrap();
+    assert_eq!(cell.borrow_dependent(), &["f"]);
+    let cell = WordsCell::new_with_ctx("g,h".into(), ',', |text, sep| text.split(sep).collect());
+    assert_eq!(cell.borrow_dependent(), &["g", "h"]);
+    let result = WordsCell::try_new_or_recover_with_ctx("i".into(), "bad", |_, ctx| Err(ctx));
+    assert!(matches!(result, Err((owner, "bad")) if &*owner == "i"));
+    let cell = WordsCell::new_two_phase(
+        "j k".into(),
+        |text| text.len(),
+        |text, len| vec![&text[..len - 2]],
+    );
+    assert_eq!(cell.borrow_dependent(), &["j"]);
+}
+
+#[test]
+fn drop_finalizer() {
+    use std::cell::RefCell;
+    use std::panic::AssertUnwindSafe;
+    use std::rc::Rc;
+
+    struct LogFile {
+        name: String,
+        flushed: Rc<RefCell<Vec<String>>>,
+    }
+
+    struct Wal<'a> {
+        name: &'a str,
+        pending: Vec<String>,
+    }
+
+    fn flush<'a>(file: &'a LogFile, wal: Wal<'a>) {
+        assert_eq!(wal.name, file.name);
+        file.flushed.borrow_mut().extend(wal.pending);
+    }
+
+    self_cell!(
+        struct WalCell {
+            owner: LogFile,
+
+            #[covariant]
+            dependent: Wal,
+        }
+
+        impl {Drop(flush)}
+    );
+
+    let flushed = Rc::new(RefCell::new(Vec::new()));
+    let file = LogFile {
+        name: "wal".into(),
+        flushed: flushed.clone(),
+    };
+    let mut cell = WalCell::new(file, |file| Wal {
+        name: &file.name,
+        pending: Vec::new(),
+    });
+    cell.with_dependent_mut(|_, wal| wal.pending.push("a".into()));
+    assert!(flushed.borrow().is_empty());
+
+    drop(cell);
+    assert_eq!(*flushed.borrow(), ["a"]);
+    // Owner was dropped after the finalizer.
+    assert_eq!(Rc::strong_count(&flushed), 1);
+
+    // into_owner doesn't finalize.
+    let file = WalCell::new(
+        LogFile {
+            name: "wal".into(),
+            flushed: flushed.clone(),
+        },
+        |file| Wal {
+            name: &file.name,
+            pending: vec!["b".into()],
+        },
+    )
+    .into_owner();
+    assert_eq!(file.name, "wal");
+    assert_eq!(flushed.borrow().len(), 1);
+
+    // Closures work too, next to other automatic impls.
+    type Count<'a> = &'a Rc<RefCell<usize>>;
+
+    self_cell!(
+        struct CountCell {
+            owner: Rc<RefCell<usize>>,
+
+            #[covariant]
+            dependent: Count,
+        }
+
+        impl {Debug, Drop(|owner, count| {
+            assert!(Rc::ptr_eq(owner, count));
+            *count.borrow_mut() += 1;
+        })}
+    );
+
+    let drops = Rc::new(RefCell::new(0));
+    drop(CountCell::new(drops.clone(), |owner| owner));
+    drop(CountCell::new(drops.clone(), |owner| owner));
+    assert_eq!(*drops.borrow(), 2);
+    assert_eq!(Rc::strong_count(&drops), 1);
+
+    // swap_and_rebuild finalizes both replaced dependents, and only those.
+    let mut front = CountCell::new(drops.clone(), |owner| owner);
+    let mut back = CountCell::new(drops.clone(), |owner| owner);
+    front.swap_and_rebuild(&mut back, |owner| owner);
+    assert_eq!(*drops.borrow(), 4);
+
+    // Should the builder panic, the old dependent stays in the cell and isn't
+    // finalized yet.
+    let result = catch_unwind(AssertUnwindSafe(|| {
+        front.swap_and_rebuild(&mut back, |_| panic!("rebuild failed"))
+    }));
+    assert!(result.is_err());
+    assert_eq!(*drops.borrow(), 4);
+
+    drop(front);
+    drop(back);
+    assert_eq!(*drops.borrow(), 6);
+    assert_eq!(Rc::strong_count(&drops), 1);
+}
+
+#[test]
+fn stable_pointers() {
+    let cell = PackedAstCell::new("fox cat".into(), |owner| Ast(owner.split(' ').collect()));
+
+    let owner_ptr = cell.owner_ptr();
+    let dependent_ptr = cell.dependent_ptr() as *const ();
+    assert!(std::ptr::eq(owner_ptr, cell.borrow_owner()));
+    assert!(std::ptr::eq(
+        dependent_ptr,
+        cell.borrow_dependent() as *const Ast as *const ()
+    ));
+
+    // Neither moving nor swapping the cells moves owner or dependent.
+    let mut boxed = Box::new(cell);
+    let mut other = PackedAstCell::new("dog".into(), |owner| Ast(vec![owner.as_str()]));
+    assert_eq!(boxed.owner_ptr(), owner_ptr);
+    boxed.swap(&mut other);
+    assert_eq!(other.owner_ptr(), owner_ptr);
+    assert_eq!(other.dependent_ptr() as *const (), dependent_ptr);
+
+    // The pointers can be read as long as the cell exists.
+    assert_eq!(unsafe { &*owner_ptr }, "fox cat");
+    assert_eq!(unsafe { &*other.dependent_ptr() }, &Ast(vec!["fox", "cat"]));
+
+    // owner_mut cells only hand out the dependent.
+    type Chunks<'a> = Vec<&'a mut [u8]>;
+
+    self_cell!(
+        struct ChunksCell {
+            owner: Vec<u8>,
+
+            #[not_covariant, owner_mut]
+            dependent: Chunks,
+        }
+    );
+
+    let mut cell = ChunksCell::new(vec![1, 2, 3], |owner| owner.chunks_mut(2).collect());
+    let dependent_ptr = cell.dependent_ptr() as *const ();
+    cell.with_dependent_mut(|chunks| {
+        assert!(std::ptr::eq(
+            chunks as *const Chunks as *const (),
+            dependent_ptr
+        ));
+    });
+}
+
+#[test]
+fn small_owner() {
+    use std::mem::size_of;
+
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    struct Config {
+        id: u64,
+        name: [u8; 8],
+    }
+
+    type Name<'a> = &'a [u8];
+
+    self_cell!(
+        struct ConfigCell {
+            owner: Config,
+
+            #[covariant, small_owner]
+            dependent: Name,
+        }
+
+        impl {Debug, PartialEq}
+    );
+
+    let config = Config {
+        id: 7,
+        name: *b"fox\0\0\0\0\0",
+    };
+    let cell = ConfigCell::new(config, |config| {
+        let len = config.name.iter().position(|&b| b == 0).unwrap();
+        &config.name[..len]
+    });
+
+    assert_eq!(cell.borrow_owner(), &config);
+    assert_eq!(cell.borrow_dependent(), b"fox");
+
+    // The owner is read from the copy in the cell, with_dependent passes the
+    // one on the heap that dependent borrows.
+    let owner_in_cell = cell.borrow_owner() as *const Config as usize;
+    let cell_start = &cell as *const ConfigCell as usize;
+    assert!((cell_start..cell_start + size_of::<ConfigCell>()).contains(&owner_in_cell));
+    cell.with_dependent(|owner, name| {
+        assert_eq!(owner, &config);
+        assert!(std::ptr::eq(name.as_ptr(), owner.name.as_ptr()));
+        assert!(!std::ptr::eq(owner, cell.borrow_owner()));
+        assert!(std::ptr::eq(owner, cell.owner_ptr()));
+    });
+
+    assert_eq!(
+        size_of::<ConfigCell>(),
+        size_of::<Config>() + size_of::<PackedAstCell>()
+    );
+
+    // Moving the cell moves the copy, the dependent stays valid.
+    let cells = vec![cell, ConfigCell::new(config, |config| &config.name[..1])];
+    assert_eq!(cells[0], cells[1]);
+    assert_eq!(cells[1].borrow_dependent(), b"f");
+
+    let err = ConfigCell::try_new_or_recover(config, |_| Err("nope")).unwrap_err();
+    assert_eq!(err, (config, "nope"));
+    assert!(ConfigCell::try_new(config, |config| Ok::<_, ()>(&config.name[..])).is_ok());
+
+    let mut cells = cells.into_iter();
+    assert_eq!(cells.next().unwrap().into_owner(), config);
+    let (owner, len) = cells.next().unwrap().into_owner_and(|name| name.len());
+    assert_eq!((owner, len), (config, 1));
+
+    // The other constructors keep the copy in sync with the heap owner too.
+    for cell in [
+        ConfigCell::new_from_box(Box::new(config), |config| &config.name[..2]),
+        ConfigCell::try_new_fallible_alloc(config, |config| &config.name[..2]).unwrap(),
+        ConfigCell::new_from(config, |config| &config.name[..2]),
+        ConfigCell::new_with_ctx(config, 2, |config, len| &config.name[..len]),
+        ConfigCell::try_new_with_ctx(config, 2, |config, len| Ok::<_, ()>(&config.name[..len]))
+            .unwrap(),
+        ConfigCell::new_two_phase(
+            config,
+            |config| config.id as usize - 5,
+            |config, len| &config.name[..len],
+        ),
+    ] {
+        assert_eq!(cell.borrow_owner(), &config);
+        assert_eq!(cell.borrow_dependent(), b"fo");
+        cell.with_dependent(|owner, name| {
+            assert!(std::ptr::eq(name.as_ptr(), owner.name.as_ptr()));
+            assert!(std::ptr::eq(owner, cell.owner_ptr()));
+        });
+    }
+}
+
+#[test]
+fn nested_cells() {
+    use std::rc::Rc;
+
+    type Words<'a> = Vec<&'a str>;
+
+    self_cell!(
+        struct WordsCell {
+            owner: String,
+
+            #[covariant]
+            dependent: Words,
+        }
+    );
+
+    type Longest<'a> = Option<&'a str>;
+
+    self_cell!(
+        struct LongestCell {
+            owner: WordsCell,
+
+            #[covariant]
+            dependent: Longest,
+        }
+    );
+
+    let words = WordsCell::new("fox horse cat".into(), |owner| owner.split(' ').collect());
+    let longest = LongestCell::new(words, |words| {
+        words
+            .borrow_dependent()
+            .iter()
+            .copied()
+            .max_by_key(|word| word.len())
+    });
+
+    // Moving the outer cell moves neither of the JoinedCells.
+    let longest = Box::new(longest);
+    assert_eq!(longest.borrow_dependent(), &Some("horse"));
+    assert_eq!(longest.borrow_owner().borrow_dependent().len(), 3);
+
+    assert!(impls!(LongestCell: Send & Sync));
+
+    let words = longest.into_owner();
+    assert_eq!(words.borrow_dependent(), &["fox", "horse", "cat"]);
+    assert_eq!(words.into_owner(), "fox horse cat");
+
+    // Send and Sync of the inner layer propagate. Dropping the outer cell
+    // drops the inner one.
+    type RcWords<'a> = Vec<&'a str>;
+
+    self_cell!(
+        struct RcWordsCell {
+            owner: Rc<String>,
+
+            #[covariant]
+            dependent: RcWords,
+        }
+    );
+
+    type First<'a> = &'a str;
+
+    self_cell!(
+        struct FirstCell {
+            owner: RcWordsCell,
+
+            #[covariant]
+            dependent: First,
+        }
+    );
+
+    assert!(!impls!(FirstCell: Send));
+    assert!(!impls!(FirstCell: Sync));
+
+    let text = Rc::new(String::from("a b"));
+    let words = RcWordsCell::new(text.clone(), |owner| owner.split(' ').collect());
+    let first = FirstCell::new(words, |words| words.borrow_dependent()[0]);
+    assert_eq!(*first.borrow_dependent(), "a");
+    drop(first);
+    assert_eq!(Rc::strong_count(&text), 1);
+}
+
+#[test]
+fn new_two_phase() {
+    use std::rc::Rc;
+
+    // Word boundaries, computed once and not kept in the cell.
+    let scratch = Rc::new(());
+    let scratch_probe = Rc::downgrade(&scratch);
+
+    let cell = PackedAstCell::new_two_phase(
+        "fox cat dog".into(),
+        |owner| {
+            let ends: Vec<usize> = owner
+                .match_indices(' ')
+                .map(|(i, _)| i)
+                .chain([owner.len()])
+                .collect();
+            (ends, scratch)
+        },
+        |owner, (ends, _scratch)| {
+            let mut start = 0;
+            Ast(ends
+                .into_iter()
+                .map(|end| {
+                    let word = &owner[start..end];
+                    start = end + 1;
+                    word
+                })
+                .collect())
+        },
+    );
+
+    assert_eq!(cell.borrow_dependent(), &Ast(vec!["fox", "cat", "dog"]));
+    assert!(scratch_probe.upgrade().is_none());
+}
+
+#[test]
+fn as_dyn() {
+    use std::cell::Cell;
+    use std::fmt::{self, Display};
+
+    trait WordCount {
+        fn word_count(&self) -> usize;
+    }
+
+    impl WordCount for Ast<'_> {
+        fn word_count(&self) -> usize {
+            self.0.len()
+        }
+    }
+
+    self_cell!(
+        pub struct CountedCell {
+            owner: String,
+
+            #[covariant]
+            dependent: Ast,
+        }
+
+        impl {AsDyn(as_word_count, dyn WordCount), AsDyn(as_debug, dyn Debug + Send)}
+    );
+
+    let cell = CountedCell::new("fox cat dog".into(), |owner| {
+        Ast(owner.split(' ').collect())
+    });
+    let word_count: &dyn WordCount = cell.as_word_count();
+    assert_eq!(word_count.word_count(), 3);
+    assert_eq!(
+        format!("{:?}", cell.as_debug()),
+        r#"Ast(["fox", "cat", "dog"])"#
+    );
+
+    // Cell makes the dependent invariant, the trait object still hides it.
+    struct Cursor<'a>(Cell<&'a str>);
+
+    impl Display for Cursor<'_> {
+        fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+            fmt.write_str(self.0.get())
+        }
+    }
+
+    self_cell!(
+        struct CursorCell {
+            owner: String,
+
+            #[not_covariant]
+            dependent: Cursor,
+        }
+
+        impl {AsDyn(as_display, dyn Display)}
+    );
+
+    let cell = CursorCell::new("fox cat".into(), |owner| Cursor(Cell::new(&owner[4..])));
+    assert_eq!(cell.as_display().to_string(), "cat");
+}
+
+#[test]
+fn partial_cell() {
+    use std::rc::Rc;
+
+    // A download arriving in chunks, parsed into words once complete.
+    #[allow(clippy::ptr_arg)]
+    fn parse_words(owner: &String) -> Result<Ast<'_>, &'static str> {
+        match owner.strip_suffix('.') {
+            Some(text) => Ok(Ast(text.split(' ').collect())),
+            None => Err("incomplete"),
+        }
+    }
+
+    let mut partial = PackedAstCell::new_uninit_dependent(String::new());
+    let owner_ptr = partial.borrow_owner() as *const String;
+
+    partial.owner_mut().push_str("fox c");
+    let (mut partial, err) = partial.try_build(parse_words).unwrap_err();
+    assert_eq!(err, "incomplete");
+    assert_eq!(partial.borrow_owner(), "fox c");
+
+    partial.owner_mut().push_str("at dog.");
+    let cell = partial
+        .try_build(parse_words)
+        .unwrap_or_else(|_| panic!("complete"));
+    assert_eq!(cell.borrow_dependent(), &Ast(vec!["fox", "cat", "dog"]));
+    // Owner wasn't moved when sealing the cell.
+    assert_eq!(cell.owner_ptr(), owner_ptr);
+
+    let partial = PackedAstCell::new_uninit_dependent("a b".into());
+    let cell = partial.build(|owner| Ast(owner.split(' ').collect()));
+    assert_eq!(cell.borrow_dependent(), &Ast(vec!["a", "b"]));
+
+    type RcRef<'a> = &'a Rc<String>;
+
+    self_cell!(
+        struct RcCell {
+            owner: Rc<String>,
+
+            #[covariant]
+            dependent: RcRef,
+        }
+    );
+
+    // Dropping or taking back owner without a dependent.
+    let owner = Rc::new(String::from("abc"));
+
+    drop(RcCell::new_uninit_dependent(owner.clone()));
+    assert_eq!(Rc::strong_count(&owner), 1);
+
+    let recovered = RcCell::new_uninit_dependent(owner.clone()).into_owner();
+    assert!(Rc::ptr_eq(&recovered, &owner));
+    drop(recovered);
+
+    let result = catch_unwind(|| {
+        RcCell::new_uninit_dependent(owner.clone()).build(|_| panic!("build failed"))
+    });
+    assert!(result.is_err());
+    assert_eq!(Rc::strong_count(&owner), 1);
+}
+
+#[test]
+fn cell_arena() {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    let lines = vec!["fox cat".to_string(), "dog".to_string(), String::new()];
+    let cells = PackedAstCell::new_arena(lines, |line| Ast(line.split(' ').collect()));
+    assert_eq!(cells.len(), 3);
+    assert_eq!(cells[0].borrow_dependent(), &Ast(vec!["fox", "cat"]));
+    assert_eq!(cells[2].borrow_dependent(), &Ast(vec![""]));
+
+    let owners = (&cells)
+        .into_iter()
+        .map(|cell| cell.borrow_owner().as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(owners, ["fox cat", "dog", ""]);
+
+    // All owners live next to each other in one block.
+    let stride = cells[1].owner_ptr() as usize - cells[0].owner_ptr() as usize;
+    assert_eq!(
+        cells[2].owner_ptr() as usize - cells[1].owner_ptr() as usize,
+        stride
+    );
+
+    assert!(PackedAstCell::new_arena(Vec::new(), |_| unreachable!()).is_empty());
+
+    type RcRef<'a> = &'a Rc<String>;
+
+    self_cell!(
+        struct RcCell {
+            owner: Rc<String>,
+
+            #[covariant]
+            dependent: RcRef,
+        }
+    );
+
+    // A panicking builder drops the owners moved in so far and the rest.
+    let owner = Rc::new(String::from("abc"));
+    let result = catch_unwind(|| {
+        let mut built = 0;
+        RcCell::new_arena(vec![owner.clone(); 4], |owner| {
+            built += 1;
+            if built == 3 {
+                panic!("build failed");
+            }
+            owner
+        })
+    });
+    assert!(result.is_err());
+    assert_eq!(Rc::strong_count(&owner), 1);
+
+    // Finalizers run for each cell in the arena.
+    struct Job {
+        id: usize,
+        done: Rc<RefCell<Vec<usize>>>,
+    }
+
+    type JobRef<'a> = &'a Job;
+
+    fn finish<'a>(job: &'a Job, dependent: JobRef<'a>) {
+        assert!(std::ptr::eq(job, dependent));
+        job.done.borrow_mut().push(job.id);
+    }
+
+    self_cell!(
+        struct JobCell {
+            owner: Job,
+
+            #[covariant]
+            dependent: JobRef,
+        }
+
+        impl {Drop(finish)}
+    );
+
+    let done = Rc::new(RefCell::new(Vec::new()));
+    let jobs = (0..3)
+        .map(|id| Job {
+            id,
+            done: done.clone(),
+        })
+        .collect();
+    let cells = JobCell::new_arena(jobs, |job| job);
+    assert!(done.borrow().is_empty());
+
+    drop(cells);
+    assert_eq!(*done.borrow(), [0, 1, 2]);
+    assert_eq!(Rc::strong_count(&done), 1);
+}
+
+#[test]
+fn for_each_dependent() {
+    use self_cell::{item_family, OwnedIterCell};
+
+    let lines = vec![
+        "fox cat".to_string(),
+        "dog".to_string(),
+        "a b c".to_string(),
+    ];
+    let cells = PackedAstCell::new_arena(lines, |line| Ast(line.split(' ').collect()));
+
+    let mut words = Vec::new();
+    cells.for_each_dependent(|owner, ast| words.push((owner.len(), ast.0.len())));
+    assert_eq!(words, [(7, 2), (3, 1), (5, 3)]);
+
+    // Stops at the first error.
+    let mut visited = 0;
+    let result = cells.try_for_each_dependent(|owner, ast| {
+        visited += 1;
+        if ast.0.len() == 1 {
+            Err(owner.clone())
+        } else {
+            Ok(())
+        }
+    });
+    assert_eq!(result, Err("dog".to_string()));
+    assert_eq!(visited, 2);
+    assert_eq!(cells.try_for_each_dependent(|_, _| Ok::<_, ()>(())), Ok(()));
+
+    item_family!(struct Words<'a> = &'a str);
+
+    let words =
+        OwnedIterCell::<String, Words>::new("fox cat dog".into(), |text| text.split(' ').collect());
+    let mut lens = Vec::new();
+    words.for_each_dependent(|text, word| lens.push((text.len(), word.len())));
+    assert_eq!(lens, [(11, 3); 3]);
+
+    // The error can't borrow from the cell.
+    let result = words.try_for_each_dependent(|_, word| match *word {
+        "cat" => Err(word.to_string()),
+        _ => Ok(()),
+    });
+    assert_eq!(result, Err("cat".to_string()));
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+fn par_iter_dependents() {
+    use rayon::iter::ParallelIterator;
+    use self_cell::{item_family, OwnedIterCell};
+
+    let lines = (0..100).map(|x| format!("{x} {x}")).collect();
+    let cells = PackedAstCell::new_arena(lines, |line| Ast(line.split(' ').collect()));
+    let words = cells
+        .par_iter_dependents(|owner, ast| owner.len() + ast.0.len())
+        .collect::<Vec<_>>();
+    assert_eq!(words.len(), 100);
+    assert_eq!(words[5], 5);
+    assert_eq!(words[42], 7);
+
+    item_family!(struct Words<'a> = &'a str);
+
+    let words =
+        OwnedIterCell::<String, Words>::new("fox cat dog".into(), |text| text.split(' ').collect());
+    let lens = words
+        .par_iter_dependents()
+        .map(|word| word.len())
+        .sum::<usize>();
+    assert_eq!(lens, 9);
+}
+
+#[cfg(feature = "ffi")]
+#[test]
+fn ffi_cell() {
+    use std::ffi::c_void;
+
+    use self_cell::FfiCell;
+
+    assert_eq!(
+        std::mem::size_of::<FfiCell<PackedAstCell>>(),
+        2 * std::mem::size_of::<*const c_void>()
+    );
+
+    extern "C" fn parse(len: usize) -> FfiCell<PackedAstCell> {
+        let text = "fox cat dog"[..len].to_string();
+        PackedAstCell::new(text, |text| Ast(text.split(' ').collect())).into()
+    }
+
+    extern "C" fn word_count(cell: &FfiCell<PackedAstCell>) -> usize {
+        unsafe { cell.with_dependent(|_, ast| ast.0.len()) }
+    }
+
+    let cell = parse(7);
+    assert_eq!(word_count(&cell), 2);
+    assert_eq!(unsafe { cell.borrow_owner() }, "fox cat");
+
+    let owner_ptr = cell.owner_ptr();
+    let cells = vec![cell];
+    let cell = unsafe { cells.into_iter().next().unwrap().into_cell() };
+    assert_eq!(cell.owner_ptr().cast::<c_void>(), owner_ptr);
+    assert_eq!(cell.borrow_dependent(), &Ast(vec!["fox", "cat"]));
+
+    type RcRef<'a> = &'a Rc<String>;
+
+    self_cell!(
+        struct RcCell {
+            owner: Rc<String>,
+
+            #[not_covariant]
+            dependent: RcRef,
+        }
+    );
+
+    // Dropping the handle drops the cell through the table.
+    let owner = Rc::new(String::from("abc"));
+    let cell = FfiCell::new(RcCell::new(owner.clone(), |owner| owner));
+    assert_eq!(
+        cell.dependent_ptr(),
+        cell.owner_ptr()
+            .wrapping_add(std::mem::size_of::<Rc<String>>())
+    );
+    assert!(unsafe { cell.with_dependent(|owner, dependent| std::ptr::eq(owner, *dependent)) });
+    assert_eq!(Rc::strong_count(&owner), 2);
+
+    drop(cell);
+    assert_eq!(Rc::strong_count(&owner), 1);
+}
+
+#[test]
+fn with_dependent_ref() {
+    use std::cell::Cell;
+
+    use self_cell::DependentRef;
+
+    // A callback that is only handed the dependent reports where in owner it
+    // failed.
+    fn first_long_word(ast: DependentRef<'_, String, Ast<'_>>) -> Result<(), usize> {
+        match ast.0.iter().find(|word| word.len() > 3) {
+            Some(word) => Err(word.as_ptr() as usize - DependentRef::owner(&ast).as_ptr() as usize),
+            None => Ok(()),
+        }
+    }
+
+    let cell = PackedAstCell::new("fox jumps".into(), |text| Ast(text.split(' ').collect()));
+    assert_eq!(cell.with_dependent_ref(first_long_word), Err(4));
+
+    cell.with_dependent_ref(|ast| {
+        // Copies of the handle lead to the same owner.
+        let copy = ast;
+        assert!(std::ptr::eq(
+            DependentRef::owner(&copy),
+            cell.borrow_owner()
+        ));
+        assert_eq!(DependentRef::get(&ast).0, ["fox", "jumps"]);
+    });
+
+    type Cursor<'a> = Cell<&'a str>;
+
+    self_cell!(
+        struct CursorCell {
+            owner: String,
+
+            #[not_covariant]
+            dependent: Cursor,
+        }
+    );
+
+    let cell = CursorCell::new("fox cat".into(), |text| Cell::new(&text[..3]));
+    cell.with_dependent_ref(|cursor| cursor.set(&DependentRef::owner(&cursor)[4..]));
+    cell.with_dependent(|_, cursor| assert_eq!(cursor.get(), "cat"));
+}
+
+#[test]
+fn stored_builders() {
+    // Builders created outside of the constructor call, and cells declared
+    // inside the function.
+    let split = PackedAstCell::builder(|owner| Ast(owner.split(' ').collect()));
+    let a = PackedAstCell::new("a b".into(), split);
+    let b = PackedAstCell::new("c d".into(), split);
+    assert_eq!(a.borrow_dependent(), &Ast(vec!["a", "b"]));
+    assert_eq!(b.borrow_dependent(), &Ast(vec!["c", "d"]));
+
+    let non_empty = PackedAstCell::try_builder(|owner| {
+        if owner.is_empty() {
+            Err("empty")
+        } else {
+            Ok(Ast(owner.split(' ').collect()))
+        }
+    });
+    assert!(PackedAstCell::try_new(String::new(), non_empty).is_err());
+    let cell = PackedAstCell::try_new("x".into(), non_empty).unwrap();
+    assert_eq!(cell.borrow_dependent(), &Ast(vec!["x"]));
+
+    fn build_all<F>(owners: &[&str], builder: F) -> Vec<PackedAstCell>
+    where
+        F: for<'a> FnOnce(&'a String) -> Ast<'a> + Copy,
+    {
+        owners
+            .iter()
+            .map(|owner| PackedAstCell::new(owner.to_string(), builder))
+            .collect()
+    }
+
+    let cells = build_all(&["a", "b c"], split);
+    assert_eq!(cells[1].borrow_dependent(), &Ast(vec!["b", "c"]));
+
+    type Chunks<'a> = Vec<&'a mut [u8]>;
+
+    self_cell!(
+        struct ChunksCell {
+            owner: Vec<u8>,
+
+            #[not_covariant, owner_mut]
+            dependent: Chunks,
+        }
+    );
+
+    let chunks = ChunksCell::builder(|owner| owner.chunks_mut(2).collect());
+    let mut cell = ChunksCell::new(vec![1, 2, 3], chunks);
+    cell.with_dependent_mut(|chunks| chunks[1][0] = 7);
+    assert_eq!(cell.into_owner(), [1, 2, 7]);
+}
+
+#[test]
+fn map_dependent() {
+    #[derive(Debug, PartialEq)]
+    enum Token<'a> {
+        Ident(&'a str),
+        Number(u32),
+    }
+
+    type RawTokens<'a> = Vec<&'a str>;
+    type Tokens<'a> = Vec<Token<'a>>;
+
+    self_cell!(
+        struct RawTokensCell {
+            owner: Rc<String>,
+
+            #[covariant]
+            dependent: RawTokens,
+        }
+    );
+
+    self_cell!(
+        struct TokensCell {
+            owner: Rc<String>,
+
+            #[not_covariant]
+            dependent: Tokens,
+        }
+
+        impl {Debug}
+    );
+
+    let owner = Rc::new(String::from("x 1 y"));
+    let raw_cell = RawTokensCell::new(owner.clone(), |owner| owner.split(' ').collect());
+    let owner_ptr: *const String = &**raw_cell.borrow_owner();
+
+    let cell: TokensCell = raw_cell.map_dependent(|_, raw_tokens| {
+        raw_tokens
+            .into_iter()
+            .map(|raw| match raw.parse() {
+                Ok(number) => Token::Number(number),
+                Err(_) => Token::Ident(raw),
+            })
+            .collect()
+    });
+
+    assert!(std::ptr::eq(&**cell.borrow_owner(), owner_ptr));
+    cell.with_dependent(|_, tokens| {
+        assert_eq!(
+            tokens,
+            &vec![Token::Ident("x"), Token::Number(1), Token::Ident("y")]
+        );
+    });
+
+    // Dependents of different size, the padding of the JoinedCell evens them
+    // out.
+    type WordCount<'a> = (u32, PhantomData<&'a str>);
+    type NonEmpty<'a> = (bool, PhantomData<&'a str>);
+
+    self_cell!(
+        struct WordCountCell {
+            owner: String,
+
+            #[covariant]
+            dependent: WordCount,
+        }
+    );
+
+    self_cell!(
+        struct NonEmptyCell {
+            owner: String,
+
+            #[covariant]
+            dependent: NonEmpty,
+        }
+    );
+
+    assert_ne!(
+        std::mem::size_of::<WordCount>(),
+        std::mem::size_of::<NonEmpty>()
+    );
+    assert_eq!(
+        WordCountCell::joined_layout(),
+        NonEmptyCell::joined_layout()
+    );
+
+    let count_cell = WordCountCell::new("fox cat".into(), |text| {
+        (text.split(' ').count() as u32, PhantomData)
+    });
+    let owner_ptr = count_cell.owner_ptr();
+    let non_empty_cell: NonEmptyCell =
+        count_cell.map_dependent(|_, (count, marker)| (count > 0, marker));
+    assert!(non_empty_cell.borrow_dependent().0);
+    assert_eq!(non_empty_cell.owner_ptr(), owner_ptr);
+
+    // A panic in func drops owner.
+    let raw_cell = RawTokensCell::new(owner.clone(), |owner| owner.split(' ').collect());
+    let panicked = catch_unwind(std::panic::AssertUnwindSafe(|| {
+        let _cell: TokensCell = raw_cell.map_dependent(|_, _| panic!("lowering failed"));
+    }));
+    assert!(panicked.is_err());
+
+    drop(cell);
+    assert_eq!(Rc::strong_count(&owner), 1);
+}
+
+#[test]
+fn owned_captures() {
+    use self_cell::{CapturesFamily, OwnedCaptures};
+
+    #[derive(Debug, PartialEq)]
+    struct KeyValue<'a> {
+        key: &'a str,
+        value: &'a str,
+    }
+
+    struct KeyValueCaptures;
+
+    impl<'a> CapturesFamily<'a> for KeyValueCaptures {
+        type Captures = KeyValue<'a>;
+    }
+
+    fn find_key_value(line: &str) -> Option<KeyValue<'_>> {
+        let (key, value) = line.split_once('=')?;
+        Some(KeyValue { key, value })
+    }
+
+    let mut line =
+        OwnedCaptures::<Rc<String>, KeyValueCaptures>::new(Rc::new("a=b".into()), |line| {
+            find_key_value(line)
+        });
+    assert!(line.is_match());
+    assert_eq!(
+        line.map_captures(|_, captures| captures.value.len()),
+        Some(1)
+    );
+    line.with_captures(|owner, captures| {
+        assert_eq!(owner.as_str(), "a=b");
+        assert_eq!(
+            captures,
+            Some(&KeyValue {
+                key: "a",
+                value: "b"
+            })
+        );
+    });
+
+    assert!(!line.rematch(|line| line.find(':').map(|pos| KeyValue {
+        key: &line[..pos],
+        value: &line[pos..],
+    })));
+    assert!(!line.is_match());
+    assert_eq!(line.map_captures(|_, captures| captures.key.len()), None);
+
+    assert!(line.rematch(|line| find_key_value(line)));
+
+    let owner = line.into_owner();
+    assert_eq!(Rc::strong_count(&owner), 1);
+
+    let no_match = OwnedCaptures::<String, KeyValueCaptures>::try_new("ab".into(), |line| {
+        Ok::<_, ()>(find_key_value(line))
+    })
+    .unwrap();
+    assert!(!no_match.is_match());
+    assert_eq!(no_match.borrow_owner(), "ab");
+
+    let owner = Rc::new(String::from("a=b"));
+    let err = OwnedCaptures::<Rc<String>, KeyValueCaptures>::try_new(owner.clone(), |_| {
+        Err::<Option<KeyValue>, _>(-1)
+    });
+    assert!(matches!(err, Err(-1)));
+    assert_eq!(Rc::strong_count(&owner), 1);
+}
+
+#[test]
+fn owned_iter_cell() {
+    use self_cell::{item_family, OwnedIterCell};
+
+    #[derive(Debug, PartialEq)]
+    struct Record<'a> {
+        key: &'a str,
+        value: &'a str,
+    }
+
+    item_family!(struct Records<'a> = Record<'a>);
+
+    fn parse_records(text: &str) -> Result<Vec<Record<'_>>, usize> {
+        text.lines()
+            .enumerate()
+            .map(|(line_nr, line)| {
+                let (key, value) = line.split_once('=').ok_or(line_nr)?;
+                Ok(Record { key, value })
+            })
+            .collect()
+    }
+
+    let owner = Rc::new(String::from("a=b\nc=d"));
+    let mut records =
+        OwnedIterCell::<Rc<String>, Records>::try_new(owner.clone(), |text| parse_records(text))
+            .unwrap();
+    assert_eq!(records.len(), 2);
+    assert!(!records.is_empty());
+    assert_eq!(records.borrow_owner().as_str(), "a=b\nc=d");
+
+    let keys = records.iter().map(|record| record.key).collect::<Vec<_>>();
+    assert_eq!(keys, ["a", "c"]);
+    assert_eq!(
+        records.iter().next_back(),
+        Some(&Record {
+            key: "c",
+            value: "d"
+        })
+    );
+
+    let mut values = String::new();
+    for record in &records {
+        values.push_str(record.value);
+    }
+    assert_eq!(values, "bd");
+
+    records.with_items_mut(|text, items| items.retain(|record| record.key != &text[..1]));
+    assert_eq!(records.as_slice().len(), 1);
+
+    assert!(Rc::ptr_eq(&records.into_owner(), &owner));
+    assert_eq!(Rc::strong_count(&owner), 1);
+
+    let err = OwnedIterCell::<Rc<String>, Records>::try_new(Rc::new("a=b\nc".into()), |text| {
+        parse_records(text)
+    });
+    assert!(matches!(err, Err(1)));
+
+    item_family!(struct Words<'a> = &'a str);
+
+    let empty = OwnedIterCell::<String, Words>::new(String::new(), |_| Vec::new());
+    assert!(empty.is_empty());
+    assert_eq!(empty.iter().next(), None);
+}
+
+#[test]
+fn generic_self_cell() {
+    use std::cell::Cell;
+
+    use self_cell::{DependentBuilder, SelfCell};
+
+    struct Words;
+
+    impl<'a> DependentBuilder<'a, Rc<String>> for Words {
+        type Output = Vec<&'a str>;
+
+        fn build(owner: &'a Rc<String>) -> Self::Output {
+            owner.split(' ').collect()
+        }
+    }
+
+    let owner = Rc::new(String::from("fox cat dog"));
+    let mut cell = SelfCell::<Rc<String>, Words>::new(owner.clone());
+    assert_eq!(Rc::strong_count(&owner), 2);
+
+    cell.with_dependent(|owner, words| {
+        assert_eq!(words, &["fox", "cat", "dog"]);
+        assert_eq!(owner.as_ptr(), words[0].as_ptr());
+    });
+    cell.with_dependent_mut(|owner, words| {
+        words.retain(|word| *word != "cat");
+        words.push(&owner[..1]);
+    });
+    assert_eq!(cell.with_dependent(|_, words| words.join(" ")), "fox dog f");
+
+    assert_eq!(cell.borrow_owner().as_str(), "fox cat dog");
+    let recovered = cell.into_owner();
+    assert!(Rc::ptr_eq(&recovered, &owner));
+    drop(recovered);
+    assert_eq!(Rc::strong_count(&owner), 1);
+
+    drop(SelfCell::<Rc<String>, Words>::new(owner.clone()));
+    assert_eq!(Rc::strong_count(&owner), 1);
+
+    // Dependents that aren't covariant work the same, as all access goes
+    // through closures.
+    struct FirstWord;
+
+    impl<'a> DependentBuilder<'a, String> for FirstWord {
+        type Output = Cell<&'a str>;
+
+        fn build(owner: &'a String) -> Self::Output {
+            Cell::new(owner.split(' ').next().unwrap_or(""))
+        }
+    }
+
+    let mut cell = SelfCell::<String, FirstWord>::new("fox cat".into());
+    cell.with_dependent_mut(|owner, first| first.set(&owner[4..]));
+    assert_eq!(
+        cell.with_dependent(|_, first| first.get().to_string()),
+        "cat"
+    );
+
+    // Should build panic, owner is dropped.
+    struct Panics;
+
+    impl<'a> DependentBuilder<'a, Rc<String>> for Panics {
+        type Output = &'a str;
+
+        fn build(_: &'a Rc<String>) -> Self::Output {
+            panic!("build failed")
+        }
+    }
+
+    let result = catch_unwind(|| SelfCell::<Rc<String>, Panics>::new(owner.clone()));
+    assert!(result.is_err());
+    assert_eq!(Rc::strong_count(&owner), 1);
+}
+
+mod restricted {
+    use super::Ast;
+
+    use self_cell::self_cell;
+
+    self_cell!(
+        pub struct RestrictedCell {
+            pub(self) owner: String,
+
+            #[covariant]
+            pub(crate) dependent: Ast,
+        }
+    );
+
+    pub fn owner_len(cell: &RestrictedCell) -> usize {
+        cell.borrow_owner().len()
+    }
+}
+
+#[test]
+fn field_visibility() {
+    use restricted::{owner_len, RestrictedCell};
+
+    let cell = RestrictedCell::new("abc".into(), |owner| Ast(vec![&owner[1..]]));
+    assert_eq!(owner_len(&cell), 3);
+    assert_eq!(cell.borrow_dependent(), &Ast(vec!["bc"]));
+}
+
+mod lexer {
+    use self_cell::self_cell;
+
+    // Public, but in a private module, so code outside of lexer can't name it.
+    mod tokens {
+        #[derive(Debug, PartialEq)]
+        pub struct Tokens<'a>(pub Vec<&'a str>);
+    }
+
+    use tokens::Tokens;
+
+    self_cell!(
+        pub struct TokensCell {
+            owner: String,
+
+            #[covariant]
+            dependent: Tokens,
+        }
+    );
+
+    pub fn lex(text: String) -> TokensCell {
+        TokensCell::new(text, |text| Tokens(text.split(' ').collect()))
+    }
+}
+
+#[test]
+fn borrowed_dependent_alias() {
+    use lexer::{lex, TokensCell};
+    use self_cell::BorrowedDependent;
+
+    fn tokens<'a>(cell: &'a TokensCell) -> &'a BorrowedDependent<'a, TokensCell> {
+        cell.borrow_dependent()
+    }
+
+    trait Borrowed<'a> {
+        type Target;
+
+        fn borrowed(&'a self) -> &'a Self::Target;
+    }
+
+    impl<'a> Borrowed<'a> for TokensCell {
+        type Target = BorrowedDependent<'a, TokensCell>;
+
+        fn borrowed(&'a self) -> &'a Self::Target {
+            self.borrow_dependent()
+        }
+    }
+
+    let cell = lex("fox cat".into());
+    assert_eq!(tokens(&cell).0, ["fox", "cat"]);
+    assert_eq!(cell.borrowed(), tokens(&cell));
+}
+
+mod sealed {
+    use super::Ast;
+
+    use self_cell::self_cell;
+
+    self_cell!(
+        #[sealed(ast_cell)]
+        pub struct SealedAstCell {
+            pub(super) owner: String,
+
+            #[covariant]
+            dependent: Ast,
+        }
+
+        impl {Debug, SelfCellExt}
+    );
+
+    self_cell!(
+        #[sealed(private_cell)]
+        struct PrivateCell {
+            pub(self) owner: String,
+
+            #[not_covariant]
+            dependent: Ast,
+        }
+    );
+
+    // Extends the cell with the public API only, its field isn't accessible
+    // here.
+    impl SealedAstCell {
+        pub fn first(&self) -> Option<&str> {
+            self.borrow_dependent().0.first().copied()
+        }
+    }
+
+    pub fn private_len(text: &str) -> usize {
+        let cell = PrivateCell::new(text.into(), |owner| Ast(owner.split(' ').collect()));
+        assert_eq!(cell.borrow_owner(), text);
+        cell.with_dependent(|_, ast| ast.0.len())
+    }
+}
+
+#[test]
+fn sealed_cell() {
+    use sealed::{private_len, SealedAstCell};
+    use self_cell::SelfCellExt;
+
+    let cell = SealedAstCell::new("fox cat".into(), |owner| Ast(owner.split(' ').collect()));
+    assert_eq!(cell.first(), Some("fox"));
+    assert_eq!(cell.borrow_owner(), "fox cat");
+    assert_eq!(private_len("a b c"), 3);
+
+    // The escape hatch, opted into with impl {SelfCellExt}.
+    let raw = unsafe { cell.unsafe_self_cell() };
+    let ast = unsafe { raw.borrow_dependent::<Ast<'_>>() };
+    assert_eq!(ast, &Ast(vec!["fox", "cat"]));
+
+    assert_eq!(
+        format!("{:?}", cell),
+        r#"SealedAstCell { owner: "fox cat", dependent: Ast(["fox", "cat"]) }"#
+    );
+}
+
+#[deny(missing_docs)]
+mod documented {
+    //! Cell with documentation, compiled with missing_docs denied.
+
+    use super::Ast;
+
+    use self_cell::self_cell;
+
+    self_cell!(
+        /// Words of a text, borrowing the text.
+        #[allow(dead_code)]
+        pub struct DocumentedCell {
+            /// The text.
+            #[doc(alias = "text")]
+            owner: String,
+
+            #[covariant]
+            /// The words of the text.
+            #[must_use]
+            dependent: Ast,
+        }
+
+        impl {Debug}
+    );
+}
+
+// The types don't exist, so this only compiles if the whole expansion is
+// removed.
+#[cfg(any())]
+self_cell!(
+    struct RemovedCell {
+        owner: DoesNotExist,
+
+        #[covariant]
+        dependent: DoesNotExistEither,
+    }
+);
+
+#[test]
+fn doc_comments_and_attributes() {
+    use documented::DocumentedCell;
+
+    let cell = DocumentedCell::new("a b".into(), |owner| Ast(owner.split(' ').collect()));
+    assert_eq!(cell.borrow_owner(), "a b");
+    assert_eq!(cell.borrow_dependent(), &Ast(vec!["a", "b"]));
+}
+
+#[test]
+fn debug_derive() {
+    use std::fmt;
+
+    #[allow(clippy::ptr_arg)]
+    fn summarize(owner: &String, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "<{} bytes>", owner.len())
+    }
+
+    self_cell!(
+        struct SummaryCell {
+            owner: String,
+
+            #[covariant]
+            dependent: Ast,
+        }
+
+        impl {Debug(summarize)}
+    );
+
+    #[derive(Debug)]
+    struct Lengths<'a>(std::cell::Cell<&'a str>);
+
+    self_cell!(
+        struct LengthsCell {
+            owner: String,
+
+            #[not_covariant]
+            dependent: Lengths,
+        }
+
+        impl {Debug(|owner: &String, fmt: &mut fmt::Formatter| write!(fmt, "{}..", &owner[..1]))}
+    );
+
+    let cell = SummaryCell::new("fox cat".into(), |owner| Ast(owner.split(' ').collect()));
+    assert_eq!(
+        format!("{:?}", cell),
+        r#"SummaryCell { owner: <7 bytes>, dependent: Ast(["fox", "cat"]) }"#
+    );
+    assert_eq!(
+        format!("{:#?}", cell),
+        r#"SummaryCell {
+    owner: <7 bytes>,
+    dependent: Ast(
+        [
+            "fox",
+            "cat",
+        ],
+    ),
+}"#
+    );
+
+    let cell = LengthsCell::new("fox".into(), |owner| Lengths(std::cell::Cell::new(owner)));
+    assert_eq!(
+        format!("{:?}", cell),
+        r#"LengthsCell { owner: f.., dependent: Lengths(Cell { value: "fox" }) }"#
+    );
+
+    assert_eq!(cell.with_dependent(|_, lengths| lengths.0.get().len()), 3);
+
+    self_cell!(
+        struct PlainCell {
+            owner: String,
+
+            #[covariant]
+            dependent: Ast,
+        }
+
+        impl {Debug}
+    );
+
+    // Without a formatter owner uses its own Debug impl.
+    let cell = PlainCell::new("fox".into(), |owner| Ast(vec![owner]));
+    assert_eq!(
+        format!("{:#?}", cell),
+        r#"PlainCell {
+    owner: "fox",
+    dependent: Ast(
+        [
+            "fox",
+        ],
+    ),
+}"#
+    );
+
+    // Lacks a Debug impl, only the owner formatter hook prints the dependent of
+    // not_covariant cells.
+    struct Lazy<'a>(std::cell::Cell<Option<&'a str>>);
+
+    self_cell!(
+        struct LazyCell {
+            owner: String,
+
+            #[not_covariant]
+            dependent: Lazy,
+        }
+
+        impl {Debug}
+    );
+
+    let cell = LazyCell::new("fox".into(), |_| Lazy(std::cell::Cell::new(None)));
+    assert_eq!(
+        format!("{:?}", cell),
+        r#"LazyCell { owner: "fox", dependent: <non-covariant> }"#
+    );
+    assert_eq!(
+        format!("{:#?}", cell),
+        r#"LazyCell {
+    owner: "fox",
+    dependent: <non-covariant>,
+}"#
+    );
+    cell.with_dependent(|owner, lazy| lazy.0.set(Some(owner)));
+    assert_eq!(
+        cell.with_dependent(|_, lazy| lazy.0.get().map(str::len)),
+        Some(3)
+    );
+}
+
+#[test]
+fn key_projection_derive() {
+    use std::collections::hash_map::DefaultHasher;
+    use std::collections::HashSet;
+    use std::hash::{Hash, Hasher};
+
+    #[derive(Debug)]
+    struct Source {
+        path: String,
+        contents: String,
+    }
+
+    fn source_path(source: &Source) -> &str {
+        &source.path
+    }
+
+    self_cell!(
+        struct SourceCell {
+            owner: Source,
+
+            #[covariant]
+            dependent: Ast,
+        }
+
+        impl {Debug, PartialEq(source_path), Eq, Hash(source_path)}
+    );
+
+    let new_cell = |path: &str, contents: &str| {
+        SourceCell::new(
+            Source {
+                path: path.into(),
+                contents: contents.into(),
+            },
+            |source| Ast(source.contents.split(' ').collect()),
+        )
+    };
+
+    let hash_of = |cell: &SourceCell| {
+        let mut hasher = DefaultHasher::new();
+        cell.hash(&mut hasher);
+        hasher.finish()
+    };
+
+    let a = new_cell("src/a.rs", "fn a");
+    let a_edited = new_cell("src/a.rs", "fn a_edited");
+    let b = new_cell("src/b.rs", "fn a");
+
+    assert_eq!(a, a_edited);
+    assert_ne!(a, b);
+    assert_eq!(hash_of(&a), hash_of(&a_edited));
+
+    let mut set = HashSet::new();
+    assert!(set.insert(a));
+    assert!(!set.insert(a_edited));
+    assert!(set.insert(b));
+    assert_eq!(set.len(), 2);
+
+    self_cell!(
+        struct SourceLenCell {
+            owner: Source,
+
+            #[covariant]
+            dependent: Ast,
+        }
+
+        impl {PartialEq(|source: &Source| source.contents.len())}
+    );
+
+    let new_len_cell = |contents: &str| {
+        SourceLenCell::new(
+            Source {
+                path: String::new(),
+                contents: contents.into(),
+            },
+            |_| Ast(Vec::new()),
+        )
+    };
+
+    assert!(new_len_cell("abc") == new_len_cell("xyz"));
+    assert!(new_len_cell("abc") != new_len_cell("abcd"));
+}
+
+#[test]
+fn ord_derive() {
+    use std::collections::{BTreeMap, BTreeSet};
+
+    self_cell!(
+        struct WordsCell {
+            owner: String,
+
+            #[covariant]
+            dependent: Ast,
+        }
+
+        impl {PartialEq, Eq, PartialOrd, Ord}
+    );
+
+    let new_cell =
+        |text: &str| WordsCell::new(text.into(), |owner| Ast(owner.split(' ').collect()));
+
+    assert!(new_cell("a b") < new_cell("b"));
+    assert_eq!(new_cell("a").cmp(&new_cell("a")), std::cmp::Ordering::Equal);
+
+    let set: BTreeSet<_> = ["dog", "cat", "fox", "cat"]
+        .iter()
+        .map(|text| new_cell(text))
+        .collect();
+    let owners: Vec<&str> = set
+        .iter()
+        .map(|cell| cell.borrow_owner().as_str())
+        .collect();
+    assert_eq!(owners, ["cat", "dog", "fox"]);
+
+    let mut map = BTreeMap::new();
+    map.insert(new_cell("fox cat"), 1);
+    assert_eq!(map.get(&new_cell("fox cat")), Some(&1));
+
+    // Orders by word count only.
+    #[allow(clippy::ptr_arg)]
+    fn word_count(owner: &String) -> usize {
+        owner.split(' ').count()
+    }
+
+    self_cell!(
+        struct CountedCell {
+            owner: String,
+
+            #[covariant]
+            dependent: Ast,
+        }
+
+        impl {PartialEq(word_count), Eq, PartialOrd(word_count), Ord(word_count)}
+    );
+
+    let mut cells: Vec<_> = ["a b c", "d", "e f"]
+        .iter()
+        .map(|text| CountedCell::new(text.to_string(), |owner| Ast(owner.split(' ').collect())))
+        .collect();
+    cells.sort();
+    let owners: Vec<&str> = cells
+        .iter()
+        .map(|cell| cell.borrow_owner().as_str())
+        .collect();
+    assert_eq!(owners, ["d", "e f", "a b c"]);
+    assert!(CountedCell::new("x y".into(), |_| Ast(Vec::new())) == cells[1]);
+}
+
+#[test]
+fn canonical_builder_derive() {
+    // The builder gets a reference to the owner type.
+    #[allow(clippy::ptr_arg)]
+    fn ast_from(owner: &String) -> Ast<'_> {
+        Ast(owner.split(' ').collect())
+    }
+
+    self_cell!(
+        struct WordsCell {
+            owner: String,
+
+            #[covariant]
+            dependent: Ast,
+        }
+
+        impl {Debug, Default(ast_from), From(ast_from)}
+    );
+
+    self_cell!(
+        struct ClosureWordsCell {
+            owner: String,
+
+            #[covariant]
+            dependent: Ast,
+        }
+
+        impl {
+            Default(|owner| Ast(owner.split(' ').collect())),
+            From(|owner| Ast(owner.split(' ').collect())),
+        }
+    );
+
+    #[derive(Default)]
+    struct Document {
+        title: WordsCell,
+        body: ClosureWordsCell,
+    }
+
+    let document = Document::default();
+    assert_eq!(document.title.borrow_dependent(), &Ast(vec![""]));
+    assert_eq!(document.body.borrow_owner(), "");
+
+    let cell = WordsCell::from(String::from("fox cat"));
+    assert_eq!(cell.borrow_dependent(), &Ast(vec!["fox", "cat"]));
+
+    let cell: ClosureWordsCell = String::from("fox cat").into();
+    assert_eq!(cell.borrow_dependent().0.len(), 2);
+
+    fn build<T: From<String>>(text: &str) -> T {
+        T::from(text.into())
+    }
+    assert_eq!(build::<WordsCell>("a b c").borrow_dependent().0.len(), 3);
+}
+
+#[test]
+fn try_from_derive() {
+    use std::convert::{TryFrom, TryInto};
+
+    #[derive(Debug, PartialEq)]
+    struct EmptyInput;
+
+    #[allow(clippy::ptr_arg)]
+    fn non_empty_ast(owner: &String) -> Result<Ast<'_>, EmptyInput> {
+        if owner.is_empty() {
+            Err(EmptyInput)
+        } else {
+            Ok(Ast(owner.split(' ').collect()))
+        }
+    }
+
+    self_cell!(
+        struct WordsCell {
+            owner: String,
+
+            #[covariant]
+            dependent: Ast,
+        }
+
+        impl {Debug, TryFrom(non_empty_ast, EmptyInput)}
+    );
+
+    let cell = WordsCell::try_from(String::from("fox cat")).unwrap();
+    assert_eq!(cell.borrow_dependent(), &Ast(vec!["fox", "cat"]));
+
+    let err: Result<WordsCell, _> = String::new().try_into();
+    assert_eq!(err.unwrap_err(), EmptyInput);
+
+    fn build<T: TryFrom<String>>(text: &str) -> Option<T> {
+        T::try_from(text.into()).ok()
+    }
+    assert!(build::<WordsCell>("a b").is_some());
+    assert!(build::<WordsCell>("").is_none());
+}
+
+#[test]
+fn owner_with_lifetime() {
+    type Fields<'a> = Vec<&'a [u8]>;
+
+    self_cell!(
+        struct FieldsView<'o> {
+            owner: &'o [u8],
+
+            #[covariant]
+            dependent: Fields,
+        }
+
+        impl {Debug, PartialEq, Eq, Hash}
+    );
+
+    self_cell!(
+        struct CowFieldsView {
+            owner: std::borrow::Cow<'static, [u8]>,
+
+            #[covariant]
+            dependent: Fields,
+        }
+    );
+
+    let input = vec![b'a', b',', b'b'];
+    let fields_of = |input: &[u8]| input.split(|byte| *byte == b',').count();
+
+    let view = FieldsView::new(&input, |owner| owner.split(|byte| *byte == b',').collect());
+    assert_eq!(view.borrow_dependent(), &vec![&b"a"[..], &b"b"[..]]);
+    assert_eq!(view, FieldsView::new(&input, |_| Vec::new()));
+    assert_eq!(
+        format!("{:?}", view),
+        "FieldsView { owner: [97, 44, 98], dependent: [[97], [98]] }"
+    );
+
+    // The dependent can only ever be borrowed for less than 'o.
+    let field: &[u8] = view.borrow_dependent()[1];
+    assert_eq!(field, b"b");
+
+    let owner: &[u8] = view.into_owner();
+    assert_eq!(fields_of(owner), 2);
+
+    let cow_view = CowFieldsView::new(input.clone().into(), |owner| vec![&owner[..1]]);
+    assert_eq!(cow_view.borrow_dependent(), &vec![&b"a"[..]]);
+}
+
+#[test]
+fn static_reference_owner() {
+    type Words<'a> = Vec<&'a str>;
+
+    self_cell!(
+        struct StaticStrCell {
+            owner: &'static str,
+
+            #[covariant]
+            dependent: Words,
+        }
+
+        impl {Debug,}
+    );
+
+    let cell = StaticStrCell::new("a b c", |owner| owner.split(' ').collect());
+    assert_eq!(*cell.borrow_owner(), "a b c");
+    assert_eq!(cell.borrow_dependent(), &vec!["a", "b", "c"]);
+    assert_eq!(cell.into_owner(), "a b c");
+
+    static mut BUF: [u8; 3] = *b"abc";
+
+    self_cell!(
+        struct StaticMutCell {
+            owner: &'static mut [u8],
+
+            #[covariant]
+            dependent: Words,
+        }
+    );
+
+    // SAFETY: BUF is only accessed here.
+    let buf = unsafe { &mut *std::ptr::addr_of_mut!(BUF) };
+    let mut cell = StaticMutCell::new(buf, |_| Vec::new());
+    cell.with_dependent_mut(|owner, dependent| {
+        assert_eq!(&**owner, b"abc");
+        dependent.push("x");
+    });
+    assert_eq!(cell.borrow_dependent().len(), 1);
+}
+
+#[test]
+fn multiple_dependent_lifetimes() {
+    #[derive(Debug, PartialEq)]
+    struct Parsed<'src, 'meta> {
+        words: Vec<&'src str>,
+        kind: &'meta str,
+    }
+
+    self_cell!(
+        struct ParsedCell {
+            owner: String,
+
+            #[covariant]
+            dependent: Parsed<'_, 'static>,
+        }
+
+        impl {Debug}
+    );
+
+    let mut cell = ParsedCell::new("fox cat".into(), |owner| Parsed {
+        words: owner.split(' ').collect(),
+        kind: "words",
+    });
+    assert_eq!(cell.borrow_dependent().words, ["fox", "cat"]);
+
+    let kind: &'static str = cell.with_dependent_mut(|owner, dependent| {
+        dependent.words.push(&owner[..1]);
+        dependent.kind
+    });
+    assert_eq!(kind, "words");
+    assert_eq!(cell.borrow_dependent().words, ["fox", "cat", "f"]);
+
+    // Both lifetimes borrow owner.
+    type Split<'a, 'b> = (&'a str, &'b str);
+
+    self_cell!(
+        struct SplitCell {
+            owner: String,
+
+            #[not_covariant]
+            dependent: Split<'_, '_>,
+        }
+    );
+
+    let cell = SplitCell::new("fox cat".into(), |owner| owner.split_at(3));
+    cell.with_dependent(|_, (left, right)| {
+        assert_eq!(*left, "fox");
+        assert_eq!(*right, " cat");
+    });
+}
+
+#[cfg(any(
+    miri,
+    feature = "shadow_state",
+    all(feature = "debug-assertions", debug_assertions)
+))]
+fn raw_ast_cell() -> self_cell::unsafe_self_cell::UnsafeSelfCell<String, Ast<'static>> {
+    use self_cell::unsafe_self_cell::{OwnerAndCellDropGuard, UnsafeSelfCell};
+
+    unsafe {
+        let drop_guard = OwnerAndCellDropGuard::<String, Ast>::allocate("abc".into());
+        UnsafeSelfCell::new(drop_guard.init_dependent(Ast(Vec::new())))
+    }
+}
+
+#[cfg(any(
+    miri,
+    feature = "shadow_state",
+    all(feature = "debug-assertions", debug_assertions)
+))]
+#[test]
+#[should_panic(expected = "UnsafeSelfCell used after its JoinedCell was dropped")]
+fn shadow_state_double_drop() {
+    use self_cell::unsafe_self_cell::Storage;
+
+    let mut raw_cell = raw_ast_cell();
+
+    unsafe {
+        raw_cell.drop_joined::<Ast>(Storage::Heap);
+        raw_cell.drop_joined::<Ast>(Storage::Heap);
+    }
+}
+
+#[cfg(any(
+    miri,
+    feature = "shadow_state",
+    all(feature = "debug-assertions", debug_assertions)
+))]
+#[test]
+#[should_panic(expected = "UnsafeSelfCell used after its JoinedCell was dropped")]
+fn shadow_state_use_after_drop() {
+    use self_cell::unsafe_self_cell::Storage;
+
+    let mut raw_cell = raw_ast_cell();
+
+    unsafe {
+        raw_cell.drop_joined::<Ast>(Storage::Heap);
+        raw_cell.borrow_owner::<Ast>();
+    }
+}
+
+#[cfg(all(feature = "debug-assertions", debug_assertions))]
+#[test]
+#[should_panic(expected = "UnsafeSelfCell points to a JoinedCell that isn't alive")]
+fn debug_assertions_poisoned_canary() {
+    use self_cell::unsafe_self_cell::{
+        JoinedStorage, OwnerAndCellDropGuard, Storage, UnsafeSelfCell,
+    };
+
+    // Static storage is never freed, so the poisoned canary can still be read.
+    static mut STORAGE: JoinedStorage<String, Ast<'static>> = JoinedStorage::new();
+
+    unsafe {
+        let storage = &mut *std::ptr::addr_of_mut!(STORAGE);
+        let drop_guard = OwnerAndCellDropGuard::<String, Ast>::in_storage(
+            storage.joined_void_ptr(),
+            "abc".into(),
+        );
+        let mut raw_cell =
+            UnsafeSelfCell::<String, Ast<'static>>::new(drop_guard.init_dependent(Ast(Vec::new())));
+        // A second cell for the same JoinedCell, its own flag still says alive.
+        let stale_cell = std::ptr::read(&raw_cell);
+
+        raw_cell.drop_joined::<Ast>(Storage::Static);
+        stale_cell.borrow_owner::<Ast>();
+    }
+}
+
+// The shadow state makes the cell larger.
+#[cfg(not(any(
+    miri,
+    feature = "shadow_state",
+    all(feature = "debug-assertions", debug_assertions)
+)))]
+#[test]
+fn cell_mem_size() {
+    use std::mem::size_of;
+
+    assert_eq!(size_of::<PackedAstCell>(), size_of::<*const u8>());
+    assert_eq!(size_of::<Option<PackedAstCell>>(), size_of::<*const u8>());
+
+    const _: () = assert!(size_of::<Option<PackedAstCell>>() == size_of::<*const u8>());
+}
+
+#[test]
+fn cell_niche() {
+    use std::mem::size_of;
+
+    // Holds with and without the shadow state.
+    const _: () = assert!(size_of::<Option<PackedAstCell>>() == size_of::<PackedAstCell>());
+}
+
+#[test]
+fn joined_layout() {
+    use std::alloc::Layout;
+    use std::mem::{align_of, size_of};
+
+    const LAYOUT: Layout = PackedAstCell::joined_layout();
+
+    assert!(LAYOUT.size() >= size_of::<String>() + size_of::<Ast<'static>>());
+    assert_eq!(
+        LAYOUT.align(),
+        align_of::<String>().max(align_of::<Ast<'static>>())
+    );
+}
+
+// Nothing assumes 64-bit pointers. The exact sizes are pinned for the pointer
+// widths CI runs the tests on, see the miri job.
+#[cfg(not(all(feature = "debug-assertions", debug_assertions)))]
+#[test]
+fn joined_layout_pointer_width() {
+    use std::alloc::Layout;
+    use std::mem::align_of;
+
+    type ByteRef<'a> = &'a u8;
+
+    self_cell!(
+        struct ByteCell {
+            owner: u8,
+
+            #[covariant]
+            dependent: ByteRef,
+        }
+    );
+
+    let layout = ByteCell::joined_layout();
+
+    assert_eq!(layout.align(), align_of::<&u8>());
+    assert_eq!(
+        layout,
+        Layout::new::<u8>()
+            .extend(Layout::new::<&u8>())
+            .unwrap()
+            .0
+            .pad_to_align()
+    );
+
+    #[cfg(target_pointer_width = "32")]
+    assert_eq!((layout.size(), layout.align()), (8, 4));
+
+    #[cfg(target_pointer_width = "64")]
+    assert_eq!((layout.size(), layout.align()), (16, 8));
+
+    let cell = ByteCell::new(7, |owner| owner);
+    assert_eq!(**cell.borrow_dependent(), 7);
+    assert!(std::ptr::eq(cell.owner_ptr(), *cell.borrow_dependent()));
+}
+
+// The dependent follows owner like in a repr(C) struct, with Layout::extend
+// and not any assumption about the pointer width.
+#[test]
+fn joined_layout_matches_layout_extend() {
+    use std::alloc::Layout;
+
+    fn assert_extended<Owner, Dependent>(
+        joined_layout: Layout,
+        owner_ptr: *const Owner,
+        dependent_ptr: *const Dependent,
+    ) {
+        let (layout, dependent_offset) = Layout::new::<Owner>()
+            .extend(Layout::new::<Dependent>())
+            .unwrap();
+        assert_eq!(
+            dependent_ptr as usize - owner_ptr as usize,
+            dependent_offset
+        );
+
+        // The canary follows the dependent.
+        #[cfg(all(feature = "debug-assertions", debug_assertions))]
+        let layout = layout.extend(Layout::new::<u32>()).unwrap().0;
+
+        assert_eq!(joined_layout, layout.pad_to_align());
+    }
+
+    type ByteRef<'a> = &'a u8;
+
+    self_cell!(
+        struct ByteCell {
+            owner: u8,
+
+            #[covariant]
+            dependent: ByteRef,
+        }
+    );
+
+    type Tagged<'a> = (&'a u64, u8);
+
+    self_cell!(
+        struct TaggedCell {
+            owner: u16,
+
+            #[not_covariant]
+            dependent: Tagged,
+        }
+    );
+
+    let cell = ByteCell::new(7, |owner| owner);
+    assert_extended(
+        ByteCell::joined_layout(),
+        cell.owner_ptr(),
+        cell.dependent_ptr(),
+    );
+
+    static WIDE: u64 = 9;
+    let cell = TaggedCell::new(3, |_| (&WIDE, 1));
+    assert_extended(
+        TaggedCell::joined_layout(),
+        cell.owner_ptr(),
+        cell.dependent_ptr(),
+    );
+
+    let cell = PackedAstCell::new("fox cat".into(), |owner| Ast(owner.split(' ').collect()));
+    assert_extended(
+        PackedAstCell::joined_layout(),
+        cell.owner_ptr(),
+        cell.dependent_ptr(),
+    );
 }
 
 #[test]